@@ -0,0 +1,308 @@
+use std::fs;
+use std::path;
+use std::time;
+
+use crate::{ids, model, parse};
+
+// INDEX positions are minutes:seconds:frames, with 75 CD frames to a second
+const FRAMES_PER_SECOND: u64 = 75;
+
+struct CueSheet {
+    title: Option<String>,
+    performer: Option<String>,
+    // The composer
+    songwriter: Option<String>,
+    date: Option<String>,
+    genre: Option<String>,
+    disc_number: Option<u32>,
+    files: Vec<CueFile>,
+}
+
+struct CueFile {
+    name: String,
+    tracks: Vec<CueTrack>,
+}
+
+struct CueTrack {
+    number: u32,
+    title: Option<String>,
+    performer: Option<String>,
+    songwriter: Option<String>,
+    start: Option<time::Duration>,
+}
+
+pub fn parse_cue_file(
+    cue_path: &path::Path,
+    options: &model::ScanOptions,
+) -> Result<Vec<model::AudioFileTrackMetadata>, model::Error> {
+    let bytes = fs::read(cue_path)?;
+    let text = String::from_utf8_lossy(&bytes);
+    let sheet = parse_cue_sheet(text.trim_start_matches('\u{feff}'), cue_path)?;
+
+    let track_total = sheet.files.iter().map(|f| f.tracks.len() as u32).sum();
+    let directory = cue_path.parent().unwrap_or(path::Path::new("."));
+
+    let mut tracks = Vec::new();
+    for file in sheet.files.iter() {
+        let audio_file_path = directory.join(&file.name);
+        if options.tag_readers.reader_for(&audio_file_path).is_none() {
+            return Err(model::Error::InvalidCueSheet(
+                cue_path.to_path_buf(),
+                "FILE is not in a format any tag reader handles",
+            ));
+        }
+
+        // The tags of the whole rip fill in whatever the sheet leaves out;
+        // many rips have none, so the artist and title are only checked on each track
+        let mut whole_file = parse::read_audio_file(audio_file_path, options)?;
+        if options.hash_audio {
+            ids::fill_track_id(&mut whole_file, true)?;
+        }
+
+        for (index, cue_track) in file.tracks.iter().enumerate() {
+            let start = cue_track.start.ok_or(model::Error::InvalidCueSheet(
+                cue_path.to_path_buf(),
+                "TRACK without an INDEX 01",
+            ))?;
+            // A track runs until the next one on the same file starts, the last one to the end
+            let end = file.tracks.get(index + 1).and_then(|next| next.start);
+            let duration = match end {
+                Some(end) => Some(end.saturating_sub(start)),
+                None => whole_file.duration.map(|d| d.saturating_sub(start)),
+            };
+
+            // Only what the sheet leaves out can have been inferred for the rip
+            let inferred_fields = whole_file
+                .inferred_fields
+                .iter()
+                .filter(|field| match field {
+                    model::TrackField::Artist => {
+                        cue_track.performer.is_none() && sheet.performer.is_none()
+                    }
+                    model::TrackField::Album => sheet.title.is_none(),
+                    model::TrackField::DiscNumber => sheet.disc_number.is_none(),
+                    model::TrackField::TrackNumber | model::TrackField::Title => false,
+                })
+                .cloned()
+                .collect();
+
+            let mut track = model::AudioFileTrackMetadata {
+                // Without an id of the rip's own, each track's comes from its tags below
+                id: if whole_file.id.0.is_empty() {
+                    model::TrackId(String::new())
+                } else {
+                    ids::cue_track_id(&whole_file.id, cue_track.number)
+                },
+                musicbrainz: model::MusicBrainzIds {
+                    // A recording id on the rip can't belong to every track on it
+                    release_track_id: None,
+                    ..whole_file.musicbrainz.clone()
+                },
+                artist: cue_track
+                    .performer
+                    .clone()
+                    .or_else(|| sheet.performer.clone())
+                    .unwrap_or_else(|| whole_file.artist.clone()),
+                album_artist: sheet
+                    .performer
+                    .clone()
+                    .or_else(|| whole_file.album_artist.clone()),
+                album: sheet.title.clone().or_else(|| whole_file.album.clone()),
+                disc_no: sheet.disc_number.or(whole_file.disc_no),
+                disc_total: whole_file.disc_total,
+                track: Some(cue_track.number),
+                track_total: Some(track_total),
+                track_title: cue_track
+                    .title
+                    .clone()
+                    .unwrap_or_else(|| format!("Track {}", cue_track.number)),
+                genre: sheet.genre.clone().or_else(|| whole_file.genre.clone()),
+                date: sheet.date.clone().or_else(|| whole_file.date.clone()),
+                duration,
+                replay_gain: model::ReplayGain {
+                    track_gain: None,
+                    track_peak: None,
+                    album_gain: whole_file.replay_gain.album_gain,
+                    album_peak: whole_file.replay_gain.album_peak,
+                },
+                classical: model::ClassicalTags {
+                    composer: cue_track
+                        .songwriter
+                        .clone()
+                        .or_else(|| sheet.songwriter.clone())
+                        .or_else(|| whole_file.classical.composer.clone()),
+                    // Like the recording id, a movement of the rip can't be every track's
+                    movement_name: None,
+                    movement_number: None,
+                    ..whole_file.classical.clone()
+                },
+                // Like the track gain, a rating of the whole rip is not one of each track
+                rating: None,
+                lyrics: None,
+                full_path: whole_file.full_path.clone(),
+                range: Some(model::TrackRange { start, end }),
+                inferred_fields,
+            };
+            parse::check_required_fields(&track)?;
+            ids::fill_track_id(&mut track, false)?;
+            tracks.push(track);
+        }
+    }
+
+    Ok(tracks)
+}
+
+fn parse_cue_sheet(text: &str, cue_path: &path::Path) -> Result<CueSheet, model::Error> {
+    let mut sheet = CueSheet {
+        title: None,
+        performer: None,
+        songwriter: None,
+        date: None,
+        genre: None,
+        disc_number: None,
+        files: Vec::new(),
+    };
+
+    // Lines belonging to a data track are read past
+    let mut in_data_track = false;
+
+    for line in text.lines() {
+        let (command, arguments) = split_word(line.trim());
+        let command = command.to_uppercase();
+        if in_data_track && command != "FILE" && command != "TRACK" {
+            continue;
+        }
+        let in_track = sheet
+            .files
+            .last()
+            .map(|f| !f.tracks.is_empty())
+            .unwrap_or(false);
+
+        match command.as_str() {
+            "FILE" => {
+                in_data_track = false;
+                sheet.files.push(CueFile {
+                    name: file_name(arguments),
+                    tracks: Vec::new(),
+                })
+            }
+            "TRACK" => {
+                let (number, track_type) = split_word(arguments);
+                // Data tracks on enhanced CDs are not music
+                in_data_track = !track_type.eq_ignore_ascii_case("AUDIO");
+                if in_data_track {
+                    continue;
+                }
+                let number = number.parse::<u32>().map_err(|_| {
+                    model::Error::InvalidCueSheet(
+                        cue_path.to_path_buf(),
+                        "TRACK number is not a number",
+                    )
+                })?;
+                match sheet.files.last_mut() {
+                    Some(file) => file.tracks.push(CueTrack {
+                        number,
+                        title: None,
+                        performer: None,
+                        songwriter: None,
+                        start: None,
+                    }),
+                    None => {
+                        return Err(model::Error::InvalidCueSheet(
+                            cue_path.to_path_buf(),
+                            "TRACK before any FILE",
+                        ))
+                    }
+                }
+            }
+            "INDEX" => {
+                let (number, position) = split_word(arguments);
+                if number != "01" {
+                    continue;
+                }
+                let start = parse_position(position).ok_or(model::Error::InvalidCueSheet(
+                    cue_path.to_path_buf(),
+                    "INDEX is not in mm:ss:ff form",
+                ))?;
+                if let Some(track) = last_track(&mut sheet) {
+                    track.start = Some(start);
+                }
+            }
+            "TITLE" if in_track => {
+                if let Some(track) = last_track(&mut sheet) {
+                    track.title = Some(unquote(arguments));
+                }
+            }
+            "PERFORMER" if in_track => {
+                if let Some(track) = last_track(&mut sheet) {
+                    track.performer = Some(unquote(arguments));
+                }
+            }
+            "SONGWRITER" if in_track => {
+                if let Some(track) = last_track(&mut sheet) {
+                    track.songwriter = Some(unquote(arguments));
+                }
+            }
+            "TITLE" => sheet.title = Some(unquote(arguments)),
+            "PERFORMER" => sheet.performer = Some(unquote(arguments)),
+            "SONGWRITER" => sheet.songwriter = Some(unquote(arguments)),
+            "REM" => {
+                let (key, value) = split_word(arguments);
+                match key.to_uppercase().as_str() {
+                    "DATE" => sheet.date = Some(unquote(value)),
+                    "GENRE" => sheet.genre = Some(unquote(value)),
+                    "DISCNUMBER" => sheet.disc_number = unquote(value).parse::<u32>().ok(),
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(sheet)
+}
+
+fn last_track(sheet: &mut CueSheet) -> Option<&mut CueTrack> {
+    sheet.files.last_mut().and_then(|f| f.tracks.last_mut())
+}
+
+fn split_word(text: &str) -> (&str, &str) {
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (text, ""),
+    }
+}
+
+fn file_name(arguments: &str) -> String {
+    // FILE "name.flac" WAVE: the last word is the file type
+    match arguments
+        .strip_prefix('"')
+        .and_then(|rest| rest.split_once('"'))
+    {
+        Some((name, _)) => name.to_string(),
+        None => unquote(arguments.rsplit_once(' ').map_or(arguments, |(n, _)| n)),
+    }
+}
+
+fn unquote(text: &str) -> String {
+    let text = text.trim();
+    text.strip_prefix('"')
+        .and_then(|t| t.strip_suffix('"'))
+        .unwrap_or(text)
+        .to_string()
+}
+
+fn parse_position(position: &str) -> Option<time::Duration> {
+    let mut parts = position.split(':').map(|p| p.parse::<u64>().ok());
+    let minutes = parts.next()??;
+    let seconds = parts.next()??;
+    let frames = parts.next()??;
+    if parts.next().is_some() {
+        return None;
+    }
+
+    let total_frames = (minutes * 60 + seconds) * FRAMES_PER_SECOND + frames;
+    Some(time::Duration::from_micros(
+        total_frames * 1_000_000 / FRAMES_PER_SECOND,
+    ))
+}
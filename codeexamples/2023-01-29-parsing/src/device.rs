@@ -0,0 +1,363 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, BufRead};
+use std::path;
+use std::time;
+
+use crate::{model, util};
+
+const COVER_FILE_NAME: &str = "cover.jpg";
+
+// Lists every file the last sync wrote, relative to the target directory,
+// so a sync only ever removes what an earlier one put there
+const RECORD_FILE_NAME: &str = ".simpleaudioparser-sync";
+const RECORD_HEADER: &str = "# simpleaudioparser device sync 1";
+
+// FAT filesystems, as found on phones and SD cards, only keep modification times to 2 seconds
+const MODIFIED_TOLERANCE: time::Duration = time::Duration::from_secs(2);
+
+pub fn sync_to_device(
+    library: &model::Library,
+    target_directory: &path::Path,
+    options: &model::DeviceSyncOptions,
+) -> Result<model::DeviceSyncReport, model::Error> {
+    let mut not_in_library = Vec::new();
+    let tracks = select_tracks(library, &options.selection, &mut not_in_library)?;
+
+    let mut planned: BTreeMap<path::PathBuf, model::DeviceFile> = BTreeMap::new();
+    let mut left_out = Vec::new();
+    let mut total_bytes = 0;
+    for track in tracks.into_iter() {
+        // Once something did not fit, everything selected after it is left out too,
+        // rather than filling the gap with whatever smaller files come later
+        if !left_out.is_empty() {
+            left_out.push(track_key(track));
+            continue;
+        }
+
+        let files: Vec<model::DeviceFile> = files_for_track(track)?
+            .into_iter()
+            .filter(|file| !planned.contains_key(&file.target_path))
+            .collect();
+        let files_bytes: u64 = files.iter().map(|file| file.size_bytes).sum();
+        if options
+            .max_total_bytes
+            .is_some_and(|max| total_bytes + files_bytes > max)
+        {
+            left_out.push(track_key(track));
+            continue;
+        }
+
+        total_bytes += files_bytes;
+        for file in files.into_iter() {
+            planned.insert(file.target_path.clone(), file);
+        }
+    }
+
+    let mut recorded = read_record(target_directory)?;
+    let synced: Vec<path::PathBuf> = planned.keys().cloned().collect();
+
+    let mut report = model::DeviceSyncReport {
+        copied: Vec::new(),
+        removed: Vec::new(),
+        unchanged: 0,
+        left_out,
+        not_in_library,
+        bytes_to_add: 0,
+        bytes_to_remove: 0,
+        total_bytes,
+    };
+
+    for file in planned.into_values() {
+        recorded.remove(&file.target_path);
+        // A file already at a planned path is taken over, whoever wrote it
+        match fs::metadata(target_directory.join(&file.target_path)).ok() {
+            Some(metadata) if is_unchanged(&file, &metadata) => report.unchanged += 1,
+            Some(metadata) => {
+                report.bytes_to_remove += metadata.len();
+                report.bytes_to_add += file.size_bytes;
+                report.copied.push(file);
+            }
+            None => {
+                report.bytes_to_add += file.size_bytes;
+                report.copied.push(file);
+            }
+        }
+    }
+    for target_path in recorded.into_iter() {
+        // Whatever was deleted by hand since is simply forgotten
+        if let Ok(metadata) = fs::metadata(target_directory.join(&target_path)) {
+            report.bytes_to_remove += metadata.len();
+            report.removed.push(target_path);
+        }
+    }
+
+    if !options.dry_run {
+        apply(&report, &synced, target_directory)?;
+    }
+
+    Ok(report)
+}
+
+fn select_tracks<'a>(
+    library: &'a model::Library,
+    selection: &[model::DeviceSelection],
+    not_in_library: &mut Vec<path::PathBuf>,
+) -> Result<Vec<&'a model::AudioFileTrackMetadata>, model::Error> {
+    let mut by_path = None;
+    let mut seen = BTreeSet::new();
+    let mut tracks = Vec::new();
+    let mut add = |track: &'a model::AudioFileTrackMetadata| {
+        if seen.insert(&track.id) {
+            tracks.push(track);
+        }
+    };
+
+    for selected in selection.iter() {
+        match selected {
+            model::DeviceSelection::Artist(artist_name) => {
+                if let Some(artist) = library.artists.get(artist_name) {
+                    artist.albums.values().for_each(|album| {
+                        album_tracks(album).for_each(&mut add);
+                    });
+                }
+            }
+            model::DeviceSelection::Album(key) => {
+                if let Some(album) = library
+                    .artists
+                    .get(&key.artist_name)
+                    .and_then(|artist| artist.albums.get(&key.album_name))
+                {
+                    album_tracks(album).for_each(&mut add);
+                }
+            }
+            model::DeviceSelection::Playlist(playlist_path) => {
+                let by_path = by_path.get_or_insert_with(|| tracks_by_path(library));
+                for entry in read_playlist(playlist_path)?.into_iter() {
+                    let wanted = fs::canonicalize(&entry).unwrap_or_else(|_| entry.clone());
+                    match by_path.get(&wanted) {
+                        Some(tracks) => tracks.iter().for_each(|track| add(track)),
+                        None => not_in_library.push(entry),
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(tracks)
+}
+
+fn album_tracks(album: &model::Album) -> impl Iterator<Item = &model::AudioFileTrackMetadata> {
+    album.discs.values().flat_map(|disc| disc.tracks.values())
+}
+
+fn read_playlist(playlist_path: &path::Path) -> Result<Vec<path::PathBuf>, model::Error> {
+    // .m3u files are often Latin-1 rather than UTF-8;
+    // an entry that does not decode just won't match a file of the library
+    let text = String::from_utf8_lossy(&fs::read(playlist_path)?).to_string();
+    let playlist_directory = playlist_path.parent().unwrap_or(path::Path::new(""));
+
+    Ok(text
+        .lines()
+        .map(|line| line.trim_start_matches('\u{feff}').trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| playlist_directory.join(line))
+        .collect())
+}
+
+fn tracks_by_path(
+    library: &model::Library,
+) -> BTreeMap<path::PathBuf, Vec<&model::AudioFileTrackMetadata>> {
+    // The playlist and the library may name the same file differently, e.g. through "..",
+    // and a single-file rip holds every track of its CUE sheet
+    let mut by_path: BTreeMap<path::PathBuf, Vec<&model::AudioFileTrackMetadata>> = BTreeMap::new();
+    for track in library
+        .artists
+        .values()
+        .flat_map(|artist| artist.albums.values())
+        .flat_map(album_tracks)
+    {
+        let full_path =
+            fs::canonicalize(&track.full_path).unwrap_or_else(|_| track.full_path.clone());
+        by_path.entry(full_path).or_default().push(track);
+    }
+    by_path
+}
+
+fn track_key(track: &model::AudioFileTrackMetadata) -> model::TrackKey {
+    model::TrackKey {
+        artist_name: track.resolve_album_artist(),
+        album_name: track.resolve_album(),
+        disc_number: track.resolve_disc_number(),
+        track_number: track.resolve_track_number(),
+    }
+}
+
+fn files_for_track(
+    track: &model::AudioFileTrackMetadata,
+) -> Result<Vec<model::DeviceFile>, model::Error> {
+    let disc_directory = path::PathBuf::from(device_file_name(&track.resolve_album_artist()))
+        .join(device_file_name(&track.resolve_album()))
+        .join(format!("Disc {}", track.resolve_disc_number()));
+    let source_directory = track.full_path.parent().unwrap_or(path::Path::new(""));
+
+    let mut source_paths = vec![track.full_path.clone()];
+    let cover_path = source_directory.join(COVER_FILE_NAME);
+    if cover_path.is_file() {
+        source_paths.push(cover_path);
+    }
+    // A track cut from a single-file rip is only playable with the CUE sheet next to it
+    if track.range.is_some() {
+        for entry in fs::read_dir(source_directory)? {
+            let entry_path = entry?.path();
+            if util::get_maybe_extension_string(&entry_path).as_deref() == Some("cue") {
+                source_paths.push(entry_path);
+            }
+        }
+    }
+
+    let mut files = Vec::new();
+    for source_path in source_paths.into_iter() {
+        let file_name = match source_path.file_name() {
+            Some(file_name) => device_file_name(&file_name.to_string_lossy()),
+            None => continue,
+        };
+        files.push(model::DeviceFile {
+            size_bytes: fs::metadata(&source_path)?.len(),
+            target_path: disc_directory.join(file_name),
+            source_path,
+        });
+    }
+
+    Ok(files)
+}
+
+fn device_file_name(name: &str) -> String {
+    // Phones and SD cards are usually FAT or exFAT, which allow a lot less than Linux does
+    let replaced: String = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let trimmed = replaced.trim_end_matches(['.', ' ']).trim_start();
+    if trimmed.is_empty() {
+        "_".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+fn read_record(target_directory: &path::Path) -> Result<BTreeSet<path::PathBuf>, model::Error> {
+    let file = match fs::File::open(target_directory.join(RECORD_FILE_NAME)) {
+        Ok(file) => io::BufReader::new(file),
+        // Never synced to, or nothing of it is ours
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeSet::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut recorded = BTreeSet::new();
+    for line in file.lines() {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // Only ever a path below the target, however the record was edited
+        let relative_path = path::PathBuf::from(line);
+        if relative_path
+            .components()
+            .all(|component| matches!(component, path::Component::Normal(_)))
+        {
+            recorded.insert(relative_path);
+        }
+    }
+    Ok(recorded)
+}
+
+fn write_record<'a, I: Iterator<Item = &'a path::PathBuf>>(
+    target_directory: &path::Path,
+    relative_paths: I,
+) -> Result<(), model::Error> {
+    // Device file names never hold a line break, see device_file_name
+    let mut text = String::new();
+    text.push_str(RECORD_HEADER);
+    text.push('\n');
+    for relative_path in relative_paths {
+        text.push_str(&relative_path.to_string_lossy());
+        text.push('\n');
+    }
+
+    fs::create_dir_all(target_directory)?;
+    let record_path = target_directory.join(RECORD_FILE_NAME);
+    let mut temporary_path = record_path.as_os_str().to_owned();
+    temporary_path.push(".saving");
+    fs::write(&temporary_path, text)?;
+    fs::rename(&temporary_path, &record_path)?;
+    Ok(())
+}
+
+fn is_unchanged(file: &model::DeviceFile, existing: &fs::Metadata) -> bool {
+    if existing.len() != file.size_bytes {
+        return false;
+    }
+    // Copies get the modification time of their source, so a newer source means it was edited
+    let source_modified = fs::metadata(&file.source_path).and_then(|m| m.modified());
+    match (source_modified, existing.modified()) {
+        (Ok(source), Ok(target)) => {
+            let difference = source
+                .duration_since(target)
+                .or_else(|_| target.duration_since(source))
+                .unwrap_or_default();
+            difference <= MODIFIED_TOLERANCE
+        }
+        _ => false,
+    }
+}
+
+fn apply(
+    report: &model::DeviceSyncReport,
+    synced: &[path::PathBuf],
+    target_directory: &path::Path,
+) -> Result<(), model::Error> {
+    // Recorded up front, so what an interrupted sync leaves behind is still removed later
+    write_record(target_directory, synced.iter().chain(report.removed.iter()))?;
+
+    // Remove first, so the space is free before copying
+    for relative_path in report.removed.iter() {
+        let full_path = target_directory.join(relative_path);
+        fs::remove_file(&full_path)?;
+        remove_empty_directories(target_directory, full_path.parent());
+    }
+
+    // An interrupted copy leaves a file of the wrong size, which the next sync copies again
+    for file in report.copied.iter() {
+        let full_path = target_directory.join(&file.target_path);
+        if let Some(parent) = full_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(&file.source_path, &full_path)?;
+        let source_modified = fs::metadata(&file.source_path)?.modified()?;
+        fs::File::options()
+            .write(true)
+            .open(&full_path)?
+            .set_modified(source_modified)?;
+    }
+
+    write_record(target_directory, synced.iter())
+}
+
+fn remove_empty_directories(target_directory: &path::Path, mut directory: Option<&path::Path>) {
+    // Walk up until a directory still has something in it, but never past the target itself;
+    // remove_dir refuses to remove a directory that is not empty
+    while let Some(current) = directory {
+        if current == target_directory || !current.starts_with(target_directory) {
+            break;
+        }
+        if fs::remove_dir(current).is_err() {
+            break;
+        }
+        directory = current.parent();
+    }
+}
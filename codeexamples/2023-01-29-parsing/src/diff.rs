@@ -0,0 +1,230 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::model;
+
+pub fn diff_libraries(old: &model::Library, new: &model::Library) -> model::LibraryDiff {
+    let old_artists: BTreeSet<&String> = old.artists.keys().collect();
+    let new_artists: BTreeSet<&String> = new.artists.keys().collect();
+
+    let old_albums = album_keys(old);
+    let new_albums = album_keys(new);
+
+    let old_tracks = all_tracks(old);
+    let new_tracks = all_tracks(new);
+
+    // Tracks are matched by id first, which follows the audio through tag edits,
+    // and only then by where they sit in the library
+    let mut new_by_id: BTreeMap<&model::TrackId, usize> = BTreeMap::new();
+    let mut new_by_key: BTreeMap<&model::TrackKey, usize> = BTreeMap::new();
+    for (index, (key, track)) in new_tracks.iter().enumerate() {
+        new_by_id.entry(&track.id).or_insert(index);
+        new_by_key.entry(key).or_insert(index);
+    }
+
+    let mut matched = BTreeSet::new();
+    let mut old_unmatched = Vec::new();
+    let mut changed_tracks = Vec::new();
+
+    for (old_key, old_track) in old_tracks.iter() {
+        let found = new_by_id
+            .get(&old_track.id)
+            .filter(|index| !matched.contains(*index))
+            .or_else(|| {
+                new_by_key
+                    .get(old_key)
+                    .filter(|index| !matched.contains(*index))
+            })
+            .cloned();
+
+        match found {
+            Some(index) => {
+                matched.insert(index);
+                let (new_key, new_track) = &new_tracks[index];
+                let fields = changed_fields(old_track, new_track);
+                if !fields.is_empty() {
+                    changed_tracks.push(model::TrackChange {
+                        id: new_track.id.clone(),
+                        old_key: old_key.clone(),
+                        new_key: new_key.clone(),
+                        fields,
+                    });
+                }
+            }
+            None => old_unmatched.push(old_key.clone()),
+        }
+    }
+
+    let added_tracks = new_tracks
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| !matched.contains(index))
+        .map(|(_, (key, _))| key.clone())
+        .collect();
+
+    model::LibraryDiff {
+        added_artists: new_artists
+            .difference(&old_artists)
+            .map(|a| a.to_string())
+            .collect(),
+        removed_artists: old_artists
+            .difference(&new_artists)
+            .map(|a| a.to_string())
+            .collect(),
+        added_albums: new_albums.difference(&old_albums).cloned().collect(),
+        removed_albums: old_albums.difference(&new_albums).cloned().collect(),
+        added_tracks,
+        removed_tracks: old_unmatched,
+        changed_tracks,
+    }
+}
+
+fn album_keys(library: &model::Library) -> BTreeSet<model::AlbumKey> {
+    library
+        .artists
+        .values()
+        .flat_map(|artist| {
+            artist.albums.values().map(|album| model::AlbumKey {
+                artist_name: artist.name.clone(),
+                album_name: album.name.clone(),
+            })
+        })
+        .collect()
+}
+
+fn all_tracks(library: &model::Library) -> Vec<(model::TrackKey, &model::AudioFileTrackMetadata)> {
+    let mut tracks = Vec::new();
+    for artist in library.artists.values() {
+        for album in artist.albums.values() {
+            for disc in album.discs.values() {
+                for (track_number, track) in disc.tracks.iter() {
+                    tracks.push((
+                        model::TrackKey {
+                            artist_name: artist.name.clone(),
+                            album_name: album.name.clone(),
+                            disc_number: disc.number,
+                            track_number: *track_number,
+                        },
+                        track,
+                    ));
+                }
+            }
+        }
+    }
+    tracks
+}
+
+fn changed_fields(
+    old: &model::AudioFileTrackMetadata,
+    new: &model::AudioFileTrackMetadata,
+) -> Vec<model::FieldChange> {
+    // Only the tags are compared; paths differ between roots by design,
+    // and durations depend on how precisely each format could be measured
+    let fields: [(&'static str, Option<String>, Option<String>); 24] = [
+        ("artist", Some(old.artist.clone()), Some(new.artist.clone())),
+        (
+            "album_artist",
+            old.album_artist.clone(),
+            new.album_artist.clone(),
+        ),
+        ("album", old.album.clone(), new.album.clone()),
+        ("disc_no", to_string(old.disc_no), to_string(new.disc_no)),
+        (
+            "disc_total",
+            to_string(old.disc_total),
+            to_string(new.disc_total),
+        ),
+        ("track", to_string(old.track), to_string(new.track)),
+        (
+            "track_total",
+            to_string(old.track_total),
+            to_string(new.track_total),
+        ),
+        (
+            "track_title",
+            Some(old.track_title.clone()),
+            Some(new.track_title.clone()),
+        ),
+        ("genre", old.genre.clone(), new.genre.clone()),
+        ("date", old.date.clone(), new.date.clone()),
+        (
+            "composer",
+            old.classical.composer.clone(),
+            new.classical.composer.clone(),
+        ),
+        (
+            "conductor",
+            old.classical.conductor.clone(),
+            new.classical.conductor.clone(),
+        ),
+        (
+            "performer",
+            old.classical.performer.clone(),
+            new.classical.performer.clone(),
+        ),
+        (
+            "work",
+            old.classical.work.clone(),
+            new.classical.work.clone(),
+        ),
+        (
+            "movement_name",
+            old.classical.movement_name.clone(),
+            new.classical.movement_name.clone(),
+        ),
+        (
+            "movement_number",
+            to_string(old.classical.movement_number),
+            to_string(new.classical.movement_number),
+        ),
+        ("rating", to_string(old.rating), to_string(new.rating)),
+        (
+            "musicbrainz_release_track_id",
+            old.musicbrainz.release_track_id.clone(),
+            new.musicbrainz.release_track_id.clone(),
+        ),
+        (
+            "musicbrainz_album_id",
+            old.musicbrainz.album_id.clone(),
+            new.musicbrainz.album_id.clone(),
+        ),
+        (
+            "musicbrainz_album_artist_id",
+            old.musicbrainz.album_artist_id.clone(),
+            new.musicbrainz.album_artist_id.clone(),
+        ),
+        (
+            "replaygain_track_gain",
+            to_string(old.replay_gain.track_gain),
+            to_string(new.replay_gain.track_gain),
+        ),
+        (
+            "replaygain_album_gain",
+            to_string(old.replay_gain.album_gain),
+            to_string(new.replay_gain.album_gain),
+        ),
+        (
+            "replaygain_track_peak",
+            to_string(old.replay_gain.track_peak),
+            to_string(new.replay_gain.track_peak),
+        ),
+        (
+            "replaygain_album_peak",
+            to_string(old.replay_gain.album_peak),
+            to_string(new.replay_gain.album_peak),
+        ),
+    ];
+
+    fields
+        .into_iter()
+        .filter(|(_, old_value, new_value)| old_value != new_value)
+        .map(|(field, old_value, new_value)| model::FieldChange {
+            field,
+            old_value,
+            new_value,
+        })
+        .collect()
+}
+
+fn to_string<T: ToString>(value: Option<T>) -> Option<String> {
+    value.map(|v| v.to_string())
+}
@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::time;
+
+use crate::{fingerprint, model, util};
+
+pub fn find_duplicates(
+    tracks: Vec<model::AudioFileTrackMetadata>,
+    options: &model::DuplicateOptions,
+    events: &mut dyn FnMut(model::ScanEvent),
+) -> Result<Vec<model::DuplicateGroup>, model::Error> {
+    let mut by_artist_and_title: BTreeMap<(String, String), Vec<model::AudioFileTrackMetadata>> =
+        BTreeMap::new();
+
+    for track in tracks.into_iter() {
+        by_artist_and_title
+            .entry((
+                normalize_artist(&track.artist),
+                normalize_title(&track.track_title),
+            ))
+            .or_default()
+            .push(track);
+    }
+
+    let mut groups = Vec::new();
+
+    for candidates in by_artist_and_title.into_values() {
+        if candidates.len() < 2 {
+            continue;
+        }
+
+        for cluster in cluster_by_duration(candidates, options.duration_tolerance) {
+            if cluster.len() < 2 {
+                continue;
+            }
+            if let Some(group) = build_group(cluster, options, events)? {
+                groups.push(group);
+            }
+        }
+    }
+
+    Ok(groups)
+}
+
+fn cluster_by_duration(
+    tracks: Vec<model::AudioFileTrackMetadata>,
+    tolerance: time::Duration,
+) -> Vec<Vec<model::AudioFileTrackMetadata>> {
+    let mut clusters: Vec<Vec<model::AudioFileTrackMetadata>> = Vec::new();
+
+    for track in tracks.into_iter() {
+        // A track whose length we could not determine is allowed to match anything
+        let matching_cluster = clusters.iter_mut().find(|cluster| {
+            cluster
+                .iter()
+                .all(|other| durations_match(track.duration, other.duration, tolerance))
+        });
+
+        match matching_cluster {
+            Some(cluster) => cluster.push(track),
+            None => clusters.push(vec![track]),
+        }
+    }
+
+    clusters
+}
+
+fn durations_match(
+    a: Option<time::Duration>,
+    b: Option<time::Duration>,
+    tolerance: time::Duration,
+) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.abs_diff(b) <= tolerance,
+        _ => true,
+    }
+}
+
+fn build_group(
+    mut tracks: Vec<model::AudioFileTrackMetadata>,
+    options: &model::DuplicateOptions,
+    events: &mut dyn FnMut(model::ScanEvent),
+) -> Result<Option<model::DuplicateGroup>, model::Error> {
+    // Lossless copies go first so they are the reference for fingerprinting
+    // and the natural one to keep when cleaning up
+    tracks.sort_by_key(|track| {
+        let format = util::get_maybe_extension_string(&track.full_path);
+        (format.as_deref() != Some("flac"), track.full_path.clone())
+    });
+
+    let artist = tracks[0].artist.clone();
+    let track_title = tracks[0].track_title.clone();
+
+    let similarities = if options.compare_fingerprints {
+        // Damaged copies are what a cleanup turns up, so a file that can't be fingerprinted
+        // is reported and stays in the group without a similarity, rather than ending the search
+        let mut fingerprints = Vec::new();
+        for track in tracks.iter() {
+            match fingerprint::fingerprint_file(&track.full_path, track.range.as_ref()) {
+                Ok(fingerprint) => fingerprints.push(Some(fingerprint)),
+                Err(e) => {
+                    events(model::ScanEvent::Failed(track.full_path.clone(), e));
+                    fingerprints.push(None);
+                }
+            }
+        }
+        // The first copy that could be read is the reference
+        match fingerprints.iter().flatten().next() {
+            Some(reference) => fingerprints
+                .iter()
+                .map(|fingerprint| {
+                    fingerprint
+                        .as_ref()
+                        .map(|candidate| reference.similarity(candidate))
+                })
+                .collect(),
+            None => vec![None; tracks.len()],
+        }
+    } else {
+        vec![None; tracks.len()]
+    };
+
+    let mut entries = Vec::new();
+    for (track, similarity) in tracks.into_iter().zip(similarities) {
+        if let Some(s) = similarity {
+            if s < options.fingerprint_threshold {
+                continue;
+            }
+        }
+
+        entries.push(model::DuplicateEntry {
+            format: util::get_maybe_extension_string(&track.full_path).unwrap_or_default(),
+            size_bytes: fs::metadata(&track.full_path)?.len(),
+            album: track.resolve_album(),
+            duration: track.duration,
+            fingerprint_similarity: similarity,
+            full_path: track.full_path,
+        });
+    }
+
+    if entries.len() < 2 {
+        return Ok(None);
+    }
+
+    Ok(Some(model::DuplicateGroup {
+        artist,
+        track_title,
+        entries,
+    }))
+}
+
+fn normalize_artist(artist: &str) -> String {
+    // "Someone feat. Someone Else" is still a duplicate of "Someone"
+    let lowered = artist.to_lowercase();
+    let main_artist = [" feat.", " feat ", " ft.", " featuring "]
+        .iter()
+        .filter_map(|marker| lowered.find(marker))
+        .min()
+        .map(|index| &lowered[..index])
+        .unwrap_or(&lowered);
+    normalize_title(main_artist)
+}
+
+fn normalize_title(title: &str) -> String {
+    // Drop bracketed qualifiers such as "(Remastered 2011)" or "[Album Version]",
+    // then compare only the lowercased words, ignoring punctuation and spacing
+    let mut depth = 0u32;
+    let mut unbracketed = String::new();
+    for c in title.chars() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth = depth.saturating_sub(1),
+            _ if depth == 0 => unbracketed.push(c),
+            _ => (),
+        }
+    }
+
+    unbracketed
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
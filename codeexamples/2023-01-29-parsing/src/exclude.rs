@@ -0,0 +1,196 @@
+use std::fs;
+use std::io;
+use std::path;
+
+pub const MUSICIGNORE_FILE_NAME: &str = ".musicignore";
+
+// Exclude patterns follow .gitignore rules: later patterns override earlier ones,
+// "!" re-includes, a trailing "/" only matches directories, and a pattern containing
+// a "/" is relative to where it was defined rather than matching a name at any depth
+#[derive(Clone)]
+pub struct ExcludeRules {
+    rules: Vec<ExcludeRule>,
+}
+
+#[derive(Clone)]
+struct ExcludeRule {
+    base: path::PathBuf,
+    pattern: Vec<char>,
+    anchored: bool,
+    negated: bool,
+    directory_only: bool,
+}
+
+impl ExcludeRules {
+    pub fn new<S: AsRef<str>>(base: &path::Path, patterns: &[S]) -> Self {
+        let mut rules = ExcludeRules { rules: Vec::new() };
+        rules.add_patterns(base, patterns.iter().map(|p| p.as_ref()));
+        rules
+    }
+
+    pub fn with_musicignore(&self, directory: &path::Path) -> Result<Self, io::Error> {
+        let musicignore_path = directory.join(MUSICIGNORE_FILE_NAME);
+        if !musicignore_path.is_file() {
+            return Ok(self.clone());
+        }
+
+        let contents = fs::read(&musicignore_path)?;
+        let contents = String::from_utf8_lossy(&contents);
+        let mut rules = self.clone();
+        rules.add_patterns(directory, contents.lines());
+        Ok(rules)
+    }
+
+    pub fn is_excluded(&self, path: &path::Path, is_directory: bool) -> bool {
+        let mut excluded = false;
+        for rule in self.rules.iter() {
+            if rule.directory_only && !is_directory {
+                continue;
+            }
+            if rule.matches(path) {
+                excluded = !rule.negated;
+            }
+        }
+        excluded
+    }
+
+    fn add_patterns<'a, I: Iterator<Item = &'a str>>(&mut self, base: &path::Path, lines: I) {
+        for line in lines {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+            let (directory_only, line) = match line.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let anchored = line.contains('/');
+            let line = line.strip_prefix('/').unwrap_or(line);
+            if line.is_empty() {
+                continue;
+            }
+
+            self.rules.push(ExcludeRule {
+                base: base.to_path_buf(),
+                pattern: line.chars().collect(),
+                anchored,
+                negated,
+                directory_only,
+            });
+        }
+    }
+}
+
+impl ExcludeRule {
+    fn matches(&self, path: &path::Path) -> bool {
+        let relative = match path.strip_prefix(&self.base) {
+            Ok(v) => v,
+            Err(_) => return false,
+        };
+
+        let text: Vec<char> = if self.anchored {
+            relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().to_string())
+                .collect::<Vec<String>>()
+                .join("/")
+                .chars()
+                .collect()
+        } else {
+            match relative.file_name() {
+                Some(name) => name.to_string_lossy().chars().collect(),
+                None => return false,
+            }
+        };
+
+        glob_match(&self.pattern, &text)
+    }
+}
+
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    // "**/" matches any number of leading directories, including none
+    if pattern.len() >= 3 && pattern[0] == '*' && pattern[1] == '*' && pattern[2] == '/' {
+        let rest = &pattern[3..];
+        if glob_match(rest, text) {
+            return true;
+        }
+        return (0..text.len())
+            .filter(|i| text[*i] == '/')
+            .any(|i| glob_match(rest, &text[i + 1..]));
+    }
+    // A trailing or otherwise bare "**" matches everything below
+    if pattern.len() >= 2 && pattern[0] == '*' && pattern[1] == '*' {
+        let rest = &pattern[2..];
+        return (0..=text.len()).any(|i| glob_match(rest, &text[i..]));
+    }
+
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') => {
+            let rest = &pattern[1..];
+            for i in 0..=text.len() {
+                if glob_match(rest, &text[i..]) {
+                    return true;
+                }
+                if i < text.len() && text[i] == '/' {
+                    break;
+                }
+            }
+            false
+        }
+        Some('?') => match text.first() {
+            Some(c) if *c != '/' => glob_match(&pattern[1..], &text[1..]),
+            _ => false,
+        },
+        Some('[') => match (text.first(), match_class(&pattern[1..], text.first())) {
+            (Some(_), Some((true, consumed))) => glob_match(&pattern[1 + consumed..], &text[1..]),
+            _ => false,
+        },
+        Some('\\') if pattern.len() > 1 => match text.first() {
+            Some(c) if *c == pattern[1] => glob_match(&pattern[2..], &text[1..]),
+            _ => false,
+        },
+        Some(p) => match text.first() {
+            Some(c) if c == p => glob_match(&pattern[1..], &text[1..]),
+            _ => false,
+        },
+    }
+}
+
+fn match_class(class: &[char], c: Option<&char>) -> Option<(bool, usize)> {
+    // Returns whether the character is in the class,
+    // and how much of the pattern the class (with its closing "]") used up
+    let c = *c?;
+    let (negated, mut i) = match class.first() {
+        Some('!') | Some('^') => (true, 1),
+        _ => (false, 0),
+    };
+
+    let mut matched = false;
+    let mut first = true;
+    while i < class.len() {
+        if class[i] == ']' && !first {
+            return Some((matched != negated && c != '/', i + 1));
+        }
+        first = false;
+        if i + 2 < class.len() && class[i + 1] == '-' && class[i + 2] != ']' {
+            if class[i] <= c && c <= class[i + 2] {
+                matched = true;
+            }
+            i += 3;
+        } else {
+            if class[i] == c {
+                matched = true;
+            }
+            i += 1;
+        }
+    }
+
+    // An unclosed "[" never matches, like in git
+    None
+}
@@ -0,0 +1,243 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{model, util};
+
+const CSV_HEADER: [&str; 12] = [
+    "album_artist",
+    "album",
+    "disc",
+    "track",
+    "title",
+    "artist",
+    "genre",
+    "date",
+    "duration_seconds",
+    "format",
+    "path",
+    "id",
+];
+
+// The shape of a JSON export, which snapshot::read_snapshot reads back
+#[derive(Serialize, Deserialize)]
+pub struct LibraryJson {
+    pub artists: Vec<ArtistJson>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ArtistJson {
+    pub id: String,
+    pub name: String,
+    pub albums: Vec<AlbumJson>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AlbumJson {
+    pub id: String,
+    pub name: String,
+    pub dominant_genre: Option<String>,
+    pub discs: Vec<DiscJson>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DiscJson {
+    pub number: u32,
+    pub tracks: Vec<TrackJson>,
+}
+
+// Lyrics are left out, they would make up most of the export
+#[derive(Serialize, Deserialize)]
+pub struct TrackJson {
+    pub id: String,
+    pub artist: String,
+    pub album_artist: Option<String>,
+    pub album: Option<String>,
+    pub disc_no: Option<u32>,
+    pub disc_total: Option<u32>,
+    pub track: Option<u32>,
+    pub track_total: Option<u32>,
+    pub track_title: String,
+    pub genre: Option<String>,
+    pub date: Option<String>,
+    pub duration_seconds: Option<f64>,
+    #[serde(default)]
+    pub musicbrainz: MusicBrainzJson,
+    #[serde(default)]
+    pub replay_gain: ReplayGainJson,
+    #[serde(default)]
+    pub classical: ClassicalJson,
+    pub rating: Option<f64>,
+    pub range: Option<RangeJson>,
+    // Exports from before fields could be inferred don't have the array
+    #[serde(default)]
+    pub inferred_fields: Option<Vec<String>>,
+    // JSON strings can't hold a path that isn't valid UTF-8
+    pub full_path: String,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct MusicBrainzJson {
+    pub release_track_id: Option<String>,
+    pub album_id: Option<String>,
+    pub album_artist_id: Option<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct ReplayGainJson {
+    pub track_gain: Option<f64>,
+    pub track_peak: Option<f64>,
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct ClassicalJson {
+    pub composer: Option<String>,
+    pub conductor: Option<String>,
+    pub performer: Option<String>,
+    pub work: Option<String>,
+    pub movement_name: Option<String>,
+    pub movement_number: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RangeJson {
+    pub start_seconds: Option<f64>,
+    pub end_seconds: Option<f64>,
+}
+
+pub fn library_to_json(library: &model::Library) -> String {
+    let export = LibraryJson {
+        artists: library
+            .artists
+            .values()
+            .map(|artist| ArtistJson {
+                id: artist.id.0.clone(),
+                name: artist.name.clone(),
+                albums: artist.albums.values().map(album_to_json).collect(),
+            })
+            .collect(),
+    };
+
+    let mut text = serde_json::to_string_pretty(&export).expect("an export always serializes");
+    text.push('\n');
+    text
+}
+
+fn album_to_json(album: &model::Album) -> AlbumJson {
+    AlbumJson {
+        id: album.id.0.clone(),
+        name: album.name.clone(),
+        dominant_genre: album.dominant_genre.clone(),
+        discs: album
+            .discs
+            .values()
+            .map(|disc| DiscJson {
+                number: disc.number,
+                tracks: disc.tracks.values().map(track_to_json).collect(),
+            })
+            .collect(),
+    }
+}
+
+fn track_to_json(track: &model::AudioFileTrackMetadata) -> TrackJson {
+    TrackJson {
+        id: track.id.0.clone(),
+        artist: track.artist.clone(),
+        album_artist: track.album_artist.clone(),
+        album: track.album.clone(),
+        disc_no: track.disc_no,
+        disc_total: track.disc_total,
+        track: track.track,
+        track_total: track.track_total,
+        track_title: track.track_title.clone(),
+        genre: track.genre.clone(),
+        date: track.date.clone(),
+        duration_seconds: track.duration.map(|d| d.as_secs_f64()),
+        musicbrainz: MusicBrainzJson {
+            release_track_id: track.musicbrainz.release_track_id.clone(),
+            album_id: track.musicbrainz.album_id.clone(),
+            album_artist_id: track.musicbrainz.album_artist_id.clone(),
+        },
+        replay_gain: ReplayGainJson {
+            track_gain: track.replay_gain.track_gain,
+            track_peak: track.replay_gain.track_peak,
+            album_gain: track.replay_gain.album_gain,
+            album_peak: track.replay_gain.album_peak,
+        },
+        classical: ClassicalJson {
+            composer: track.classical.composer.clone(),
+            conductor: track.classical.conductor.clone(),
+            performer: track.classical.performer.clone(),
+            work: track.classical.work.clone(),
+            movement_name: track.classical.movement_name.clone(),
+            movement_number: track.classical.movement_number,
+        },
+        rating: track.rating,
+        range: track.range.map(|range| RangeJson {
+            start_seconds: Some(range.start.as_secs_f64()),
+            end_seconds: range.end.map(|e| e.as_secs_f64()),
+        }),
+        inferred_fields: Some(
+            track
+                .inferred_fields
+                .iter()
+                .map(|field| field.name().to_string())
+                .collect(),
+        ),
+        full_path: track.full_path.to_string_lossy().to_string(),
+    }
+}
+
+pub fn library_to_csv(library: &model::Library) -> String {
+    let mut out = String::new();
+    write_csv_row(&mut out, CSV_HEADER.iter().map(|h| h.to_string()));
+
+    for artist in library.artists.values() {
+        for album in artist.albums.values() {
+            for disc in album.discs.values() {
+                for track in disc.tracks.values() {
+                    write_csv_row(
+                        &mut out,
+                        [
+                            artist.name.clone(),
+                            album.name.clone(),
+                            disc.number.to_string(),
+                            track.resolve_track_number().to_string(),
+                            track.track_title.clone(),
+                            track.artist.clone(),
+                            track.genre.clone().unwrap_or_default(),
+                            track.date.clone().unwrap_or_default(),
+                            track
+                                .duration
+                                .map(|d| format!("{:.3}", d.as_secs_f64()))
+                                .unwrap_or_default(),
+                            util::get_maybe_extension_string(&track.full_path).unwrap_or_default(),
+                            track.full_path.to_string_lossy().to_string(),
+                            track.id.0.clone(),
+                        ]
+                        .into_iter(),
+                    );
+                }
+            }
+        }
+    }
+
+    out
+}
+
+fn write_csv_row<I: Iterator<Item = String>>(out: &mut String, fields: I) {
+    for (index, field) in fields.enumerate() {
+        if index > 0 {
+            out.push(',');
+        }
+        // Quote only when needed, doubling any quotes inside (RFC 4180)
+        if field.contains([',', '"', '\n', '\r']) {
+            out.push('"');
+            out.push_str(&field.replace('"', "\"\""));
+            out.push('"');
+        } else {
+            out.push_str(&field);
+        }
+    }
+    out.push_str("\r\n");
+}
@@ -0,0 +1,126 @@
+use std::fs;
+use std::path;
+use std::time;
+
+use crate::{model, util};
+
+// Length of each analysis window; long enough that the few milliseconds of
+// encoder delay an MP3 adds do not shift the fingerprint
+const WINDOW_MILLISECONDS: u32 = 100;
+
+// A fingerprint is one bit per window: whether the loudness went up
+// compared with the window before it. It ignores the sample rate,
+// bit depth and overall volume of the file, so a FLAC and an MP3
+// made from the same recording produce (almost) the same bits.
+pub struct Fingerprint {
+    bits: Vec<bool>,
+}
+
+impl Fingerprint {
+    pub fn similarity(&self, other: &Fingerprint) -> f64 {
+        let overlap = self.bits.len().min(other.bits.len());
+        if overlap == 0 {
+            return 0.0;
+        }
+        let agreeing = self
+            .bits
+            .iter()
+            .zip(other.bits.iter())
+            .filter(|(a, b)| a == b)
+            .count();
+        agreeing as f64 / overlap as f64
+    }
+}
+
+pub fn fingerprint_file(
+    path: &path::Path,
+    range: Option<&model::TrackRange>,
+) -> Result<Fingerprint, model::Error> {
+    let mut energies = match util::get_maybe_extension_string(path).as_deref() {
+        Some("flac") => flac_window_energies(path)?,
+        Some("mp3") => mp3_window_energies(path)?,
+        _ => Vec::new(),
+    };
+
+    // Only keep the windows of a track cut from a longer file
+    if let Some(range) = range {
+        let window = |d: time::Duration| (d.as_millis() / WINDOW_MILLISECONDS as u128) as usize;
+        let end = range.end.map_or(energies.len(), window).min(energies.len());
+        energies = energies[window(range.start).min(end)..end].to_vec();
+    }
+
+    Ok(Fingerprint {
+        bits: energies.windows(2).map(|w| w[1] > w[0]).collect(),
+    })
+}
+
+struct EnergyWindows {
+    window_length: usize,
+    current_sum: f64,
+    current_count: usize,
+    energies: Vec<f64>,
+}
+
+impl EnergyWindows {
+    fn new(sample_rate: u32) -> Self {
+        EnergyWindows {
+            window_length: (sample_rate * WINDOW_MILLISECONDS / 1000).max(1) as usize,
+            current_sum: 0.0,
+            current_count: 0,
+            energies: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, mono_sample: f64) {
+        self.current_sum += mono_sample * mono_sample;
+        self.current_count += 1;
+        if self.current_count == self.window_length {
+            self.energies
+                .push(self.current_sum / self.current_count as f64);
+            self.current_sum = 0.0;
+            self.current_count = 0;
+        }
+    }
+}
+
+fn flac_window_energies(path: &path::Path) -> Result<Vec<f64>, model::Error> {
+    let mut reader = claxon::FlacReader::open(path)?;
+    let streaminfo = reader.streaminfo();
+    let channels = streaminfo.channels as usize;
+    let scale = (1i64 << (streaminfo.bits_per_sample - 1)) as f64;
+
+    let mut windows = EnergyWindows::new(streaminfo.sample_rate);
+    let mut frame_sum = 0.0;
+    for (index, sample) in reader.samples().enumerate() {
+        frame_sum += sample? as f64 / scale;
+        if index % channels == channels - 1 {
+            windows.push(frame_sum / channels as f64);
+            frame_sum = 0.0;
+        }
+    }
+
+    Ok(windows.energies)
+}
+
+fn mp3_window_energies(path: &path::Path) -> Result<Vec<f64>, model::Error> {
+    let mut decoder = minimp3::Decoder::new(fs::File::open(path)?);
+
+    let mut windows: Option<EnergyWindows> = None;
+    loop {
+        match decoder.next_frame() {
+            Ok(frame) => {
+                let windows =
+                    windows.get_or_insert_with(|| EnergyWindows::new(frame.sample_rate as u32));
+                for interleaved in frame.data.chunks(frame.channels.max(1)) {
+                    let frame_sum: f64 = interleaved.iter().map(|s| *s as f64 / 32768.0).sum();
+                    windows.push(frame_sum / interleaved.len() as f64);
+                }
+            }
+            Err(minimp3::Error::Eof) => break,
+            Err(minimp3::Error::SkippedData) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(windows.map(|w| w.energies).unwrap_or_default())
+}
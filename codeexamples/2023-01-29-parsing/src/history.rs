@@ -0,0 +1,188 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path;
+use std::time;
+
+use crate::model;
+
+const HEADER: &str = "# simpleaudioparser listening history 1";
+
+// Audioscrobbler's rule: a play counts once half the track or four minutes were listened to
+const LISTENED_AT_MOST: time::Duration = time::Duration::from_secs(4 * 60);
+
+pub fn open_history(history_path: &path::Path) -> Result<model::ListeningHistory, model::Error> {
+    let mut history = model::ListeningHistory {
+        path: history_path.to_path_buf(),
+        plays: Vec::new(),
+        stats: BTreeMap::new(),
+    };
+
+    let file = match fs::File::open(history_path) {
+        Ok(file) => io::BufReader::new(file),
+        // Nothing was played yet
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(history),
+        Err(e) => return Err(e.into()),
+    };
+    for (index, line) in file.lines().enumerate() {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let play = parse_play(&line).ok_or_else(|| {
+            model::Error::InvalidListeningHistory(history_path.to_path_buf(), index + 1)
+        })?;
+        add_to_stats(&mut history.stats, &play);
+        history.plays.push(play);
+    }
+
+    Ok(history)
+}
+
+pub fn append_play(
+    history: &mut model::ListeningHistory,
+    play: model::Play,
+) -> Result<(), model::Error> {
+    // Appended rather than rewritten, so the file costs the same to add to however long it gets
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&history.path)?;
+    let mut text = String::new();
+    if file.metadata()?.len() == 0 {
+        text.push_str(HEADER);
+        text.push('\n');
+    }
+    text.push_str(&format_play(&play));
+    text.push('\n');
+    file.write_all(text.as_bytes())?;
+
+    add_to_stats(&mut history.stats, &play);
+    history.plays.push(play);
+    Ok(())
+}
+
+fn add_to_stats(stats: &mut BTreeMap<model::TrackId, model::TrackPlayStats>, play: &model::Play) {
+    let track_stats = stats.entry(play.track_id.clone()).or_default();
+    if play.skipped {
+        track_stats.skip_count += 1;
+    } else {
+        track_stats.play_count += 1;
+    }
+    // Skipped plays count too, the track was still put on
+    track_stats.last_played = track_stats.last_played.max(Some(play.started_at));
+}
+
+pub fn is_skip(played: time::Duration, duration: Option<time::Duration>) -> bool {
+    let listened_at = duration
+        .map(|duration| duration / 2)
+        .unwrap_or(LISTENED_AT_MOST)
+        .min(LISTENED_AT_MOST);
+    played < listened_at
+}
+
+pub fn start_play(
+    tracker: &mut model::PlayTracker,
+    track: &model::AudioFileTrackMetadata,
+    started_at: time::SystemTime,
+) -> Option<model::Play> {
+    let stopped = stop_play(tracker);
+    let play = model::Play {
+        track_id: track.id.clone(),
+        artist: track.artist.clone(),
+        album: track.album.clone(),
+        track_title: track.track_title.clone(),
+        started_at,
+        played: time::Duration::ZERO,
+        skipped: false,
+    };
+    tracker.current = Some((play, track.duration));
+    stopped
+}
+
+pub fn stop_play(tracker: &mut model::PlayTracker) -> Option<model::Play> {
+    let (mut play, duration) = tracker.current.take()?;
+    play.skipped = is_skip(play.played, duration);
+    Some(play)
+}
+
+pub fn end_play(tracker: &mut model::PlayTracker) -> Option<model::Play> {
+    let (mut play, duration) = tracker.current.take()?;
+    // The last progress report comes a little before the end
+    play.played = duration.unwrap_or(play.played).max(play.played);
+    play.skipped = false;
+    Some(play)
+}
+
+fn format_play(play: &model::Play) -> String {
+    let started_at = play
+        .started_at
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    // Tab separated, as names hold anything else
+    [
+        started_at.to_string(),
+        play.played.as_millis().to_string(),
+        if play.skipped { "S" } else { "L" }.to_string(),
+        escape(&play.track_id.0),
+        escape(&play.artist),
+        escape(play.album.as_deref().unwrap_or_default()),
+        escape(&play.track_title),
+    ]
+    .join("\t")
+}
+
+fn parse_play(line: &str) -> Option<model::Play> {
+    let parts: Vec<&str> = line.split('\t').collect();
+    if parts.len() != 7 {
+        return None;
+    }
+    let album = unescape(parts[5])?;
+    Some(model::Play {
+        track_id: model::TrackId(unescape(parts[3])?),
+        artist: unescape(parts[4])?,
+        album: if album.is_empty() { None } else { Some(album) },
+        track_title: unescape(parts[6])?,
+        started_at: time::UNIX_EPOCH + time::Duration::from_secs(parts[0].parse().ok()?),
+        played: time::Duration::from_millis(parts[1].parse().ok()?),
+        skipped: match parts[2] {
+            "L" => false,
+            "S" => true,
+            _ => return None,
+        },
+    })
+}
+
+fn escape(value: &str) -> String {
+    let mut out = String::new();
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn unescape(value: &str) -> Option<String> {
+    let mut out = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        out.push(match chars.next()? {
+            '\\' => '\\',
+            't' => '\t',
+            'n' => '\n',
+            'r' => '\r',
+            _ => return None,
+        });
+    }
+    Some(out)
+}
@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read, Seek};
+use std::path;
+
+use sha2::{Digest, Sha256};
+
+use crate::{model, mpeg, util};
+
+pub fn track_id(musicbrainz: &model::MusicBrainzIds, audio_hash: Option<String>) -> model::TrackId {
+    // Left empty when neither is known, for fill_track_id to make one up after inference
+    match (&musicbrainz.release_track_id, audio_hash) {
+        (Some(mbid), _) => model::TrackId(format!("mb:{}", mbid)),
+        (None, Some(audio_hash)) => model::TrackId(format!("audio:{}", audio_hash)),
+        (None, None) => model::TrackId(String::new()),
+    }
+}
+
+pub fn fill_track_id(
+    track: &mut model::AudioFileTrackMetadata,
+    hash_audio: bool,
+) -> Result<(), model::Error> {
+    if !track.id.0.is_empty() {
+        return Ok(());
+    }
+    let audio_hash = match util::get_maybe_extension_string(&track.full_path).as_deref() {
+        Some("flac") if hash_audio => Some(flac_frames_hash(&track.full_path)?),
+        Some("mp3") if hash_audio => Some(mp3_audio_hash(&track.full_path)?),
+        _ => None,
+    };
+    track.id = match audio_hash {
+        Some(audio_hash) => model::TrackId(format!("audio:{}", audio_hash)),
+        None => tags_track_id(track),
+    };
+    Ok(())
+}
+
+pub fn cue_track_id(whole_file_id: &model::TrackId, track_number: u32) -> model::TrackId {
+    // Every track of a single-file rip shares the file's audio, told apart by its number
+    model::TrackId(format!("{}#{}", whole_file_id.0, track_number))
+}
+
+pub fn flac_stored_hash(md5sum: [u8; 16]) -> Option<String> {
+    // Encoders store an MD5 of the decoded audio in STREAMINFO, which is exactly
+    // the content hash we want for free; it is all zeroes when left unset
+    if md5sum == [0; 16] {
+        None
+    } else {
+        Some(util::to_hex(&md5sum))
+    }
+}
+
+fn flac_frames_hash(path: &path::Path) -> Result<String, model::Error> {
+    let mut file = fs::File::open(path)?;
+    let mut marker_and_header = [0u8; 4];
+    file.read_exact(&mut marker_and_header)?;
+
+    // Skip over every metadata block, where the tags live, to the first audio frame
+    loop {
+        file.read_exact(&mut marker_and_header)?;
+        let is_last = marker_and_header[0] & 0x80 != 0;
+        let length = u32::from_be_bytes([
+            0,
+            marker_and_header[1],
+            marker_and_header[2],
+            marker_and_header[3],
+        ]);
+        file.seek(io::SeekFrom::Current(length as i64))?;
+        if is_last {
+            break;
+        }
+    }
+
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(util::to_hex(&hasher.finalize()[..16]))
+}
+
+fn mp3_audio_hash(path: &path::Path) -> Result<String, model::Error> {
+    // Hash only what sits between a leading ID3v2 and a trailing ID3v1 tag,
+    // so editing the tags doesn't change the id
+    let mut file = fs::File::open(path)?;
+    let file_length = file.metadata()?.len();
+
+    let mut id3_header = [0u8; 10];
+    let start = match file.read_exact(&mut id3_header) {
+        Ok(()) => mpeg::id3v2_length(&id3_header) as u64,
+        Err(_) => 0,
+    };
+
+    let mut end = file_length;
+    if file_length >= 128 {
+        let mut id3v1_marker = [0u8; 3];
+        file.seek(io::SeekFrom::Start(file_length - 128))?;
+        file.read_exact(&mut id3v1_marker)?;
+        if &id3v1_marker == b"TAG" {
+            end = file_length - 128;
+        }
+    }
+
+    let mut hasher = Sha256::new();
+    file.seek(io::SeekFrom::Start(start.min(end)))?;
+    io::copy(&mut file.take(end.saturating_sub(start)), &mut hasher)?;
+    Ok(util::to_hex(&hasher.finalize()[..16]))
+}
+
+pub fn index_ids(library: &mut model::Library) {
+    for artist in library.artists.values_mut() {
+        for album in artist.albums.values_mut() {
+            album.id = album_id(album);
+
+            for disc in album.discs.values() {
+                for track in disc.tracks.values() {
+                    // Two copies of the very same file share an id; the first one wins
+                    library
+                        .track_ids
+                        .entry(track.id.clone())
+                        .or_insert(model::TrackKey {
+                            artist_name: artist.name.clone(),
+                            album_name: album.name.clone(),
+                            disc_number: disc.number,
+                            track_number: track.resolve_track_number(),
+                        });
+                }
+            }
+
+            library
+                .album_ids
+                .entry(album.id.clone())
+                .or_insert(model::AlbumKey {
+                    artist_name: artist.name.clone(),
+                    album_name: album.name.clone(),
+                });
+        }
+
+        artist.id = artist_id(artist);
+        library
+            .artist_ids
+            .entry(artist.id.clone())
+            .or_insert(artist.name.clone());
+    }
+}
+
+fn tags_track_id(track: &model::AudioFileTrackMetadata) -> model::TrackId {
+    // Survives renaming or moving the file, but not correcting its tags
+    let normalized = |value: &str| value.trim().to_lowercase();
+    let mut hasher = Sha256::new();
+    for value in [
+        normalized(track.album_artist.as_deref().unwrap_or(&track.artist)),
+        normalized(track.album.as_deref().unwrap_or_default()),
+        track.disc_no.map(|n| n.to_string()).unwrap_or_default(),
+        track.track.map(|n| n.to_string()).unwrap_or_default(),
+        normalized(&track.track_title),
+    ] {
+        hasher.update(value.as_bytes());
+        hasher.update(b"\n");
+    }
+    model::TrackId(format!("tags:{}", util::to_hex(&hasher.finalize()[..16])))
+}
+
+fn album_id(album: &model::Album) -> model::AlbumId {
+    let tracks = || album.discs.values().flat_map(|disc| disc.tracks.values());
+
+    // Use the MusicBrainz release only when every track agrees on it
+    let mut mbids = tracks().map(|track| track.musicbrainz.album_id.clone());
+    if let Some(Some(first)) = mbids.next() {
+        if mbids.all(|mbid| mbid.as_deref() == Some(first.as_str())) {
+            return model::AlbumId(format!("mb:{}", first));
+        }
+    }
+
+    // Otherwise the album is identified by what is on it, which survives
+    // renaming the album or its files but not adding or removing tracks
+    let mut track_ids: Vec<&str> = tracks().map(|track| track.id.0.as_str()).collect();
+    track_ids.sort();
+    let mut hasher = Sha256::new();
+    for track_id in track_ids.into_iter() {
+        hasher.update(track_id.as_bytes());
+        hasher.update(b"\n");
+    }
+    model::AlbumId(format!("tracks:{}", util::to_hex(&hasher.finalize()[..16])))
+}
+
+fn artist_id(artist: &model::Artist) -> model::ArtistId {
+    // Take the most common MusicBrainz album artist across the artist's tracks,
+    // which tolerates a few untagged files
+    let mut mbid_counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for album in artist.albums.values() {
+        for disc in album.discs.values() {
+            for track in disc.tracks.values() {
+                if let Some(ref mbid) = track.musicbrainz.album_artist_id {
+                    *mbid_counts.entry(mbid.as_str()).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+
+    match mbid_counts.into_iter().max_by_key(|(_, count)| *count) {
+        Some((mbid, _)) => model::ArtistId(format!("mb:{}", mbid)),
+        None => model::ArtistId(format!("name:{}", artist.name.trim().to_lowercase())),
+    }
+}
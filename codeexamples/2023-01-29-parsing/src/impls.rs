@@ -1,17 +1,440 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
 use std::path;
+use std::time;
 
-use crate::{model, organize, parse, scan};
+use crate::{
+    device, diff, duplicates, export, history, infer, integrity, loudness, manifest, model,
+    organize, parse, playlists, ratings, scrobbler, snapshot, util,
+};
 
 impl model::Library {
     pub fn from_library_directory<P: AsRef<path::Path>>(
         library_directory: P,
     ) -> Result<Self, model::Error> {
-        let audio_file_paths = scan::find_audio_files(&library_directory.as_ref().to_path_buf())?;
+        // Without anyone to tell, a file that can't be added fails the whole library
+        let mut first_failure = None;
+        let library = Self::from_library_directory_with_options(
+            library_directory,
+            &model::ScanOptions::default(),
+            &mut |event| {
+                if let model::ScanEvent::Failed(_, e) = event {
+                    first_failure.get_or_insert(e);
+                }
+            },
+        )?;
+        match first_failure {
+            Some(e) => Err(e),
+            None => Ok(library),
+        }
+    }
+
+    // Files that fail to parse or conflict with a track added before are reported
+    // as ScanEvent::Failed and left out; only an unreadable directory fails the library
+    pub fn from_library_directory_with_options<P: AsRef<path::Path>>(
+        library_directory: P,
+        scan_options: &model::ScanOptions,
+        events: &mut dyn FnMut(model::ScanEvent),
+    ) -> Result<Self, model::Error> {
+        let mut builder = model::LibraryBuilder::new();
+        let mut tracks =
+            model::TrackStream::from_library_directory(library_directory, scan_options, events);
+        while let Some(track) = tracks.next() {
+            let track = track?;
+            let full_path = track.full_path.clone();
+            if let Err(e) = builder.add_track(track) {
+                (tracks.events)(model::ScanEvent::Failed(full_path, e));
+            }
+        }
+
+        Ok(builder.finish())
+    }
+
+    pub fn from_json_snapshot<P: AsRef<path::Path>>(
+        snapshot_path: P,
+    ) -> Result<Self, model::Error> {
+        let tracks = snapshot::read_snapshot(snapshot_path.as_ref())?;
+
+        organize::organize_tracks(tracks)
+    }
+
+    pub fn fill_missing_replay_gain(
+        &mut self,
+        options: &model::LoudnessScanOptions,
+    ) -> Result<Vec<model::LoudnessScanResult>, model::Error> {
+        loudness::fill_missing_replay_gain(self, options)
+    }
+
+    pub fn to_json(&self) -> String {
+        export::library_to_json(self)
+    }
+
+    pub fn to_csv(&self) -> String {
+        export::library_to_csv(self)
+    }
+
+    pub fn sync_to_device<P: AsRef<path::Path>>(
+        &self,
+        target_directory: P,
+        options: &model::DeviceSyncOptions,
+    ) -> Result<model::DeviceSyncReport, model::Error> {
+        device::sync_to_device(self, target_directory.as_ref(), options)
+    }
+}
+
+impl<'a> model::TrackStream<'a> {
+    pub fn from_library_directory<P: AsRef<path::Path>>(
+        library_directory: P,
+        scan_options: &'a model::ScanOptions,
+        events: &'a mut dyn FnMut(model::ScanEvent),
+    ) -> Self {
+        parse::stream_tracks(library_directory.as_ref(), scan_options, events)
+    }
+}
+
+impl model::LibraryBuilder {
+    pub fn new() -> Self {
+        model::LibraryBuilder {
+            library: organize::new_library(),
+            needs_indexing: false,
+        }
+    }
+
+    // A track with the same album artist, album, disc and track number
+    // as one added before is refused, and the builder can carry on
+    pub fn add_track(&mut self, track: model::AudioFileTrackMetadata) -> Result<(), model::Error> {
+        organize::insert_track(&mut self.library, track)?;
+        self.needs_indexing = true;
+        Ok(())
+    }
+
+    // Added tracks show up under their artist and album right away,
+    // but genres, album summaries, credits and ids only as of the last index()
+    pub fn library(&self) -> &model::Library {
+        &self.library
+    }
+
+    // Goes over every track again, so call it once per batch rather than per track
+    pub fn index(&mut self) {
+        if self.needs_indexing {
+            organize::index_library(&mut self.library);
+            self.needs_indexing = false;
+        }
+    }
+
+    pub fn finish(mut self) -> model::Library {
+        self.index();
+        self.library
+    }
+}
 
-        let audio_file_track_metadata_entries = parse::parse_all_audio_files(audio_file_paths)?;
+impl Default for model::LibraryBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl model::DuplicateReport {
+    pub fn from_library_directory<P: AsRef<path::Path>>(
+        library_directory: P,
+        options: &model::DuplicateOptions,
+        events: &mut dyn FnMut(model::ScanEvent),
+    ) -> Result<Self, model::Error> {
+        // Duplicates would collide as conflicting tracks when organized into a Library,
+        // so they are looked for among the parsed tracks instead
+        let audio_file_track_metadata_entries = model::TrackStream::from_library_directory(
+            library_directory,
+            &options.scan_options,
+            events,
+        )
+        .collect::<Result<Vec<_>, _>>()?;
+
+        let groups =
+            duplicates::find_duplicates(audio_file_track_metadata_entries, options, events)?;
+
+        Ok(model::DuplicateReport { groups })
+    }
+}
+
+impl model::LibraryDiff {
+    pub fn between(old: &model::Library, new: &model::Library) -> Self {
+        diff::diff_libraries(old, new)
+    }
 
-        let library = organize::organize_tracks(audio_file_track_metadata_entries)?;
+    pub fn is_empty(&self) -> bool {
+        self.added_artists.is_empty()
+            && self.removed_artists.is_empty()
+            && self.added_albums.is_empty()
+            && self.removed_albums.is_empty()
+            && self.added_tracks.is_empty()
+            && self.removed_tracks.is_empty()
+            && self.changed_tracks.is_empty()
+    }
+}
+
+impl model::Manifest {
+    pub fn from_library_directory<P: AsRef<path::Path>>(
+        library_directory: P,
+        scan_options: &model::ScanOptions,
+    ) -> Result<Self, model::Error> {
+        manifest::build_manifest(library_directory.as_ref(), scan_options)
+    }
+
+    pub fn read_from_file<P: AsRef<path::Path>>(manifest_path: P) -> Result<Self, model::Error> {
+        manifest::read_manifest(manifest_path.as_ref())
+    }
+
+    pub fn write_to_file<P: AsRef<path::Path>>(&self, output_path: P) -> Result<(), model::Error> {
+        manifest::write_manifest(self, output_path.as_ref())
+    }
+
+    pub fn verify_library_directory<P: AsRef<path::Path>>(
+        &self,
+        library_directory: P,
+        scan_options: &model::ScanOptions,
+    ) -> Result<model::ManifestVerification, model::Error> {
+        manifest::verify_manifest(self, library_directory.as_ref(), scan_options)
+    }
+}
+
+impl model::ManifestVerification {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty() && self.corrupted.is_empty()
+    }
+}
+
+impl model::IntegrityReport {
+    pub fn from_library_directory<P: AsRef<path::Path>>(
+        library_directory: P,
+        options: &model::IntegrityOptions,
+        events: &mut dyn FnMut(model::ScanEvent),
+    ) -> Result<Self, model::Error> {
+        integrity::check_library(library_directory.as_ref(), options, events)
+    }
+}
+
+impl model::RatingsStore {
+    // A store that doesn't exist yet starts out empty, and is created by save
+    pub fn open<P: AsRef<path::Path>>(store_path: P) -> Result<Self, model::Error> {
+        ratings::open_store(store_path.as_ref())
+    }
+
+    pub fn save(&self) -> Result<(), model::Error> {
+        ratings::save_store(self)
+    }
+
+    pub fn rating(&self, track: &model::AudioFileTrackMetadata) -> Option<f64> {
+        match self.ratings.get(&track.id) {
+            Some(rating) => *rating,
+            None => track.rating,
+        }
+    }
+
+    pub fn is_favourite(&self, id: &model::TrackId) -> bool {
+        self.favourites.contains(id)
+    }
+
+    // Only changes the store in memory, and the file when write_to_files is set;
+    // the store itself is written by save
+    pub fn set_rating(
+        &mut self,
+        track: &model::AudioFileTrackMetadata,
+        rating: Option<f64>,
+    ) -> Result<(), model::Error> {
+        let rating = rating.map(|rating| rating.clamp(0.0, 1.0));
+        if self.write_to_files {
+            ratings::write_rating_to_file(track, rating)?;
+        }
+        self.ratings.insert(track.id.clone(), rating);
+        Ok(())
+    }
+
+    pub fn set_favourite(&mut self, id: &model::TrackId, favourite: bool) {
+        if favourite {
+            self.favourites.insert(id.clone());
+        } else {
+            self.favourites.remove(id);
+        }
+    }
+}
+
+impl model::ListeningHistory {
+    // A history that doesn't exist yet starts out empty, and the file is created by the first play
+    pub fn open<P: AsRef<path::Path>>(history_path: P) -> Result<Self, model::Error> {
+        history::open_history(history_path.as_ref())
+    }
+
+    // Written to the file straight away
+    pub fn record(&mut self, play: model::Play) -> Result<(), model::Error> {
+        history::append_play(self, play)
+    }
+
+    pub fn stats(&self, id: &model::TrackId) -> model::TrackPlayStats {
+        self.stats.get(id).cloned().unwrap_or_default()
+    }
+
+    pub fn all_stats(&self) -> &BTreeMap<model::TrackId, model::TrackPlayStats> {
+        &self.stats
+    }
+
+    // Plays already in the history are left out, so the same log can be imported again as it grows;
+    // utc_offset_minutes only applies to a log whose times are local
+    pub fn import_scrobbler_log(
+        &mut self,
+        log: &model::ScrobblerLog,
+        library: &model::Library,
+        utc_offset_minutes: i32,
+    ) -> Result<model::ScrobblerImport, model::Error> {
+        scrobbler::import_log(self, log, library, utc_offset_minutes)
+    }
+
+    // Plays started from `from` up to but not including `to`
+    pub fn plays_between(&self, from: time::SystemTime, to: time::SystemTime) -> Vec<&model::Play> {
+        self.plays
+            .iter()
+            .filter(|play| from <= play.started_at && play.started_at < to)
+            .collect()
+    }
+}
+
+impl model::ScrobblerLog {
+    pub fn from_history(history: &model::ListeningHistory, library: &model::Library) -> Self {
+        scrobbler::from_history(history, library)
+    }
+
+    pub fn read_from_file<P: AsRef<path::Path>>(log_path: P) -> Result<Self, model::Error> {
+        scrobbler::read_log(log_path.as_ref())
+    }
+
+    pub fn write_to_file<P: AsRef<path::Path>>(&self, output_path: P) -> Result<(), model::Error> {
+        scrobbler::write_log(self, output_path.as_ref())
+    }
+}
+
+impl model::PlayTracker {
+    pub fn new() -> Self {
+        model::PlayTracker::default()
+    }
+
+    // The player started on a track; gives back the play of the track it was on before, if any
+    pub fn started(
+        &mut self,
+        track: &model::AudioFileTrackMetadata,
+        started_at: time::SystemTime,
+    ) -> Option<model::Play> {
+        history::start_play(self, track, started_at)
+    }
+
+    // How long the player has played the track so far
+    pub fn progress(&mut self, played: time::Duration) {
+        if let Some((play, _)) = self.current.as_mut() {
+            play.played = played;
+        }
+    }
+
+    // The player got to the end of the track
+    pub fn ended(&mut self) -> Option<model::Play> {
+        history::end_play(self)
+    }
+
+    // The player stopped before the end, e.g. because the program is closing
+    pub fn stopped(&mut self) -> Option<model::Play> {
+        history::stop_play(self)
+    }
+}
+
+impl model::PlaylistRules {
+    pub fn parse(text: &str) -> Result<Self, model::Error> {
+        playlists::parse_rules(text)
+    }
+
+    pub fn read_from_file<P: AsRef<path::Path>>(rules_path: P) -> Result<Self, model::Error> {
+        playlists::parse_rules(&fs::read_to_string(rules_path)?)
+    }
+
+    // Worked out from the whole history each time, so they follow it as it grows
+    pub fn generate(
+        &self,
+        history: &model::ListeningHistory,
+        library: &model::Library,
+    ) -> Vec<model::ContextPlaylist> {
+        playlists::generate(self, history, library)
+    }
+}
+
+impl model::TrackField {
+    // As the field is called in a JSON export
+    pub fn name(&self) -> &'static str {
+        match self {
+            model::TrackField::Artist => "artist",
+            model::TrackField::Album => "album",
+            model::TrackField::DiscNumber => "disc_no",
+            model::TrackField::TrackNumber => "track",
+            model::TrackField::Title => "track_title",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            model::TrackField::Artist,
+            model::TrackField::Album,
+            model::TrackField::DiscNumber,
+            model::TrackField::TrackNumber,
+            model::TrackField::Title,
+        ]
+        .into_iter()
+        .find(|field| field.name() == name)
+    }
+}
+
+impl model::PathPattern {
+    pub fn parse(pattern: &str) -> Result<Self, model::Error> {
+        Ok(model::PathPattern {
+            components: infer::parse_pattern(pattern)?,
+        })
+    }
+}
+
+impl model::TagReaderRegistry {
+    pub fn empty() -> Self {
+        model::TagReaderRegistry {
+            readers: Vec::new(),
+        }
+    }
+
+    pub fn register<R: model::TagReader + 'static>(&mut self, reader: R) {
+        self.readers.push(Box::new(reader));
+    }
+
+    pub fn reader_for<P: AsRef<path::Path>>(&self, full_path: P) -> Option<&dyn model::TagReader> {
+        let extension = util::get_maybe_extension_string(full_path.as_ref())?;
+        self.readers
+            .iter()
+            .rev()
+            .find(|reader| {
+                reader
+                    .extensions()
+                    .iter()
+                    .any(|e| e.eq_ignore_ascii_case(&extension))
+            })
+            .map(|reader| reader.as_ref())
+    }
+
+    pub fn extensions(&self) -> Vec<String> {
+        let extensions: BTreeSet<String> = self
+            .readers
+            .iter()
+            .flat_map(|reader| reader.extensions().iter().map(|e| e.to_lowercase()))
+            .collect();
+        extensions.into_iter().collect()
+    }
+}
 
-        Ok(library)
+impl Default for model::TagReaderRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(model::FlacTagReader);
+        registry.register(model::Mp3TagReader);
+        registry
     }
 }
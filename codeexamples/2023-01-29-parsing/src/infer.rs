@@ -0,0 +1,201 @@
+use std::path;
+
+use crate::model;
+
+// Tried in order, so the disc directory isn't taken for the album
+const LIBRARY_LAYOUT_PATTERNS: [&str; 4] = [
+    "{artist}/{album}/Disc {disc}/{track}.{title}",
+    "{artist}/{album}/Disc {disc}/{title}",
+    "{artist}/{album}/{track}.{title}",
+    "{artist}/{album}/{title}",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatternPart {
+    Literal(String),
+    Field(model::TrackField),
+}
+
+pub fn parse_pattern(pattern: &str) -> Result<Vec<Vec<PatternPart>>, model::Error> {
+    let invalid = |reason| model::Error::InvalidPathPattern(pattern.to_string(), reason);
+
+    let mut components = Vec::new();
+    let mut seen_fields = Vec::new();
+    for component in pattern.split('/') {
+        if component.is_empty() {
+            return Err(invalid("a directory or file name in it is empty"));
+        }
+
+        let mut parts = Vec::new();
+        let mut rest = component;
+        while !rest.is_empty() {
+            match rest.find(['{', '}']) {
+                Some(0) if rest.starts_with('{') => {
+                    let end = rest.find('}').ok_or_else(|| invalid("a { is not closed"))?;
+                    let field =
+                        match &rest[1..end] {
+                            "artist" => model::TrackField::Artist,
+                            "album" => model::TrackField::Album,
+                            "disc" => model::TrackField::DiscNumber,
+                            "track" => model::TrackField::TrackNumber,
+                            "title" => model::TrackField::Title,
+                            _ => return Err(invalid(
+                                "placeholders are {artist}, {album}, {disc}, {track} and {title}",
+                            )),
+                        };
+                    if seen_fields.contains(&field) {
+                        return Err(invalid("a placeholder is used twice"));
+                    }
+                    seen_fields.push(field);
+                    parts.push(PatternPart::Field(field));
+                    rest = &rest[end + 1..];
+                }
+                Some(0) => return Err(invalid("a } has no { before it")),
+                Some(start) => {
+                    parts.push(PatternPart::Literal(rest[..start].to_string()));
+                    rest = &rest[start..];
+                }
+                None => {
+                    parts.push(PatternPart::Literal(rest.to_string()));
+                    rest = "";
+                }
+            }
+        }
+        components.push(parts);
+    }
+
+    if seen_fields.is_empty() {
+        return Err(invalid("it has no placeholders"));
+    }
+    Ok(components)
+}
+
+pub fn fill_from_path(track: &mut model::AudioFileTrackMetadata, inference: &model::PathInference) {
+    let library_layout: Vec<Vec<Vec<PatternPart>>>;
+    let patterns: Vec<&Vec<Vec<PatternPart>>> = match inference {
+        model::PathInference::LibraryLayout => {
+            library_layout = LIBRARY_LAYOUT_PATTERNS
+                .iter()
+                .filter_map(|pattern| parse_pattern(pattern).ok())
+                .collect();
+            library_layout.iter().collect()
+        }
+        model::PathInference::Pattern(pattern) => vec![&pattern.components],
+    };
+
+    let names = path_names(&track.full_path);
+    let captures = match patterns
+        .into_iter()
+        .find_map(|components| match_path(components, &names))
+    {
+        Some(captures) => captures,
+        None => return,
+    };
+
+    for (field, value) in captures.into_iter() {
+        let value = value.trim();
+        if value.is_empty() {
+            continue;
+        }
+        let filled = match field {
+            model::TrackField::Artist if track.artist.trim().is_empty() => {
+                track.artist = value.to_string();
+                true
+            }
+            model::TrackField::Album if track.album.is_none() => {
+                track.album = Some(value.to_string());
+                true
+            }
+            model::TrackField::DiscNumber if track.disc_no.is_none() => {
+                track.disc_no = value.parse().ok();
+                track.disc_no.is_some()
+            }
+            model::TrackField::TrackNumber if track.track.is_none() => {
+                track.track = value.parse().ok();
+                track.track.is_some()
+            }
+            model::TrackField::Title if track.track_title.trim().is_empty() => {
+                track.track_title = value.to_string();
+                true
+            }
+            _ => false,
+        };
+        if filled {
+            track.inferred_fields.push(field);
+        }
+    }
+    track.inferred_fields.sort();
+}
+
+fn path_names(full_path: &path::Path) -> Vec<String> {
+    // The directories the file is in, then its name without the extension
+    let mut names: Vec<String> = full_path
+        .parent()
+        .into_iter()
+        .flat_map(|parent| parent.components())
+        .filter_map(|component| match component {
+            path::Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect();
+    if let Some(stem) = full_path.file_stem() {
+        names.push(stem.to_string_lossy().to_string());
+    }
+    names
+}
+
+fn match_path(
+    components: &[Vec<PatternPart>],
+    names: &[String],
+) -> Option<Vec<(model::TrackField, String)>> {
+    // The pattern describes the end of the path, wherever the library itself is
+    let first = names.len().checked_sub(components.len())?;
+
+    let mut captures = Vec::new();
+    for (parts, name) in components.iter().zip(names[first..].iter()) {
+        if !match_parts(parts, name, &mut captures) {
+            return None;
+        }
+    }
+    Some(captures)
+}
+
+fn match_parts(
+    parts: &[PatternPart],
+    text: &str,
+    captures: &mut Vec<(model::TrackField, String)>,
+) -> bool {
+    match parts.split_first() {
+        None => text.is_empty(),
+        Some((PatternPart::Literal(literal), rest)) => {
+            let matches = text.len() >= literal.len()
+                && text.is_char_boundary(literal.len())
+                && text[..literal.len()].eq_ignore_ascii_case(literal);
+            matches && match_parts(rest, &text[literal.len()..], captures)
+        }
+        Some((PatternPart::Field(field), rest)) => {
+            let is_number = matches!(
+                field,
+                model::TrackField::DiscNumber | model::TrackField::TrackNumber
+            );
+            // Shortest first, so in "{artist} - {album}" a " - " in the album stays there
+            let ends = text
+                .char_indices()
+                .skip(1)
+                .map(|(end, _)| end)
+                .chain(std::iter::once(text.len()));
+            for end in ends {
+                let value = &text[..end];
+                if is_number && !value.bytes().all(|b| b.is_ascii_digit()) {
+                    break;
+                }
+                captures.push((*field, value.to_string()));
+                if match_parts(rest, &text[end..], captures) {
+                    return true;
+                }
+                captures.pop();
+            }
+            false
+        }
+    }
+}
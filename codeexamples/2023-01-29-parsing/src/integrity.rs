@@ -0,0 +1,214 @@
+use std::fs;
+use std::path;
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time;
+
+use md5::{Digest, Md5};
+
+use crate::{model, mpeg, scan, util};
+
+const CHECKED_EXTENSIONS: [&str; 2] = ["flac", "mp3"];
+
+// Feed the MD5 in chunks rather than sample by sample
+const HASH_BUFFER_BYTES: usize = 64 * 1024;
+
+// How far past the ID3v2 tag to look for the first MP3 frame
+const MAX_LEADING_JUNK_BYTES: usize = 16 * 1024;
+
+enum FileCheck {
+    Good,
+    GoodWithoutMd5,
+    Bad(Option<model::ErrorLocation>, String),
+}
+
+pub fn check_library(
+    library_directory: &path::Path,
+    options: &model::IntegrityOptions,
+    events: &mut dyn FnMut(model::ScanEvent),
+) -> Result<model::IntegrityReport, model::Error> {
+    let paths = scan::find_files(
+        library_directory,
+        &options.scan_options,
+        &CHECKED_EXTENSIONS,
+        events,
+    )?;
+    let total = paths.len();
+
+    let threads = options
+        .threads
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+        .clamp(1, total.max(1));
+
+    let mut report = model::IntegrityReport {
+        files_checked: 0,
+        bad_files: Vec::new(),
+        files_without_md5: Vec::new(),
+    };
+
+    // Workers take the next file off a shared queue and send back what they found,
+    // so progress is reported from this thread in the order files finish
+    let queue = Mutex::new(paths.into_iter());
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            let sender = sender.clone();
+            let queue = &queue;
+            scope.spawn(move || loop {
+                let next = queue.lock().unwrap().next();
+                let full_path = match next {
+                    Some(full_path) => full_path,
+                    None => break,
+                };
+                let check = check_file(&full_path);
+                if sender.send((full_path, check)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        for (full_path, check) in receiver.iter() {
+            report.files_checked += 1;
+            match check {
+                FileCheck::Good => (),
+                FileCheck::GoodWithoutMd5 => report.files_without_md5.push(full_path),
+                FileCheck::Bad(location, problem) => report.bad_files.push(model::BadFile {
+                    full_path,
+                    location,
+                    problem,
+                }),
+            }
+            events(model::ScanEvent::Progress(report.files_checked, total));
+        }
+    });
+
+    report
+        .bad_files
+        .sort_by(|a, b| a.full_path.cmp(&b.full_path));
+    report.files_without_md5.sort();
+
+    Ok(report)
+}
+
+fn check_file(full_path: &path::Path) -> FileCheck {
+    match util::get_maybe_extension_string(full_path).as_deref() {
+        Some("flac") => check_flac_file(full_path),
+        Some("mp3") => check_mp3_file(full_path),
+        _ => FileCheck::Good,
+    }
+}
+
+fn check_flac_file(full_path: &path::Path) -> FileCheck {
+    let mut reader = match claxon::FlacReader::open(full_path) {
+        Ok(reader) => reader,
+        Err(e) => return FileCheck::Bad(None, format!("could not open: {:?}", e)),
+    };
+    let streaminfo = reader.streaminfo();
+    let channels = streaminfo.channels as u64;
+    let sample_rate = streaminfo.sample_rate as u64;
+    let at_sample = |interleaved: u64| {
+        Some(model::ErrorLocation::Time(time::Duration::from_secs_f64(
+            (interleaved / channels) as f64 / sample_rate as f64,
+        )))
+    };
+
+    // The STREAMINFO MD5 covers every sample, interleaved, as little endian
+    // integers of just enough bytes for the bit depth
+    let bytes_per_sample = streaminfo.bits_per_sample.div_ceil(8) as usize;
+    let mut hasher = Md5::new();
+    let mut buffer = Vec::with_capacity(HASH_BUFFER_BYTES + 4);
+    let mut decoded: u64 = 0;
+
+    for sample in reader.samples() {
+        match sample {
+            Ok(sample) => {
+                buffer.extend_from_slice(&sample.to_le_bytes()[..bytes_per_sample]);
+                decoded += 1;
+                if buffer.len() >= HASH_BUFFER_BYTES {
+                    hasher.update(&buffer);
+                    buffer.clear();
+                }
+            }
+            Err(e) => {
+                return FileCheck::Bad(at_sample(decoded), format!("decoding failed: {:?}", e))
+            }
+        }
+    }
+    hasher.update(&buffer);
+
+    if let Some(expected) = streaminfo.samples {
+        if decoded / channels != expected {
+            return FileCheck::Bad(
+                at_sample(decoded),
+                format!(
+                    "audio ends after {} of {} samples",
+                    decoded / channels,
+                    expected
+                ),
+            );
+        }
+    }
+
+    if streaminfo.md5sum == [0; 16] {
+        return FileCheck::GoodWithoutMd5;
+    }
+    if hasher.finalize()[..] != streaminfo.md5sum {
+        return FileCheck::Bad(
+            None,
+            "decoded audio does not match the STREAMINFO MD5".to_string(),
+        );
+    }
+
+    FileCheck::Good
+}
+
+fn check_mp3_file(full_path: &path::Path) -> FileCheck {
+    let bytes = match fs::read(full_path) {
+        Ok(bytes) => bytes,
+        Err(e) => return FileCheck::Bad(None, format!("could not read: {:?}", e)),
+    };
+    let at_byte = |offset: usize| Some(model::ErrorLocation::ByteOffset(offset as u64));
+
+    let start = mpeg::id3v2_length(&bytes).min(bytes.len());
+    let end =
+        if bytes.len() >= start + 128 && &bytes[bytes.len() - 128..bytes.len() - 125] == b"TAG" {
+            bytes.len() - 128
+        } else {
+            bytes.len()
+        };
+
+    let first_frame = (start..end.min(start + MAX_LEADING_JUNK_BYTES))
+        .find(|i| mpeg::parse_frame_header(&bytes[*i..end]).is_some());
+    let mut offset = match first_frame {
+        Some(offset) => offset,
+        None => return FileCheck::Bad(at_byte(start), "no MPEG audio frame found".to_string()),
+    };
+
+    while offset < end {
+        let rest = &bytes[offset..end];
+        let header = match mpeg::parse_frame_header(rest) {
+            Some(header) => header,
+            None => {
+                // APE and Lyrics3 tags, or zero padding, may follow the last frame
+                if rest.starts_with(b"APETAGEX")
+                    || rest.starts_with(b"LYRICS")
+                    || rest.iter().all(|b| *b == 0)
+                {
+                    break;
+                }
+                return FileCheck::Bad(at_byte(offset), "lost frame sync".to_string());
+            }
+        };
+
+        if header.length < 4 || header.length > rest.len() {
+            return FileCheck::Bad(at_byte(offset), "truncated frame".to_string());
+        }
+        if mpeg::layer3_crc_matches(&rest[..header.length], &header) == Some(false) {
+            return FileCheck::Bad(at_byte(offset), "frame CRC mismatch".to_string());
+        }
+        offset += header.length;
+    }
+
+    FileCheck::Good
+}
@@ -1,6 +1,26 @@
+mod cue;
+mod device;
+mod diff;
+mod duplicates;
+mod exclude;
+mod export;
+mod fingerprint;
+mod history;
+mod ids;
 pub mod impls;
+mod infer;
+mod integrity;
+mod loudness;
+mod lyrics;
+mod manifest;
 pub mod model;
+mod mpeg;
 mod organize;
 mod parse;
+mod playlists;
+mod ratings;
 mod scan;
+mod scrobbler;
+mod snapshot;
+mod tagwrite;
 mod util;
@@ -0,0 +1,305 @@
+use std::f64::consts::PI;
+use std::path;
+
+use crate::{model, tagwrite, util};
+
+// ReplayGain 2.0 targets -18 LUFS rather than the -23 LUFS of EBU R128 broadcast
+const REFERENCE_LOUDNESS: f64 = -18.0;
+
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+
+// Gating blocks are 400ms long and start every 100ms,
+// so each block is made of four consecutive 100ms steps
+const STEPS_PER_BLOCK: usize = 4;
+
+pub fn fill_missing_replay_gain(
+    library: &mut model::Library,
+    options: &model::LoudnessScanOptions,
+) -> Result<Vec<model::LoudnessScanResult>, model::Error> {
+    let mut results = Vec::new();
+
+    for artist in library.artists.values_mut() {
+        for album in artist.albums.values_mut() {
+            let mut flac_tracks: Vec<&mut model::AudioFileTrackMetadata> = album
+                .discs
+                .values_mut()
+                .flat_map(|disc| disc.tracks.values_mut())
+                // Tracks cut from a single-file rip by a CUE sheet share one set of tags,
+                // so there is nowhere to keep per-track gains for them
+                .filter(|track| {
+                    util::get_maybe_extension_string(&track.full_path).as_deref() == Some("flac")
+                        && track.range.is_none()
+                })
+                .collect();
+
+            if flac_tracks
+                .iter()
+                .all(|track| track.replay_gain.is_complete())
+            {
+                continue;
+            }
+
+            // Album gain needs every track of the album, even ones already tagged
+            let measurements: Vec<Result<Measurement, model::Error>> = flac_tracks
+                .iter()
+                .map(|track| measure_flac_file(&track.full_path))
+                .collect();
+
+            // Without every track the album gain would be off, so it is left unset,
+            // but the tracks that could be measured still get their own
+            let album_measured = measurements.iter().all(|m| m.is_ok());
+            let album_blocks: Vec<f64> = measurements
+                .iter()
+                .flatten()
+                .flat_map(|m| m.block_energies.iter().cloned())
+                .collect();
+            let album_loudness = integrated_loudness(&album_blocks);
+            let album_peak = measurements
+                .iter()
+                .flatten()
+                .map(|m| m.peak)
+                .fold(0.0, f64::max);
+
+            for (track, measurement) in flac_tracks.iter_mut().zip(measurements) {
+                if track.replay_gain.is_complete() {
+                    continue;
+                }
+                let measurement = match measurement {
+                    Ok(measurement) => measurement,
+                    Err(e) => {
+                        results.push(model::LoudnessScanResult {
+                            full_path: track.full_path.clone(),
+                            integrated_loudness: None,
+                            replay_gain: track.replay_gain.clone(),
+                            written: false,
+                            error: Some(e),
+                        });
+                        continue;
+                    }
+                };
+
+                let track_loudness = integrated_loudness(&measurement.block_energies);
+
+                let mut tags_to_write = Vec::new();
+                let gain = &mut track.replay_gain;
+                if gain.track_gain.is_none() {
+                    gain.track_gain = track_loudness.map(|l| REFERENCE_LOUDNESS - l);
+                    if let Some(v) = gain.track_gain {
+                        tags_to_write.push(("REPLAYGAIN_TRACK_GAIN", format_gain(v)));
+                    }
+                }
+                if gain.track_peak.is_none() {
+                    gain.track_peak = Some(measurement.peak);
+                    tags_to_write.push(("REPLAYGAIN_TRACK_PEAK", format_peak(measurement.peak)));
+                }
+                if gain.album_gain.is_none() && album_measured {
+                    gain.album_gain = album_loudness.map(|l| REFERENCE_LOUDNESS - l);
+                    if let Some(v) = gain.album_gain {
+                        tags_to_write.push(("REPLAYGAIN_ALBUM_GAIN", format_gain(v)));
+                    }
+                }
+                if gain.album_peak.is_none() && album_measured {
+                    gain.album_peak = Some(album_peak);
+                    tags_to_write.push(("REPLAYGAIN_ALBUM_PEAK", format_peak(album_peak)));
+                }
+
+                let mut error = None;
+                let mut written = false;
+                if options.write_tags && !tags_to_write.is_empty() {
+                    match tagwrite::write_flac_tags(&track.full_path, &tags_to_write) {
+                        Ok(()) => written = true,
+                        Err(e) => error = Some(e),
+                    }
+                }
+
+                results.push(model::LoudnessScanResult {
+                    full_path: track.full_path.clone(),
+                    integrated_loudness: track_loudness,
+                    replay_gain: track.replay_gain.clone(),
+                    written,
+                    error,
+                });
+            }
+        }
+    }
+
+    Ok(results)
+}
+
+fn format_gain(gain: f64) -> String {
+    format!("{:.2} dB", gain)
+}
+
+fn format_peak(peak: f64) -> String {
+    format!("{:.6}", peak)
+}
+
+struct Measurement {
+    // Channel-weighted mean square of each 400ms gating block
+    block_energies: Vec<f64>,
+    peak: f64,
+}
+
+fn measure_flac_file(path: &path::Path) -> Result<Measurement, model::Error> {
+    let mut reader = claxon::FlacReader::open(path)?;
+    let streaminfo = reader.streaminfo();
+    let sample_rate = streaminfo.sample_rate as f64;
+    let channels = streaminfo.channels as usize;
+    let scale = (1i64 << (streaminfo.bits_per_sample - 1)) as f64;
+
+    let mut filters: Vec<KWeighting> = (0..channels)
+        .map(|_| KWeighting::new(sample_rate))
+        .collect();
+    let step_length = (streaminfo.sample_rate / 10).max(1) as usize;
+
+    let mut step_energies = Vec::new();
+    let mut step_sum = 0.0;
+    let mut step_count = 0;
+    let mut peak: f64 = 0.0;
+
+    for (index, sample) in reader.samples().enumerate() {
+        let channel = index % channels;
+        let value = sample? as f64 / scale;
+        peak = peak.max(value.abs());
+
+        let filtered = filters[channel].process(value);
+        step_sum += channel_weight(channel, channels) * filtered * filtered;
+
+        if channel == channels - 1 {
+            step_count += 1;
+            if step_count == step_length {
+                step_energies.push(step_sum / step_length as f64);
+                step_sum = 0.0;
+                step_count = 0;
+            }
+        }
+    }
+
+    let block_energies = step_energies
+        .windows(STEPS_PER_BLOCK)
+        .map(|steps| steps.iter().sum::<f64>() / STEPS_PER_BLOCK as f64)
+        .collect();
+
+    Ok(Measurement {
+        block_energies,
+        peak,
+    })
+}
+
+fn channel_weight(channel: usize, channels: usize) -> f64 {
+    // In FLAC's channel order: L R C Ls Rs for 5 channels, L R C LFE Ls Rs for 5.1 and up;
+    // surround channels count for a bit more, and BS.1770 leaves the LFE out
+    match (channels, channel) {
+        (5, 3 | 4) => 1.41,
+        (6.., 3) => 0.0,
+        (6.., 4 | 5) => 1.41,
+        _ => 1.0,
+    }
+}
+
+fn block_loudness(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+fn integrated_loudness(block_energies: &[f64]) -> Option<f64> {
+    let above_absolute: Vec<f64> = block_energies
+        .iter()
+        .cloned()
+        .filter(|e| *e > 0.0 && block_loudness(*e) > ABSOLUTE_GATE)
+        .collect();
+    if above_absolute.is_empty() {
+        return None;
+    }
+
+    let relative_gate =
+        block_loudness(above_absolute.iter().sum::<f64>() / above_absolute.len() as f64)
+            + RELATIVE_GATE;
+
+    let above_relative: Vec<f64> = above_absolute
+        .into_iter()
+        .filter(|e| block_loudness(*e) > relative_gate)
+        .collect();
+    if above_relative.is_empty() {
+        return None;
+    }
+
+    Some(block_loudness(
+        above_relative.iter().sum::<f64>() / above_relative.len() as f64,
+    ))
+}
+
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    x1: f64,
+    x2: f64,
+    y1: f64,
+    y2: f64,
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Biquad {
+            b,
+            a,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x1 + self.b[2] * self.x2
+            - self.a[1] * self.y1
+            - self.a[2] * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
+
+// The ITU-R BS.1770 K-weighting curve: a high shelf modelling the head,
+// followed by a high pass, with coefficients derived for any sample rate
+struct KWeighting {
+    shelf: Biquad,
+    high_pass: Biquad,
+}
+
+impl KWeighting {
+    fn new(sample_rate: f64) -> Self {
+        let f0 = 1681.974450955533;
+        let gain = 3.999843853973347;
+        let q = 0.7071752369554196;
+        let k = (PI * f0 / sample_rate).tan();
+        let vh = 10f64.powf(gain / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad::new(
+            [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+        let k = (PI * f0 / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad::new(
+            [1.0, -2.0, 1.0],
+            [1.0, 2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        KWeighting { shelf, high_pass }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        self.high_pass.process(self.shelf.process(x))
+    }
+}
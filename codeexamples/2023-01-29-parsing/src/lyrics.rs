@@ -0,0 +1,138 @@
+use std::fs;
+use std::path;
+use std::time;
+
+use crate::model;
+
+pub fn read_sidecar_lrc(
+    audio_file_path: &path::Path,
+) -> Result<Option<model::Lyrics>, model::Error> {
+    // "3.Title.flac" keeps its lyrics in "3.Title.lrc" in the same directory
+    let lrc_path = audio_file_path.with_extension("lrc");
+    if !lrc_path.is_file() {
+        return Ok(None);
+    }
+
+    let bytes = fs::read(&lrc_path)?;
+    Ok(parse_lyrics(&String::from_utf8_lossy(&bytes)))
+}
+
+pub fn prefer_synced(
+    embedded: Option<model::Lyrics>,
+    sidecar: Option<model::Lyrics>,
+) -> Option<model::Lyrics> {
+    // Time-synced lyrics win wherever they come from;
+    // between two plain texts, a sidecar file was put there on purpose
+    match (embedded, sidecar) {
+        (Some(e), _) if e.is_synced() => Some(e),
+        (_, Some(s)) => Some(s),
+        (e, None) => e,
+    }
+}
+
+pub fn from_timestamped_lines(lines: Vec<(time::Duration, String)>) -> Option<model::Lyrics> {
+    let mut lines: Vec<model::SyncedLyricLine> = lines
+        .into_iter()
+        .map(|(timestamp, text)| model::SyncedLyricLine {
+            timestamp,
+            text: text.trim().to_string(),
+        })
+        .collect();
+    if lines.is_empty() {
+        return None;
+    }
+    lines.sort_by_key(|line| line.timestamp);
+    Some(model::Lyrics::Synced(lines))
+}
+
+pub fn parse_lyrics(text: &str) -> Option<model::Lyrics> {
+    // Embedded lyrics tags often hold a whole LRC file,
+    // so anything with [mm:ss.xx] timestamps is treated as synced
+    if text.trim().is_empty() {
+        return None;
+    }
+
+    let mut offset_milliseconds: i64 = 0;
+    let mut timestamped = Vec::new();
+
+    for line in text.lines() {
+        let mut rest = line.trim();
+        let mut timestamps = Vec::new();
+
+        while rest.starts_with('[') {
+            let close = match rest.find(']') {
+                Some(i) => i,
+                None => break,
+            };
+            let inner = &rest[1..close];
+            match parse_timestamp(inner) {
+                Some(millis) => timestamps.push(millis),
+                None => {
+                    // Header tags like [ar:Artist] carry no lyrics, only [offset:+100] matters
+                    if let Some(value) = inner.strip_prefix("offset:") {
+                        offset_milliseconds = value.trim().parse::<i64>().unwrap_or(0);
+                    }
+                }
+            }
+            rest = &rest[close + 1..];
+        }
+
+        let text = strip_word_timestamps(rest);
+        for millis in timestamps.into_iter() {
+            timestamped.push((millis, text.clone()));
+        }
+    }
+
+    if timestamped.is_empty() {
+        return Some(model::Lyrics::Unsynced(text.trim().to_string()));
+    }
+
+    // A positive offset means the lyrics should show up earlier
+    from_timestamped_lines(
+        timestamped
+            .into_iter()
+            .map(|(millis, text)| {
+                let shifted = (millis as i64 - offset_milliseconds).max(0) as u64;
+                (time::Duration::from_millis(shifted), text)
+            })
+            .collect(),
+    )
+}
+
+fn parse_timestamp(value: &str) -> Option<u64> {
+    // mm:ss, mm:ss.xx or mm:ss.xxx
+    let (minutes, seconds) = value.split_once(':')?;
+    let minutes = minutes.trim().parse::<u64>().ok()?;
+    let (whole_seconds, fraction) = match seconds.split_once('.') {
+        Some((whole, fraction)) => (whole, fraction),
+        None => (seconds, ""),
+    };
+    let whole_seconds = whole_seconds.trim().parse::<u64>().ok()?;
+    let fraction_millis = match fraction.len() {
+        0 => 0,
+        1 => fraction.parse::<u64>().ok()? * 100,
+        2 => fraction.parse::<u64>().ok()? * 10,
+        _ => fraction.get(..3)?.parse::<u64>().ok()?,
+    };
+    Some((minutes * 60 + whole_seconds) * 1000 + fraction_millis)
+}
+
+fn strip_word_timestamps(text: &str) -> String {
+    // Enhanced LRC marks individual words with <mm:ss.xx>, which we don't highlight
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(open) = rest.find('<') {
+        match rest[open..].find('>') {
+            Some(close) if parse_timestamp(&rest[open + 1..open + close]).is_some() => {
+                result.push_str(&rest[..open]);
+                rest = &rest[open + close + 1..];
+            }
+            _ => {
+                result.push_str(&rest[..open + 1]);
+                rest = &rest[open + 1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result.trim().to_string()
+}
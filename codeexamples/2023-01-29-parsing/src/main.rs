@@ -1,23 +1,529 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::env;
+use std::ffi;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path;
+use std::process;
+use std::time;
 
-fn main() {
-    println!("Let's read some audio metadata");
-    let args: Vec<String> = env::args().collect();
+use simpleaudioparser::model;
 
-    println!("Let's build the library by parsing all of the files");
-    let library =
-        simpleaudioparser::model::Library::from_library_directory(args[1].clone()).unwrap();
+// Exit codes, so scripts can tell a bad command line from a bad library
+const EXIT_SUCCESS: u8 = 0;
+const EXIT_PARSE_FAILURE: u8 = 1;
+const EXIT_USAGE_ERROR: u8 = 2;
+const EXIT_VERIFICATION_FAILED: u8 = 3;
 
-    println!("Now let's print all of the tracks we found");
+const USAGE: &str = "\
+Usage: simpleaudiodemo [options] <command> [arguments]
+
+Commands:
+    tree                        Print every artist, album, disc and track
+    search <query>              Find tracks whose artist, album or title contains the query
+    show <artist> [album]       Show an artist's albums, or the tracks of one album
+    composer [name]             List the composers, or the works of one and where they are
+    stats                       Count artists, albums, tracks, genres and formats
+    verify                      Parse every file and list the ones that fail
+    verify-audio                Decode every file and list the ones with damaged audio
+    export --format json|csv    Write the whole library to stdout
+    diff <other>                Compare the library with another library directory,
+                                or with a snapshot written by export --format json
+    manifest <output>           Write a checksum manifest of every audio file and cover
+    check-manifest <manifest>   Check the library against a manifest written by manifest
+    sync-device <target>        Copy the selected music to a device, as Artist/Album/Disc N/,
+                                removing what an earlier sync copied and is no longer selected
+    playlists <history> [rules] List the most played tracks of each playlist rule,
+                                e.g. \"Weekend Evenings: days sat-sun; hours 18-24\"
+    scrobbler-export <history> <output>
+                                Write the listening history as an Audioscrobbler .scrobbler.log
+    scrobbler-import <history> <log>
+                                Add the plays of a .scrobbler.log, e.g. from a Rockbox player,
+                                to the listening history, leaving out plays it already has
+
+Options:
+    -l, --library <directory>   The library directory to read (default: the current directory)
+    -v, --verbose               Also list files skipped while scanning
+    --infer-from-path           Take what a file is not tagged with from where it is,
+                                as in Artist/Album/Disc N/N.Title.flac
+    --path-pattern <pattern>    The same, but by a pattern for the end of the path,
+                                e.g. \"{artist} - {album}/{track} {title}\"
+    --hash-audio                Identify tracks without a MusicBrainz id by their audio,
+                                so ids survive retagging, at the cost of reading every file
+    --utc-offset <offset>       For scrobbler-import, how far the player's clock was ahead of UTC
+                                when its log doesn't say, e.g. +01:00 (default: +00:00)
+    -h, --help                  Print this help
+
+Options for sync-device, which can be given more than once:
+    --artist <artist>           Select all albums of an artist
+    --album <artist> <album>    Select one album
+    --playlist <file>           Select the tracks of an .m3u playlist
+    --max-size <size>           Leave out what does not fit, e.g. 8G or 500M
+    --dry-run                   Only print how much would be added and removed
+
+Exit codes:
+    0   Success
+    1   The library could not be read, or some files failed to parse
+    2   The command line was not understood
+    3   check-manifest found missing, extra or corrupted files,
+        or verify-audio found damaged audio
+";
+
+enum Command {
+    Tree,
+    Search(String),
+    Show(String, Option<String>),
+    Composer(Option<String>),
+    Stats,
+    Verify,
+    VerifyAudio,
+    Export(ExportFormat),
+    Diff(path::PathBuf),
+    Manifest(path::PathBuf),
+    CheckManifest(path::PathBuf),
+    SyncDevice(path::PathBuf, model::DeviceSyncOptions),
+    // The listening history, and the rules file if not the default rules
+    Playlists(path::PathBuf, Option<path::PathBuf>),
+    // The listening history, the log, and for import the UTC offset in minutes
+    ScrobblerExport(path::PathBuf, path::PathBuf),
+    ScrobblerImport(path::PathBuf, path::PathBuf, i32),
+}
+
+enum ExportFormat {
+    Json,
+    Csv,
+}
+
+struct Arguments {
+    library_directory: path::PathBuf,
+    verbose: bool,
+    path_inference: Option<model::PathInference>,
+    hash_audio: bool,
+    command: Command,
+}
+
+enum ParsedArguments {
+    Run(Arguments),
+    Help,
+}
+
+fn main() -> process::ExitCode {
+    let arguments = match parse_arguments(env::args_os().skip(1).collect()) {
+        Ok(ParsedArguments::Run(arguments)) => arguments,
+        Ok(ParsedArguments::Help) => {
+            print!("{}", USAGE);
+            return process::ExitCode::from(EXIT_SUCCESS);
+        }
+        Err(message) => {
+            eprintln!("simpleaudiodemo: {}", message);
+            eprintln!("Run 'simpleaudiodemo --help' for usage.");
+            return process::ExitCode::from(EXIT_USAGE_ERROR);
+        }
+    };
+
+    process::ExitCode::from(run(arguments))
+}
+
+fn parse_arguments(raw: Vec<ffi::OsString>) -> Result<ParsedArguments, String> {
+    let mut library_directory = path::PathBuf::from(".");
+    let mut verbose = false;
+    let mut path_inference = None;
+    let mut hash_audio = false;
+    let mut export_format = None;
+    let mut sync_options = model::DeviceSyncOptions::default();
+    let mut sync_options_given = false;
+    let mut utc_offset = None;
+    let mut positional: Vec<ffi::OsString> = Vec::new();
+
+    let mut raw = raw.into_iter();
+    while let Some(argument) = raw.next() {
+        // Paths may not be valid UTF-8, so the original argument is kept around
+        let text = argument.to_string_lossy().to_string();
+        match text.as_str() {
+            "-h" | "--help" => return Ok(ParsedArguments::Help),
+            "-v" | "--verbose" => verbose = true,
+            "-l" | "--library" => match raw.next() {
+                Some(directory) => library_directory = path::PathBuf::from(directory),
+                None => return Err(format!("{} needs a directory", text)),
+            },
+            "--infer-from-path" => path_inference = Some(model::PathInference::LibraryLayout),
+            "--path-pattern" => match raw
+                .next()
+                .map(|p| model::PathPattern::parse(&p.to_string_lossy()))
+            {
+                Some(Ok(pattern)) => path_inference = Some(model::PathInference::Pattern(pattern)),
+                Some(Err(model::Error::InvalidPathPattern(_, reason))) => {
+                    return Err(format!("--path-pattern is not usable: {}", reason))
+                }
+                _ => return Err("--path-pattern needs a pattern".to_string()),
+            },
+            "--hash-audio" => hash_audio = true,
+            "--format" => match raw.next().map(|f| f.to_string_lossy().to_lowercase()) {
+                Some(ref f) if f == "json" => export_format = Some(ExportFormat::Json),
+                Some(ref f) if f == "csv" => export_format = Some(ExportFormat::Csv),
+                Some(f) => return Err(format!("unknown export format '{}'", f)),
+                None => return Err("--format needs json or csv".to_string()),
+            },
+            "--artist" => match raw.next() {
+                Some(artist) => {
+                    sync_options.selection.push(model::DeviceSelection::Artist(
+                        artist.to_string_lossy().to_string(),
+                    ));
+                    sync_options_given = true;
+                }
+                None => return Err("--artist needs an artist".to_string()),
+            },
+            "--album" => match (raw.next(), raw.next()) {
+                (Some(artist), Some(album)) => {
+                    sync_options
+                        .selection
+                        .push(model::DeviceSelection::Album(model::AlbumKey {
+                            artist_name: artist.to_string_lossy().to_string(),
+                            album_name: album.to_string_lossy().to_string(),
+                        }));
+                    sync_options_given = true;
+                }
+                _ => return Err("--album needs an artist and an album".to_string()),
+            },
+            "--playlist" => match raw.next() {
+                Some(playlist) => {
+                    sync_options
+                        .selection
+                        .push(model::DeviceSelection::Playlist(path::PathBuf::from(
+                            playlist,
+                        )));
+                    sync_options_given = true;
+                }
+                None => return Err("--playlist needs a file".to_string()),
+            },
+            "--max-size" => match raw.next().map(|s| parse_size(&s.to_string_lossy())) {
+                Some(Some(size)) => {
+                    sync_options.max_total_bytes = Some(size);
+                    sync_options_given = true;
+                }
+                _ => return Err("--max-size needs a size, e.g. 8G or 500M".to_string()),
+            },
+            "--utc-offset" => match raw.next().map(|s| parse_utc_offset(&s.to_string_lossy())) {
+                Some(Some(minutes)) => utc_offset = Some(minutes),
+                _ => return Err("--utc-offset needs an offset, e.g. +01:00".to_string()),
+            },
+            "--dry-run" => {
+                sync_options.dry_run = true;
+                sync_options_given = true;
+            }
+            t if t.starts_with('-') && t.len() > 1 => {
+                return Err(format!("unknown option '{}'", t))
+            }
+            _ => positional.push(argument),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let command_name = match positional.next() {
+        Some(name) => name.to_string_lossy().to_string(),
+        None => return Err("no command given".to_string()),
+    };
+    let command_paths: Vec<ffi::OsString> = positional.collect();
+    let command_arguments: Vec<String> = command_paths
+        .iter()
+        .map(|a| a.to_string_lossy().to_string())
+        .collect();
+
+    let expect_arguments = |min: usize, max: usize| {
+        if command_arguments.len() < min || command_arguments.len() > max {
+            Err(format!("wrong number of arguments for '{}'", command_name))
+        } else {
+            Ok(())
+        }
+    };
+
+    let command = match command_name.as_str() {
+        "tree" => expect_arguments(0, 0).map(|_| Command::Tree)?,
+        "search" => {
+            expect_arguments(1, 1)?;
+            Command::Search(command_arguments[0].clone())
+        }
+        "show" => {
+            expect_arguments(1, 2)?;
+            Command::Show(
+                command_arguments[0].clone(),
+                command_arguments.get(1).cloned(),
+            )
+        }
+        "composer" => {
+            expect_arguments(0, 1)?;
+            Command::Composer(command_arguments.first().cloned())
+        }
+        "stats" => expect_arguments(0, 0).map(|_| Command::Stats)?,
+        "verify" => expect_arguments(0, 0).map(|_| Command::Verify)?,
+        "verify-audio" => expect_arguments(0, 0).map(|_| Command::VerifyAudio)?,
+        "export" => {
+            expect_arguments(0, 0)?;
+            match export_format.take() {
+                Some(format) => Command::Export(format),
+                None => return Err("export needs --format json|csv".to_string()),
+            }
+        }
+        "diff" => {
+            expect_arguments(1, 1)?;
+            Command::Diff(path::PathBuf::from(&command_paths[0]))
+        }
+        "manifest" => {
+            expect_arguments(1, 1)?;
+            Command::Manifest(path::PathBuf::from(&command_paths[0]))
+        }
+        "check-manifest" => {
+            expect_arguments(1, 1)?;
+            Command::CheckManifest(path::PathBuf::from(&command_paths[0]))
+        }
+        "playlists" => {
+            expect_arguments(1, 2)?;
+            Command::Playlists(
+                path::PathBuf::from(&command_paths[0]),
+                command_paths.get(1).map(path::PathBuf::from),
+            )
+        }
+        "scrobbler-export" => {
+            expect_arguments(2, 2)?;
+            Command::ScrobblerExport(
+                path::PathBuf::from(&command_paths[0]),
+                path::PathBuf::from(&command_paths[1]),
+            )
+        }
+        "scrobbler-import" => {
+            expect_arguments(2, 2)?;
+            Command::ScrobblerImport(
+                path::PathBuf::from(&command_paths[0]),
+                path::PathBuf::from(&command_paths[1]),
+                utc_offset.take().unwrap_or(0),
+            )
+        }
+        "sync-device" => {
+            expect_arguments(1, 1)?;
+            if sync_options.selection.is_empty() {
+                return Err("sync-device needs --artist, --album or --playlist".to_string());
+            }
+            sync_options_given = false;
+            Command::SyncDevice(
+                path::PathBuf::from(&command_paths[0]),
+                std::mem::take(&mut sync_options),
+            )
+        }
+        other => return Err(format!("unknown command '{}'", other)),
+    };
+
+    if export_format.is_some() {
+        return Err("--format only applies to export".to_string());
+    }
+    if utc_offset.is_some() {
+        return Err("--utc-offset only applies to scrobbler-import".to_string());
+    }
+    if sync_options_given {
+        return Err(
+            "--artist, --album, --playlist, --max-size and --dry-run only apply to sync-device"
+                .to_string(),
+        );
+    }
+
+    Ok(ParsedArguments::Run(Arguments {
+        library_directory,
+        verbose,
+        path_inference,
+        hash_audio,
+        command,
+    }))
+}
+
+fn run(arguments: Arguments) -> u8 {
+    // Manifests and audio checks are about the files themselves, their tags don't matter
+    match arguments.command {
+        Command::VerifyAudio => {
+            return verify_audio(&arguments.library_directory, arguments.verbose)
+        }
+        Command::Manifest(ref output_path) => {
+            return write_manifest(&arguments.library_directory, output_path)
+        }
+        Command::CheckManifest(ref manifest_path) => {
+            return check_manifest(&arguments.library_directory, manifest_path)
+        }
+        _ => (),
+    }
+
+    let mut failed_count = 0;
+    let verbose = arguments.verbose;
+    let only_verify = matches!(arguments.command, Command::Verify);
+
+    let scan_options = model::ScanOptions {
+        path_inference: arguments.path_inference,
+        hash_audio: arguments.hash_audio,
+        ..model::ScanOptions::default()
+    };
+    let library = model::Library::from_library_directory_with_options(
+        &arguments.library_directory,
+        &scan_options,
+        &mut |event| match event {
+            model::ScanEvent::Skipped(path, reason) if verbose => {
+                eprintln!("skipped ({:?}): {}", reason, path.display())
+            }
+            model::ScanEvent::Failed(path, e) => {
+                failed_count += 1;
+                // verify reports failures on stdout, as its actual output
+                if only_verify {
+                    println!("FAILED {}: {:?}", path.display(), e);
+                } else {
+                    eprintln!("failed to parse {}: {:?}", path.display(), e);
+                }
+            }
+            model::ScanEvent::Parsed(ref path) if only_verify && verbose => {
+                println!("ok {}", path.display())
+            }
+            _ => (),
+        },
+    );
+
+    let library = match library {
+        Ok(library) => library,
+        Err(e) => {
+            eprintln!(
+                "simpleaudiodemo: could not read the library in {}: {:?}",
+                arguments.library_directory.display(),
+                e
+            );
+            return EXIT_PARSE_FAILURE;
+        }
+    };
+
+    match arguments.command {
+        Command::Tree => print_tree(&library),
+        Command::Search(ref query) => print_search(&library, query),
+        Command::Show(ref artist, ref album) => {
+            if !print_show(&library, artist, album.as_deref()) {
+                return EXIT_USAGE_ERROR;
+            }
+        }
+        Command::Composer(ref composer) => {
+            if !print_composer(&library, composer.as_deref()) {
+                return EXIT_USAGE_ERROR;
+            }
+        }
+        Command::Stats => print_stats(&library),
+        Command::Verify => println!("{} file(s) failed to parse", failed_count),
+        Command::Export(ExportFormat::Json) => print!("{}", library.to_json()),
+        Command::Export(ExportFormat::Csv) => print!("{}", library.to_csv()),
+        Command::VerifyAudio | Command::Manifest(_) | Command::CheckManifest(_) => (),
+        Command::SyncDevice(ref target_directory, ref options) => {
+            match library.sync_to_device(target_directory, options) {
+                Ok(report) => print_sync_report(&report, options.dry_run, verbose),
+                Err(e) => {
+                    eprintln!(
+                        "simpleaudiodemo: could not sync to {}: {:?}",
+                        target_directory.display(),
+                        e
+                    );
+                    return EXIT_PARSE_FAILURE;
+                }
+            }
+        }
+        Command::Playlists(ref history_path, ref rules_path) => {
+            let history = match open_history(history_path) {
+                Some(history) => history,
+                None => return EXIT_PARSE_FAILURE,
+            };
+            let rules = match rules_path {
+                Some(rules_path) => match model::PlaylistRules::read_from_file(rules_path) {
+                    Ok(rules) => rules,
+                    Err(e) => {
+                        eprintln!(
+                            "simpleaudiodemo: could not read {}: {:?}",
+                            rules_path.display(),
+                            e
+                        );
+                        return EXIT_PARSE_FAILURE;
+                    }
+                },
+                None => model::PlaylistRules::default(),
+            };
+            print_playlists(&library, &rules.generate(&history, &library));
+        }
+        Command::ScrobblerExport(ref history_path, ref output_path) => {
+            let history = match open_history(history_path) {
+                Some(history) => history,
+                None => return EXIT_PARSE_FAILURE,
+            };
+            let log = model::ScrobblerLog::from_history(&history, &library);
+            match log.write_to_file(output_path) {
+                Ok(()) => println!("{} plays written", log.entries.len()),
+                Err(e) => {
+                    eprintln!(
+                        "simpleaudiodemo: could not write {}: {:?}",
+                        output_path.display(),
+                        e
+                    );
+                    return EXIT_PARSE_FAILURE;
+                }
+            }
+        }
+        Command::ScrobblerImport(ref history_path, ref log_path, utc_offset_minutes) => {
+            let mut history = match open_history(history_path) {
+                Some(history) => history,
+                None => return EXIT_PARSE_FAILURE,
+            };
+            let import = model::ScrobblerLog::read_from_file(log_path)
+                .and_then(|log| history.import_scrobbler_log(&log, &library, utc_offset_minutes));
+            match import {
+                Ok(import) => print_scrobbler_import(&import),
+                Err(e) => {
+                    eprintln!(
+                        "simpleaudiodemo: could not import {}: {:?}",
+                        log_path.display(),
+                        e
+                    );
+                    return EXIT_PARSE_FAILURE;
+                }
+            }
+        }
+        Command::Diff(ref other_path) => {
+            // A snapshot is a file, anything else is another library directory
+            let other = if other_path.is_file() {
+                model::Library::from_json_snapshot(other_path)
+            } else {
+                model::Library::from_library_directory_with_options(
+                    other_path,
+                    &scan_options,
+                    &mut |_| {},
+                )
+            };
+            match other {
+                Ok(other) => print_diff(&model::LibraryDiff::between(&library, &other)),
+                Err(e) => {
+                    eprintln!(
+                        "simpleaudiodemo: could not read {}: {:?}",
+                        other_path.display(),
+                        e
+                    );
+                    return EXIT_PARSE_FAILURE;
+                }
+            }
+        }
+    }
+
+    if failed_count > 0 {
+        EXIT_PARSE_FAILURE
+    } else {
+        EXIT_SUCCESS
+    }
+}
+
+fn print_tree(library: &model::Library) {
     for artist in library.artists.values() {
-        println!("\tArtist: {}", artist.name);
+        println!("Artist: {}", artist.name);
         for album in artist.albums.values() {
-            println!("\t\tAlbum: {}", album.name);
+            println!("\tAlbum: {}", album.name);
             for disc in album.discs.values() {
-                println!("\t\t\tDisc: {}", disc.number);
+                println!("\t\tDisc: {}", disc.number);
                 for track in disc.tracks.values() {
                     println!(
-                        "\t\t\t\tTrack: {: >3} - {}",
+                        "\t\t\tTrack: {: >3} - {}",
                         track.resolve_track_number(),
                         track.track_title
                     );
@@ -26,3 +532,590 @@ fn main() {
         }
     }
 }
+
+fn print_search(library: &model::Library, query: &str) {
+    let query = query.to_lowercase();
+    for artist in library.artists.values() {
+        for album in artist.albums.values() {
+            for disc in album.discs.values() {
+                for track in disc.tracks.values() {
+                    let matches = [&track.artist, &artist.name, &album.name, &track.track_title]
+                        .into_iter()
+                        .chain(track.classical.composer.iter())
+                        .chain(track.classical.work.iter())
+                        .any(|field| field.to_lowercase().contains(&query));
+                    if matches {
+                        println!(
+                            "{} / {} / {}-{:0>2} {}",
+                            artist.name,
+                            album.name,
+                            disc.number,
+                            track.resolve_track_number(),
+                            track.track_title
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn print_show(library: &model::Library, artist_name: &str, album_name: Option<&str>) -> bool {
+    // Names are matched ignoring case, so the shell quoting is the only hard part
+    let artist = match library
+        .artists
+        .values()
+        .find(|a| a.name.eq_ignore_ascii_case(artist_name))
+    {
+        Some(artist) => artist,
+        None => {
+            eprintln!("simpleaudiodemo: no artist named '{}'", artist_name);
+            return false;
+        }
+    };
+
+    let album_name = match album_name {
+        Some(name) => name,
+        None => {
+            println!("{}", artist.name);
+            for album in artist.albums.values() {
+                println!(
+                    "\t{} ({}{} tracks, {})",
+                    album.name,
+                    album
+                        .date
+                        .as_ref()
+                        .map(|date| format!("{}, ", date))
+                        .unwrap_or_default(),
+                    album.track_count,
+                    format_duration(album.duration)
+                );
+            }
+            if !artist.appears_on.is_empty() {
+                println!("Appears on");
+                for appears_on in artist.appears_on.values() {
+                    println!(
+                        "\t{} by {} ({} tracks)",
+                        appears_on.album_name,
+                        appears_on.artist_name,
+                        appears_on.tracks.len()
+                    );
+                }
+            }
+            return true;
+        }
+    };
+
+    let album = match artist
+        .albums
+        .values()
+        .find(|a| a.name.eq_ignore_ascii_case(album_name))
+    {
+        Some(album) => album,
+        None => {
+            eprintln!(
+                "simpleaudiodemo: {} has no album named '{}'",
+                artist.name, album_name
+            );
+            return false;
+        }
+    };
+
+    println!("{} - {}", artist.name, album.name);
+    if let Some(ref date) = album.date {
+        println!("Date:    {}", date);
+    }
+    if !album.genres.is_empty() {
+        println!("Genres:  {}", album.genres.join(", "));
+    }
+    println!(
+        "Tracks:  {} ({})",
+        album.track_count,
+        format_duration(album.duration)
+    );
+    println!(
+        "Formats: {}",
+        album.formats.iter().cloned().collect::<Vec<_>>().join(", ")
+    );
+    if let Some(ref cover) = album.cover {
+        println!("Cover:   {}", cover.display());
+    }
+    for warning in album.warnings.iter() {
+        println!("Warning: {}", format_album_warning(warning));
+    }
+    for work in album.works.iter() {
+        println!("Work:    {}", format_work(work));
+    }
+    for disc in album.discs.values() {
+        println!("Disc {}", disc.number);
+        for track in disc.tracks.values() {
+            println!(
+                "\t{: >3}. {} [{}]",
+                track.resolve_track_number(),
+                track.track_title,
+                track
+                    .duration
+                    .map(format_duration)
+                    .unwrap_or_else(|| "?".to_string())
+            );
+            if track.artist != artist.name {
+                println!("\t     by {}", track.artist);
+            }
+            if let Some(rating) = track.rating {
+                println!("\t     rated {}/5", (rating * 5.0).round());
+            }
+            if let Some(ref work) = track.classical.work {
+                match track.classical.movement_number {
+                    Some(number) => println!("\t     movement {} of {}", number, work),
+                    None => println!("\t     from {}", work),
+                }
+            }
+            println!("\t     {}", track.full_path.display());
+            if !track.inferred_fields.is_empty() {
+                let names: Vec<&str> = track.inferred_fields.iter().map(|f| f.name()).collect();
+                println!("\t     inferred from the path: {}", names.join(", "));
+            }
+        }
+    }
+    true
+}
+
+fn print_composer(library: &model::Library, composer_name: Option<&str>) -> bool {
+    let composer_name = match composer_name {
+        Some(name) => name,
+        None => {
+            for composer in library.composers.values() {
+                let track_count: usize = composer.albums.values().map(|a| a.tracks.len()).sum();
+                println!(
+                    "{} ({} albums, {} tracks)",
+                    composer.name,
+                    composer.albums.len(),
+                    track_count
+                );
+            }
+            return true;
+        }
+    };
+
+    let composer = match library.composers.get(&composer_name.to_lowercase()) {
+        Some(composer) => composer,
+        None => {
+            eprintln!("simpleaudiodemo: no composer named '{}'", composer_name);
+            return false;
+        }
+    };
+
+    println!("{}", composer.name);
+    for composer_album in composer.albums.values() {
+        println!(
+            "\t{} by {} ({} tracks)",
+            composer_album.album_name,
+            composer_album.artist_name,
+            composer_album.tracks.len()
+        );
+        for work_name in composer_album.works.iter() {
+            println!("\t\t{}", work_name);
+        }
+    }
+    true
+}
+
+fn open_history(history_path: &path::Path) -> Option<model::ListeningHistory> {
+    match model::ListeningHistory::open(history_path) {
+        Ok(history) => Some(history),
+        Err(e) => {
+            eprintln!(
+                "simpleaudiodemo: could not read {}: {:?}",
+                history_path.display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+fn print_scrobbler_import(import: &model::ScrobblerImport) {
+    println!(
+        "{} plays imported, {} already in the history",
+        import.imported, import.already_recorded
+    );
+    if !import.unmatched.is_empty() {
+        println!(
+            "{} plays of tracks not in the library:",
+            import.unmatched.len()
+        );
+        for entry in import.unmatched.iter() {
+            match entry.album {
+                Some(ref album) => {
+                    println!("\t{} - {} ({})", entry.artist, entry.track_title, album)
+                }
+                None => println!("\t{} - {}", entry.artist, entry.track_title),
+            }
+        }
+    }
+}
+
+fn print_playlists(library: &model::Library, playlists: &[model::ContextPlaylist]) {
+    for playlist in playlists.iter() {
+        println!("{} ({} tracks)", playlist.name, playlist.tracks.len());
+        for (id, play_count) in playlist.tracks.iter() {
+            if let Some(track) = library.get_track(id) {
+                println!(
+                    "\t{:>4} plays  {} - {}",
+                    play_count, track.artist, track.track_title
+                );
+            }
+        }
+    }
+}
+
+fn print_stats(library: &model::Library) {
+    let mut album_count = 0;
+    let mut inconsistent_album_count = 0;
+    let mut track_count = 0;
+    let mut inferred_track_count = 0;
+    let mut total_duration = time::Duration::ZERO;
+    let mut total_bytes = 0;
+    let mut format_counts = BTreeMap::new();
+    let mut counted_files = BTreeSet::new();
+
+    for artist in library.artists.values() {
+        album_count += artist.albums.len();
+        for album in artist.albums.values() {
+            if !album.warnings.is_empty() {
+                inconsistent_album_count += 1;
+            }
+            for disc in album.discs.values() {
+                for track in disc.tracks.values() {
+                    track_count += 1;
+                    if !track.inferred_fields.is_empty() {
+                        inferred_track_count += 1;
+                    }
+                    total_duration += track.duration.unwrap_or_default();
+                    let format = track
+                        .full_path
+                        .extension()
+                        .map(|e| e.to_string_lossy().to_lowercase())
+                        .unwrap_or_default();
+                    *format_counts.entry(format).or_insert(0) += 1;
+                    // Tracks from one CUE sheet share a file, which only takes up space once
+                    if counted_files.insert(track.full_path.clone()) {
+                        total_bytes += fs::metadata(&track.full_path).map(|m| m.len()).unwrap_or(0);
+                    }
+                }
+            }
+        }
+    }
+
+    println!("Artists:  {}", library.artists.len());
+    println!("Albums:   {}", album_count);
+    if inconsistent_album_count > 0 {
+        println!(
+            "          {} with inconsistent tags, see show <artist> <album>",
+            inconsistent_album_count
+        );
+    }
+    println!("Tracks:   {}", track_count);
+    if inferred_track_count > 0 {
+        println!(
+            "          {} with fields inferred from their path",
+            inferred_track_count
+        );
+    }
+    println!("Genres:   {}", library.genres.len());
+    if !library.composers.is_empty() {
+        println!("Composers: {}", library.composers.len());
+    }
+    println!("Duration: {}", format_duration(total_duration));
+    println!(
+        "Size:     {:.1} MiB",
+        total_bytes as f64 / (1024.0 * 1024.0)
+    );
+    for (format, count) in format_counts.iter() {
+        println!("  {: <6}  {}", format, count);
+    }
+}
+
+fn write_manifest(library_directory: &path::Path, output_path: &path::Path) -> u8 {
+    let written =
+        model::Manifest::from_library_directory(library_directory, &model::ScanOptions::default())
+            .and_then(|manifest| {
+                manifest.write_to_file(output_path)?;
+                Ok(manifest.entries.len())
+            });
+
+    match written {
+        Ok(count) => {
+            println!("Wrote {} entries to {}", count, output_path.display());
+            EXIT_SUCCESS
+        }
+        Err(e) => {
+            eprintln!("simpleaudiodemo: could not write the manifest: {:?}", e);
+            EXIT_PARSE_FAILURE
+        }
+    }
+}
+
+fn check_manifest(library_directory: &path::Path, manifest_path: &path::Path) -> u8 {
+    let verification = model::Manifest::read_from_file(manifest_path).and_then(|manifest| {
+        manifest.verify_library_directory(library_directory, &model::ScanOptions::default())
+    });
+
+    let verification = match verification {
+        Ok(verification) => verification,
+        Err(e) => {
+            eprintln!("simpleaudiodemo: could not check the manifest: {:?}", e);
+            return EXIT_PARSE_FAILURE;
+        }
+    };
+
+    for relative_path in verification.missing.iter() {
+        println!("MISSING   {}", relative_path);
+    }
+    for relative_path in verification.extra.iter() {
+        println!("EXTRA     {}", relative_path);
+    }
+    for corrupted in verification.corrupted.iter() {
+        println!("CORRUPTED {}", corrupted.expected.relative_path);
+    }
+
+    if verification.is_ok() {
+        println!("All files match the manifest");
+        EXIT_SUCCESS
+    } else {
+        EXIT_VERIFICATION_FAILED
+    }
+}
+
+fn verify_audio(library_directory: &path::Path, verbose: bool) -> u8 {
+    // Progress overwrites itself on one line, which only makes sense on a terminal
+    let show_progress = io::stderr().is_terminal();
+
+    let report = model::IntegrityReport::from_library_directory(
+        library_directory,
+        &model::IntegrityOptions::default(),
+        &mut |event| match event {
+            model::ScanEvent::Skipped(path, reason) if verbose => {
+                eprintln!("skipped ({:?}): {}", reason, path.display())
+            }
+            model::ScanEvent::Progress(done, total) if show_progress => {
+                eprint!("\rChecked {}/{}", done, total);
+                if done == total {
+                    eprintln!();
+                }
+            }
+            _ => (),
+        },
+    );
+
+    let report = match report {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("simpleaudiodemo: could not check the library: {:?}", e);
+            return EXIT_PARSE_FAILURE;
+        }
+    };
+
+    for bad_file in report.bad_files.iter() {
+        let location = match bad_file.location {
+            Some(model::ErrorLocation::Time(at)) => format!(" at {:.3}s", at.as_secs_f64()),
+            Some(model::ErrorLocation::ByteOffset(offset)) => format!(" at byte {}", offset),
+            None => String::new(),
+        };
+        println!(
+            "BAD {}{}: {}",
+            bad_file.full_path.display(),
+            location,
+            bad_file.problem
+        );
+    }
+    if verbose {
+        for full_path in report.files_without_md5.iter() {
+            println!("NO MD5 {}", full_path.display());
+        }
+    }
+
+    println!(
+        "{} of {} file(s) have damaged audio",
+        report.bad_files.len(),
+        report.files_checked
+    );
+    if !report.files_without_md5.is_empty() {
+        println!(
+            "{} FLAC file(s) have no MD5 signature and were only decoded",
+            report.files_without_md5.len()
+        );
+    }
+
+    if report.bad_files.is_empty() {
+        EXIT_SUCCESS
+    } else {
+        EXIT_VERIFICATION_FAILED
+    }
+}
+
+fn print_diff(diff: &model::LibraryDiff) {
+    if diff.is_empty() {
+        println!("No differences");
+        return;
+    }
+
+    for artist in diff.removed_artists.iter() {
+        println!("- artist {}", artist);
+    }
+    for artist in diff.added_artists.iter() {
+        println!("+ artist {}", artist);
+    }
+    for album in diff.removed_albums.iter() {
+        println!("- album  {} / {}", album.artist_name, album.album_name);
+    }
+    for album in diff.added_albums.iter() {
+        println!("+ album  {} / {}", album.artist_name, album.album_name);
+    }
+    for track in diff.removed_tracks.iter() {
+        println!("- track  {}", format_track_key(track));
+    }
+    for track in diff.added_tracks.iter() {
+        println!("+ track  {}", format_track_key(track));
+    }
+    for change in diff.changed_tracks.iter() {
+        println!("~ track  {}", format_track_key(&change.new_key));
+        for field in change.fields.iter() {
+            println!(
+                "\t{}: {} -> {}",
+                field.field,
+                field.old_value.as_deref().unwrap_or("(none)"),
+                field.new_value.as_deref().unwrap_or("(none)")
+            );
+        }
+    }
+}
+
+fn print_sync_report(report: &model::DeviceSyncReport, dry_run: bool, verbose: bool) {
+    if verbose || dry_run {
+        for file in report.copied.iter() {
+            println!("COPY   {}", file.target_path.display());
+        }
+        for target_path in report.removed.iter() {
+            println!("REMOVE {}", target_path.display());
+        }
+    }
+    for entry in report.not_in_library.iter() {
+        println!("NOT IN LIBRARY {}", entry.display());
+    }
+    for key in report.left_out.iter() {
+        println!("DOES NOT FIT   {}", format_track_key(key));
+    }
+
+    let verb = if dry_run { "Would copy" } else { "Copied" };
+    println!(
+        "{} {} file(s) ({}), {} {} file(s) ({}), {} unchanged",
+        verb,
+        report.copied.len(),
+        format_size(report.bytes_to_add),
+        if dry_run { "would remove" } else { "removed" },
+        report.removed.len(),
+        format_size(report.bytes_to_remove),
+        report.unchanged
+    );
+    println!("{} on the device in total", format_size(report.total_bytes));
+}
+
+fn parse_size(text: &str) -> Option<u64> {
+    // Binary units, like the sizes a file manager shows
+    let text = text.trim().to_uppercase();
+    let text = text
+        .strip_suffix("IB")
+        .or_else(|| text.strip_suffix('B'))
+        .unwrap_or(&text);
+    let (number, multiplier) = match text.chars().last()? {
+        'K' => (&text[..text.len() - 1], 1u64 << 10),
+        'M' => (&text[..text.len() - 1], 1 << 20),
+        'G' => (&text[..text.len() - 1], 1 << 30),
+        'T' => (&text[..text.len() - 1], 1 << 40),
+        _ => (text, 1),
+    };
+    let number: f64 = number.trim().parse().ok()?;
+    if number.is_finite() && number >= 0.0 {
+        Some((number * multiplier as f64) as u64)
+    } else {
+        None
+    }
+}
+
+fn parse_utc_offset(text: &str) -> Option<i32> {
+    // +01:00, -05:30 or just +2
+    let text = text.trim();
+    let (sign, text) = match text.split_at(text.find(|c: char| c.is_ascii_digit())?) {
+        ("+", text) => (1, text),
+        ("-", text) => (-1, text),
+        _ => return None,
+    };
+    let (hours, minutes) = text.split_once(':').unwrap_or((text, "0"));
+    let hours = hours.parse::<i32>().ok().filter(|hours| *hours <= 14)?;
+    let minutes = minutes
+        .parse::<i32>()
+        .ok()
+        .filter(|minutes| *minutes < 60)?;
+    Some(sign * (hours * 60 + minutes))
+}
+
+fn format_size(bytes: u64) -> String {
+    format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+fn format_album_warning(warning: &model::AlbumWarning) -> String {
+    match warning {
+        model::AlbumWarning::DifferentDates(dates) => {
+            format!("tracks have different dates: {}", dates.join(", "))
+        }
+        model::AlbumWarning::DifferentGenres(genres) => {
+            format!("tracks have different genres: {}", genres.join(", "))
+        }
+        model::AlbumWarning::DifferentDiscTotals(totals) => format!(
+            "tracks have different disc totals: {}",
+            totals
+                .iter()
+                .map(|total| total.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        model::AlbumWarning::SplitAcrossAlbumArtists(artist_names) => format!(
+            "tracks in the same directory are also filed under {}",
+            artist_names.join(", ")
+        ),
+    }
+}
+
+fn format_work(work: &model::Work) -> String {
+    let tracks: Vec<String> = work
+        .tracks
+        .iter()
+        .map(|(disc, track)| format!("{}-{:0>2}", disc, track))
+        .collect();
+    match work.composer {
+        Some(ref composer) => format!("{} by {} ({})", work.name, composer, tracks.join(", ")),
+        None => format!("{} ({})", work.name, tracks.join(", ")),
+    }
+}
+
+fn format_track_key(key: &model::TrackKey) -> String {
+    format!(
+        "{} / {} / {}-{:0>2}",
+        key.artist_name, key.album_name, key.disc_number, key.track_number
+    )
+}
+
+fn format_duration(duration: time::Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path;
+
+use sha2::{Digest, Sha256};
+
+use crate::{model, scan, util};
+
+const HEADER: &str = "# simpleaudioparser manifest 1";
+
+pub fn build_manifest(
+    library_directory: &path::Path,
+    options: &model::ScanOptions,
+) -> Result<model::Manifest, model::Error> {
+    let mut entries = Vec::new();
+    for (relative_path, full_path) in manifest_files(library_directory, options)? {
+        entries.push(model::ManifestEntry {
+            relative_path,
+            size_bytes: fs::metadata(&full_path)?.len(),
+            sha256: hash_file(&full_path)?,
+        });
+    }
+
+    Ok(model::Manifest { entries })
+}
+
+pub fn verify_manifest(
+    manifest: &model::Manifest,
+    library_directory: &path::Path,
+    options: &model::ScanOptions,
+) -> Result<model::ManifestVerification, model::Error> {
+    let mut present = manifest_files(library_directory, options)?;
+
+    let mut missing = Vec::new();
+    let mut corrupted = Vec::new();
+    for expected in manifest.entries.iter() {
+        let full_path = match present.remove(&expected.relative_path) {
+            Some(full_path) => full_path,
+            None => {
+                missing.push(expected.relative_path.clone());
+                continue;
+            }
+        };
+
+        // Only hash when the sizes agree, a different size is already proof enough
+        let actual_size_bytes = fs::metadata(&full_path)?.len();
+        let actual_sha256 = if actual_size_bytes == expected.size_bytes {
+            Some(hash_file(&full_path)?)
+        } else {
+            None
+        };
+        if actual_sha256.as_ref() != Some(&expected.sha256) {
+            corrupted.push(model::CorruptedFile {
+                expected: expected.clone(),
+                actual_size_bytes,
+                actual_sha256,
+            });
+        }
+    }
+
+    Ok(model::ManifestVerification {
+        missing,
+        extra: present.into_keys().collect(),
+        corrupted,
+    })
+}
+
+pub fn write_manifest(
+    manifest: &model::Manifest,
+    output_path: &path::Path,
+) -> Result<(), model::Error> {
+    let mut file = io::BufWriter::new(fs::File::create(output_path)?);
+    writeln!(file, "{}", HEADER)?;
+    // Like sha256sum, but with the size in between, and the path last so it may hold spaces
+    for entry in manifest.entries.iter() {
+        writeln!(
+            file,
+            "{} {} {}",
+            entry.sha256, entry.size_bytes, entry.relative_path
+        )?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+pub fn read_manifest(manifest_path: &path::Path) -> Result<model::Manifest, model::Error> {
+    let file = io::BufReader::new(fs::File::open(manifest_path)?);
+
+    let mut entries = Vec::new();
+    for (index, line) in file.lines().enumerate() {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let invalid = || model::Error::InvalidManifest(manifest_path.to_path_buf(), index + 1);
+        let mut parts = line.splitn(3, ' ');
+        let sha256 = parts.next().ok_or_else(invalid)?;
+        let size_bytes = parts
+            .next()
+            .and_then(|s| s.parse::<u64>().ok())
+            .ok_or_else(invalid)?;
+        let relative_path = parts.next().ok_or_else(invalid)?;
+        if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+
+        entries.push(model::ManifestEntry {
+            relative_path: relative_path.to_string(),
+            size_bytes,
+            sha256: sha256.to_lowercase(),
+        });
+    }
+
+    Ok(model::Manifest { entries })
+}
+
+fn manifest_files(
+    library_directory: &path::Path,
+    options: &model::ScanOptions,
+) -> Result<BTreeMap<String, path::PathBuf>, model::Error> {
+    let audio_extensions = scan::audio_extensions(options);
+    let extensions: Vec<&str> = audio_extensions
+        .iter()
+        .map(|e| e.as_str())
+        .chain(scan::COVER_EXTENSIONS.iter().cloned())
+        .collect();
+    let full_paths = scan::find_files(library_directory, options, &extensions, &mut |_| {})?;
+
+    // Sorted by relative path, so manifests of identical copies are identical files
+    Ok(full_paths
+        .into_iter()
+        .filter_map(|full_path| {
+            let relative_path = full_path.strip_prefix(library_directory).ok()?;
+            Some((manifest_path_string(relative_path), full_path.clone()))
+        })
+        .collect())
+}
+
+fn manifest_path_string(relative_path: &path::Path) -> String {
+    // The same file must get the same string on every machine,
+    // so separators are always "/" and the odd invalid byte is escaped rather than dropped
+    let mut out = String::new();
+    for (index, component) in relative_path.components().enumerate() {
+        if index > 0 {
+            out.push('/');
+        }
+        let mut bytes = component.as_os_str().as_encoded_bytes();
+        while !bytes.is_empty() {
+            let valid = match std::str::from_utf8(bytes) {
+                Ok(valid) => valid,
+                Err(e) => std::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or_default(),
+            };
+            for c in valid.chars() {
+                match c {
+                    '\\' => out.push_str("\\\\"),
+                    '\n' => out.push_str("\\n"),
+                    c => out.push(c),
+                }
+            }
+            bytes = &bytes[valid.len()..];
+            if let Some((invalid, rest)) = bytes.split_first() {
+                out.push_str(&format!("\\x{:02x}", invalid));
+                bytes = rest;
+            }
+        }
+    }
+    out
+}
+
+fn hash_file(path: &path::Path) -> Result<String, model::Error> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(util::to_hex(&hasher.finalize()))
+}
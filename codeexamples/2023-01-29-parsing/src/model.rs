@@ -1,18 +1,43 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::io;
 use std::path;
+use std::time;
 
 use claxon;
 use id3;
+use minimp3;
+
+use crate::{infer, playlists, scan};
 
 #[derive(Debug)]
 pub enum Error {
     IO(io::Error),
     Claxon(claxon::Error),
     ID3(id3::Error),
-    MissingMetadataKey(String, &'static str),
-    ExpectedU32MetadataValue(String, &'static str),
-    ConflictingTrack(String, String, u32, u32, String),
+    MiniMP3(minimp3::Error),
+    MissingMetadataKey(path::PathBuf, &'static str),
+    ExpectedU32MetadataValue(path::PathBuf, &'static str),
+    ExpectedF64MetadataValue(path::PathBuf, &'static str),
+    // Album artist, album, disc and track number, then the files of both tracks
+    ConflictingTrack(String, String, u32, u32, path::PathBuf, path::PathBuf),
+    InvalidCueSheet(path::PathBuf, &'static str),
+    MalformedFlacMetadata(path::PathBuf, &'static str),
+    InvalidSnapshot(path::PathBuf, String),
+    // The manifest file and the line number that could not be read
+    InvalidManifest(path::PathBuf, usize),
+    // No registered TagReader handles the file's extension
+    UnsupportedFormat(path::PathBuf),
+    // For TagReaders outside this crate to report what went wrong in their own words
+    TagReader(path::PathBuf, String),
+    // The pattern as given, and what is wrong with it
+    InvalidPathPattern(String, &'static str),
+    InvalidRatingsStore(path::PathBuf, String),
+    // The history file and the line number that could not be read
+    InvalidListeningHistory(path::PathBuf, usize),
+    // The line number of the playlist rules, and what is wrong with it
+    InvalidPlaylistRules(usize, &'static str),
+    // The log file and the line number that could not be read
+    InvalidScrobblerLog(path::PathBuf, usize),
 }
 
 impl From<io::Error> for Error {
@@ -33,18 +58,301 @@ impl From<id3::Error> for Error {
     }
 }
 
+impl From<minimp3::Error> for Error {
+    fn from(e: minimp3::Error) -> Self {
+        Error::MiniMP3(e)
+    }
+}
+
+pub struct ScanOptions {
+    // .gitignore style patterns, relative to the library directory
+    pub exclude_patterns: Vec<String>,
+    // Also read patterns from a .musicignore file in any directory,
+    // which apply to that directory and everything below it
+    pub use_musicignore_files: bool,
+    pub follow_symlinks: bool,
+    pub skip_hidden: bool,
+    // How many directories deep to look below the library directory;
+    // e.g. 3 for Artist/Album/Disc N/ and None for no limit
+    pub max_depth: Option<usize>,
+    // Which audio files are picked up, and how their tags are read
+    pub tag_readers: TagReaderRegistry,
+    // Where to find what a file's tags leave out; None fails files without an artist or title
+    pub path_inference: Option<PathInference>,
+    // Identify files without a MusicBrainz id by a hash of their audio, which survives
+    // retagging but reads every file in full; otherwise the id comes from their tags
+    pub hash_audio: bool,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            // Syncthing's and desktop trash folders that are not already hidden
+            exclude_patterns: vec!["~syncthing~*.tmp".to_string(), "$RECYCLE.BIN/".to_string()],
+            use_musicignore_files: true,
+            follow_symlinks: false,
+            skip_hidden: true,
+            max_depth: None,
+            tag_readers: TagReaderRegistry::default(),
+            path_inference: None,
+            hash_audio: false,
+        }
+    }
+}
+
+// Fills in the artist, album, disc number, track number and title a file isn't tagged with
+// from the names of the file and the directories it is in
+pub enum PathInference {
+    // Artist/Album/Disc N/N.Title.flac, where the disc directory and the track number may be left out
+    LibraryLayout,
+    Pattern(PathPattern),
+}
+
+// Matched against the end of the path, without the file extension, e.g. "{artist} - {album}/{track} {title}";
+// {artist}, {album}, {disc}, {track} and {title} stand for a field,
+// anything else has to be there as written, ignoring ASCII case
+pub struct PathPattern {
+    pub(crate) components: Vec<Vec<infer::PatternPart>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TrackField {
+    Artist,
+    Album,
+    DiscNumber,
+    TrackNumber,
+    Title,
+}
+
+// Reads the tags of one kind of audio file into a track
+pub trait TagReader: Send + Sync {
+    // Lowercase file extensions without the dot, e.g. ["flac"]
+    fn extensions(&self) -> &[&str];
+
+    // Sidecar lyrics are merged in afterwards for every format,
+    // so this only needs to read what is in the file itself;
+    // an artist or title the file has no tag for is left empty, to be inferred or reported,
+    // and so is an id the file has nothing cheap to make one from
+    fn read(&self, full_path: &path::Path) -> Result<AudioFileTrackMetadata, Error>;
+}
+
+// The TagReaders to build a library with; the default one reads FLAC and MP3,
+// and registering a reader for an extension takes over from any registered before it
+pub struct TagReaderRegistry {
+    pub readers: Vec<Box<dyn TagReader>>,
+}
+
+// The readers behind the default registry, for wrapping or falling back to
+pub struct FlacTagReader;
+
+pub struct Mp3TagReader;
+
+// Reported to the caller while a library directory is scanned and parsed
+#[derive(Debug)]
+pub enum ScanEvent {
+    Found(path::PathBuf),
+    Skipped(path::PathBuf, SkipReason),
+    Parsed(path::PathBuf),
+    // The file is left out of the library, but the rest carries on
+    Failed(path::PathBuf, Error),
+    // How many of the found files have been processed so far, out of how many;
+    // while tracks are streamed the second number grows as more files are found
+    Progress(usize, usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    Hidden,
+    Symlink,
+    BrokenSymlink,
+    AlreadyScanned,
+    Excluded,
+    TooDeep,
+    UnknownExtension,
+    NoExtension,
+    // The file is a single-file rip whose tracks come from a CUE sheet next to it
+    CoveredByCueSheet,
+}
+
+// Yields the tracks of a library directory one by one, parsing each file only when asked for,
+// so a caller can show or store tracks before the whole directory was read
+pub struct TrackStream<'a> {
+    pub(crate) walker: scan::Walker<'a>,
+    pub(crate) options: &'a ScanOptions,
+    pub(crate) events: &'a mut dyn FnMut(ScanEvent),
+    pub(crate) pending_tracks: VecDeque<AudioFileTrackMetadata>,
+    pub(crate) pending_paths: VecDeque<path::PathBuf>,
+    pub(crate) covered_by_cue_sheets: BTreeSet<path::PathBuf>,
+    pub(crate) found: usize,
+    pub(crate) done: usize,
+    pub(crate) finished: bool,
+}
+
+// Organizes tracks into a Library as they are added, e.g. from a TrackStream
+pub struct LibraryBuilder {
+    pub(crate) library: Library,
+    // Whether tracks were added since genres, album summaries, credits and ids were last worked out
+    pub(crate) needs_indexing: bool,
+}
+
 pub struct Library {
     pub artists: BTreeMap<String, Artist>,
+    // Keyed by the lowercased genre name
+    pub genres: BTreeMap<String, Genre>,
+    // Keyed by the lowercased composer name
+    pub composers: BTreeMap<String, Composer>,
+    // Where to find each entity in `artists` by its stable id
+    pub artist_ids: BTreeMap<ArtistId, String>,
+    pub album_ids: BTreeMap<AlbumId, AlbumKey>,
+    pub track_ids: BTreeMap<TrackId, TrackKey>,
+}
+
+// Ids are prefixed with where they came from:
+// "mb:" for a MusicBrainz id, "audio:" for a hash of the audio itself,
+// "tags:" for a hash of a track's normalized tags,
+// "tracks:" for a hash of an album's track ids, "name:" for a normalized name
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ArtistId(pub String);
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AlbumId(pub String);
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TrackId(pub String);
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AlbumKey {
+    pub artist_name: String,
+    pub album_name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TrackKey {
+    pub artist_name: String,
+    pub album_name: String,
+    pub disc_number: u32,
+    pub track_number: u32,
+}
+
+impl Library {
+    pub fn get_artist(&self, id: &ArtistId) -> Option<&Artist> {
+        self.artist_ids
+            .get(id)
+            .and_then(|artist_name| self.artists.get(artist_name))
+    }
+
+    pub fn get_album(&self, id: &AlbumId) -> Option<&Album> {
+        self.album_ids.get(id).and_then(|key| {
+            self.artists
+                .get(&key.artist_name)?
+                .albums
+                .get(&key.album_name)
+        })
+    }
+
+    pub fn get_track(&self, id: &TrackId) -> Option<&AudioFileTrackMetadata> {
+        self.track_ids.get(id).and_then(|key| {
+            self.artists
+                .get(&key.artist_name)?
+                .albums
+                .get(&key.album_name)?
+                .discs
+                .get(&key.disc_number)?
+                .tracks
+                .get(&key.track_number)
+        })
+    }
 }
 
 pub struct Artist {
+    pub id: ArtistId,
     pub name: String,
+    // Empty for an artist who is only credited on other artists' albums
     pub albums: BTreeMap<String, Album>,
+    // Albums of other artists with tracks this artist is credited on,
+    // e.g. as a featured artist; keyed by (album artist, album) name
+    pub appears_on: BTreeMap<(String, String), AppearsOnAlbum>,
+}
+
+pub struct AppearsOnAlbum {
+    pub artist_name: String,
+    pub album_name: String,
+    // (disc, track) numbers of the album's tracks the artist is credited on
+    pub tracks: BTreeSet<(u32, u32)>,
 }
 
 pub struct Album {
+    pub id: AlbumId,
     pub name: String,
     pub discs: BTreeMap<u32, Disc>,
+    // The genre most of the album's tracks are tagged with
+    pub dominant_genre: Option<String>,
+    // Summed up from the tracks when the library is organized,
+    // the date being the one most of them are tagged with
+    pub date: Option<String>,
+    // Every genre of any of the tracks, in the first spelling seen
+    pub genres: Vec<String>,
+    pub track_count: usize,
+    // Of the tracks whose length is known
+    pub duration: time::Duration,
+    // Lowercase file extensions, e.g. "flac"
+    pub formats: BTreeSet<String>,
+    // An image next to the tracks, preferably named cover, folder or front
+    pub cover: Option<path::PathBuf>,
+    pub warnings: Vec<AlbumWarning>,
+    // The album's tracks grouped by the work they are part of, in album order;
+    // tracks without a work tag are in none of them
+    pub works: Vec<Work>,
+}
+
+pub struct Work {
+    pub name: String,
+    pub composer: Option<String>,
+    // (disc, track) numbers of the work's tracks, in album order
+    pub tracks: Vec<(u32, u32)>,
+}
+
+// Tags the tracks of one album should agree on, but don't;
+// each lists the distinct values found, leaving out tracks without the tag
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AlbumWarning {
+    DifferentDates(Vec<String>),
+    DifferentGenres(Vec<String>),
+    DifferentDiscTotals(Vec<u32>),
+    // Other album artists with an album of the same name from the same directory,
+    // usually a compilation whose tracks lack an album artist tag
+    SplitAcrossAlbumArtists(Vec<String>),
+}
+
+pub struct Genre {
+    pub name: String,
+    // Keyed by (album artist, album) name
+    pub albums: BTreeMap<(String, String), GenreAlbum>,
+}
+
+pub struct GenreAlbum {
+    pub artist_name: String,
+    pub album_name: String,
+    // (disc, track) numbers of the album's tracks tagged with this genre
+    pub tracks: BTreeSet<(u32, u32)>,
+    // Whether this is the album's dominant genre
+    // rather than one only some of its tracks have
+    pub dominant: bool,
+}
+
+pub struct Composer {
+    pub name: String,
+    // Keyed by (album artist, album) name
+    pub albums: BTreeMap<(String, String), ComposerAlbum>,
+}
+
+pub struct ComposerAlbum {
+    pub artist_name: String,
+    pub album_name: String,
+    // (disc, track) numbers of the album's tracks by this composer
+    pub tracks: BTreeSet<(u32, u32)>,
+    // Names of the album's works by this composer, in album order
+    pub works: Vec<String>,
 }
 
 pub struct Disc {
@@ -54,6 +362,8 @@ pub struct Disc {
 
 #[derive(Debug, Clone)]
 pub struct AudioFileTrackMetadata {
+    pub id: TrackId,
+    pub musicbrainz: MusicBrainzIds,
     pub artist: String,
     pub album_artist: Option<String>,
     pub album: Option<String>,
@@ -64,7 +374,96 @@ pub struct AudioFileTrackMetadata {
     pub track_title: String,
     pub genre: Option<String>,
     pub date: Option<String>,
+    pub duration: Option<time::Duration>,
+    pub replay_gain: ReplayGain,
+    pub classical: ClassicalTags,
+    // From 0.0 to 1.0, however the file's tags put it; see RatingsStore for ratings set here
+    pub rating: Option<f64>,
+    pub lyrics: Option<Lyrics>,
     pub full_path: path::PathBuf,
+    // Set when the track is only part of full_path, as described by a CUE sheet
+    pub range: Option<TrackRange>,
+    // The fields that were not tagged, but worked out from full_path
+    pub inferred_fields: Vec<TrackField>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrackRange {
+    pub start: time::Duration,
+    // None for the last track, which plays to the end of the file
+    pub end: Option<time::Duration>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MusicBrainzIds {
+    // The track on a specific release, unlike the recording id
+    // which is shared by every release the recording appears on
+    pub release_track_id: Option<String>,
+    pub album_id: Option<String>,
+    pub album_artist_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReplayGain {
+    // Gains are in dB relative to the ReplayGain reference level,
+    // peaks are the linear sample peak where 1.0 is full scale
+    pub track_gain: Option<f64>,
+    pub track_peak: Option<f64>,
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
+}
+
+// Mostly set for classical music, where the album artist alone says little
+#[derive(Debug, Clone, Default)]
+pub struct ClassicalTags {
+    // May hold several names, e.g. "Lennon; McCartney"
+    pub composer: Option<String>,
+    pub conductor: Option<String>,
+    // The orchestra, ensemble or soloists
+    pub performer: Option<String>,
+    // The larger piece the track is a movement of, e.g. "Symphony No. 5 in C minor, Op. 67"
+    pub work: Option<String>,
+    pub movement_name: Option<String>,
+    pub movement_number: Option<u32>,
+}
+
+impl ReplayGain {
+    pub fn is_complete(&self) -> bool {
+        self.track_gain.is_some()
+            && self.track_peak.is_some()
+            && self.album_gain.is_some()
+            && self.album_peak.is_some()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Lyrics {
+    Unsynced(String),
+    // Sorted by timestamp
+    Synced(Vec<SyncedLyricLine>),
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncedLyricLine {
+    pub timestamp: time::Duration,
+    pub text: String,
+}
+
+impl Lyrics {
+    pub fn is_synced(&self) -> bool {
+        match self {
+            Lyrics::Unsynced(_) => false,
+            Lyrics::Synced(_) => true,
+        }
+    }
+
+    pub fn current_line_index(&self, position: time::Duration) -> Option<usize> {
+        // The current line is the last one that has already started
+        match self {
+            Lyrics::Unsynced(_) => None,
+            Lyrics::Synced(lines) => lines.iter().rposition(|line| line.timestamp <= position),
+        }
+    }
 }
 
 impl AudioFileTrackMetadata {
@@ -96,6 +495,33 @@ impl AudioFileTrackMetadata {
         }
     }
 
+    pub fn resolve_genres(&self) -> Vec<String> {
+        // A genre tag may hold several genres, e.g. "Rock; Blues",
+        // or ID3v2.4's null separated values
+        match self.genre {
+            Some(ref v) => v
+                .split([';', '\0'])
+                .flat_map(|g| g.split(" / "))
+                .map(|g| g.trim().to_string())
+                .filter(|g| !g.is_empty())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn resolve_composers(&self) -> Vec<String> {
+        // Several composers are listed like several genres,
+        // but "/" is left alone as some names contain it
+        match self.classical.composer {
+            Some(ref v) => v
+                .split([';', '\0'])
+                .map(|c| c.trim().to_string())
+                .filter(|c| !c.is_empty())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
     pub fn resolve_track_number(&self) -> u32 {
         // If there is no track number assigned,
         // assume that it's a single with just one track
@@ -106,3 +532,317 @@ impl AudioFileTrackMetadata {
         }
     }
 }
+
+// Ratings and favourites kept in a file of their own, keyed by track id,
+// so they survive rescans and moved files without touching the audio files
+pub struct RatingsStore {
+    pub(crate) path: path::PathBuf,
+    // Win over the rating in a track's tags; None is a rating that was taken away
+    pub ratings: BTreeMap<TrackId, Option<f64>>,
+    pub favourites: BTreeSet<TrackId>,
+    // Also write ratings into the tags of the files as they are set, for other players to see
+    pub write_to_files: bool,
+}
+
+// Every play of every track, kept in a log file of its own that plays are added to as they end
+pub struct ListeningHistory {
+    pub(crate) path: path::PathBuf,
+    // In the order they were recorded
+    pub plays: Vec<Play>,
+    // Kept up to date as plays are recorded, rather than counted again on every look
+    pub(crate) stats: BTreeMap<TrackId, TrackPlayStats>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Play {
+    pub track_id: TrackId,
+    // Kept with the play, so it still says what was played after the file is gone
+    pub artist: String,
+    pub album: Option<String>,
+    pub track_title: String,
+    pub started_at: time::SystemTime,
+    // How long the track was listened to, leaving out pauses
+    pub played: time::Duration,
+    // Stopped before it had played for long enough to count as listened to
+    pub skipped: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackPlayStats {
+    // Plays that were not skipped
+    pub play_count: usize,
+    pub skip_count: usize,
+    pub last_played: Option<time::SystemTime>,
+}
+
+// Follows what the player reports about the track it is playing, and turns that into plays
+#[derive(Default)]
+pub struct PlayTracker {
+    // The play so far, and how long the track is
+    pub(crate) current: Option<(Play, Option<time::Duration>)>,
+}
+
+// An Audioscrobbler .scrobbler.log, as portable players like Rockbox keep of what they played
+pub struct ScrobblerLog {
+    // Rockbox writes local times and says the time zone is UNKNOWN, unless it was told it
+    pub utc: bool,
+    pub entries: Vec<ScrobblerEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScrobblerEntry {
+    pub artist: String,
+    pub album: Option<String>,
+    pub track_title: String,
+    pub track: Option<u32>,
+    // In whole seconds
+    pub duration: Option<time::Duration>,
+    // L in the log, or S when the track was skipped
+    pub listened: bool,
+    // When the play started
+    pub timestamp: time::SystemTime,
+    pub musicbrainz_track_id: Option<String>,
+}
+
+pub struct ScrobblerImport {
+    pub imported: usize,
+    // Plays that were in the history already, e.g. as the same log was imported before
+    pub already_recorded: usize,
+    // Entries no track of the library has the artist, album and title of
+    pub unmatched: Vec<ScrobblerEntry>,
+}
+
+// Playlists of the tracks most often listened to at certain times, one per line, e.g.
+//   utc-offset +01:00
+//   Weekend Evenings: days sat-sun; hours 18-24
+//   2010s Winter: seasons winter; years 2010s; limit 20
+pub struct PlaylistRules {
+    // The history is kept in UTC; days and hours are matched this far ahead of it
+    pub utc_offset_minutes: i32,
+    pub playlists: Vec<PlaylistRule>,
+}
+
+impl Default for PlaylistRules {
+    fn default() -> Self {
+        playlists::default_rules()
+    }
+}
+
+// An empty set matches any time
+pub struct PlaylistRule {
+    pub name: String,
+    pub days: BTreeSet<Weekday>,
+    // 0 to 23
+    pub hours: BTreeSet<u32>,
+    // 1 to 12, which seasons are turned into as they are in the northern hemisphere
+    pub months: BTreeSet<u32>,
+    // The years the plays were in, not the years the music is from
+    pub years: BTreeSet<i32>,
+    // How many tracks the playlist holds at most
+    pub limit: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+pub struct ContextPlaylist {
+    pub name: String,
+    // Most played first, with how often they were played at the times of the rule;
+    // only tracks that are in the library
+    pub tracks: Vec<(TrackId, usize)>,
+}
+
+pub struct DuplicateOptions {
+    pub scan_options: ScanOptions,
+    // How far apart two track lengths may be and still count as the same recording
+    pub duration_tolerance: time::Duration,
+    // Decode the candidates and compare their audio, not just their tags
+    pub compare_fingerprints: bool,
+    // Fraction (0.0 to 1.0) of the fingerprint that must agree for a match
+    pub fingerprint_threshold: f64,
+}
+
+impl Default for DuplicateOptions {
+    fn default() -> Self {
+        DuplicateOptions {
+            scan_options: ScanOptions::default(),
+            duration_tolerance: time::Duration::from_secs(2),
+            compare_fingerprints: false,
+            fingerprint_threshold: 0.85,
+        }
+    }
+}
+
+pub struct DuplicateReport {
+    pub groups: Vec<DuplicateGroup>,
+}
+
+pub struct DuplicateGroup {
+    pub artist: String,
+    pub track_title: String,
+    pub entries: Vec<DuplicateEntry>,
+}
+
+pub struct DuplicateEntry {
+    pub full_path: path::PathBuf,
+    pub format: String,
+    pub size_bytes: u64,
+    pub album: String,
+    pub duration: Option<time::Duration>,
+    // Only set when fingerprints were compared, and not for a file that could not be
+    // fingerprinted (reported as ScanEvent::Failed); the similarity against the first entry
+    // of the group that could
+    pub fingerprint_similarity: Option<f64>,
+}
+
+#[derive(Default)]
+pub struct LoudnessScanOptions {
+    // Also store the computed values as REPLAYGAIN_* tags in the scanned files
+    pub write_tags: bool,
+}
+
+pub struct LoudnessScanResult {
+    pub full_path: path::PathBuf,
+    // EBU R128 integrated loudness in LUFS; None for digital silence
+    pub integrated_loudness: Option<f64>,
+    pub replay_gain: ReplayGain,
+    pub written: bool,
+    // Why the file could not be measured or its tags not written; the scan carries on
+    // with the rest, leaving the album gain of an album with an unmeasured track unset
+    pub error: Option<Error>,
+}
+
+// What changed going from one library to another, e.g. from the main library to a device
+pub struct LibraryDiff {
+    pub added_artists: Vec<String>,
+    pub removed_artists: Vec<String>,
+    pub added_albums: Vec<AlbumKey>,
+    pub removed_albums: Vec<AlbumKey>,
+    pub added_tracks: Vec<TrackKey>,
+    pub removed_tracks: Vec<TrackKey>,
+    pub changed_tracks: Vec<TrackChange>,
+}
+
+pub struct TrackChange {
+    pub id: TrackId,
+    // Where the track is in the old and in the new library,
+    // which differ when e.g. the album name was edited
+    pub old_key: TrackKey,
+    pub new_key: TrackKey,
+    pub fields: Vec<FieldChange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+}
+
+// Every audio file and cover image below a library root, for checking that copies match
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    // Relative to the library root with "/" separators, as written in the manifest file;
+    // bytes that aren't valid UTF-8 are written as \xNN escapes
+    pub relative_path: String,
+    pub size_bytes: u64,
+    pub sha256: String,
+}
+
+pub struct ManifestVerification {
+    // In the manifest but not below the verified root
+    pub missing: Vec<String>,
+    // Below the verified root but not in the manifest
+    pub extra: Vec<String>,
+    pub corrupted: Vec<CorruptedFile>,
+}
+
+pub struct CorruptedFile {
+    pub expected: ManifestEntry,
+    pub actual_size_bytes: u64,
+    // Not computed when the sizes already differ
+    pub actual_sha256: Option<String>,
+}
+
+#[derive(Default)]
+pub struct IntegrityOptions {
+    pub scan_options: ScanOptions,
+    // How many files to check at once; None for one per CPU
+    pub threads: Option<usize>,
+}
+
+pub struct IntegrityReport {
+    pub files_checked: usize,
+    pub bad_files: Vec<BadFile>,
+    // FLACs whose encoder left the STREAMINFO MD5 empty,
+    // so only decoding errors could have been noticed
+    pub files_without_md5: Vec<path::PathBuf>,
+}
+
+#[derive(Debug)]
+pub struct BadFile {
+    pub full_path: path::PathBuf,
+    pub location: Option<ErrorLocation>,
+    pub problem: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorLocation {
+    // How far into the audio decoding went wrong
+    Time(time::Duration),
+    // Where in the file the broken data starts
+    ByteOffset(u64),
+}
+
+// What to copy to a portable device, and how much of it fits
+#[derive(Default)]
+pub struct DeviceSyncOptions {
+    // Taken in order, so when the size budget runs out the earlier selections win
+    pub selection: Vec<DeviceSelection>,
+    pub max_total_bytes: Option<u64>,
+    // Only work out what would change, without touching the target directory
+    pub dry_run: bool,
+}
+
+pub enum DeviceSelection {
+    Artist(String),
+    Album(AlbumKey),
+    // An .m3u or .m3u8 file listing audio files of the library
+    Playlist(path::PathBuf),
+}
+
+pub struct DeviceSyncReport {
+    // Copied to the device, or that would be for a dry run;
+    // files already there but changed in the library are copied again
+    pub copied: Vec<DeviceFile>,
+    // Relative to the target directory
+    pub removed: Vec<path::PathBuf>,
+    pub unchanged: usize,
+    // Selected tracks that did not fit in max_total_bytes
+    pub left_out: Vec<TrackKey>,
+    // Playlist entries that are not audio files of the library
+    pub not_in_library: Vec<path::PathBuf>,
+    pub bytes_to_add: u64,
+    pub bytes_to_remove: u64,
+    // How much the synced files take up on the device afterwards
+    pub total_bytes: u64,
+}
+
+pub struct DeviceFile {
+    pub source_path: path::PathBuf,
+    // Relative to the target directory
+    pub target_path: path::PathBuf,
+    pub size_bytes: u64,
+}
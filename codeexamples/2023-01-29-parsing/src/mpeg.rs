@@ -0,0 +1,225 @@
+use std::fs;
+use std::io::{self, Read, Seek};
+use std::path;
+use std::time;
+
+const MPEG1_LAYER1_BITRATES: [u32; 15] = [
+    0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+];
+const MPEG1_LAYER2_BITRATES: [u32; 15] = [
+    0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+];
+const MPEG1_LAYER3_BITRATES: [u32; 15] = [
+    0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+];
+const MPEG2_LAYER1_BITRATES: [u32; 15] = [
+    0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+];
+const MPEG2_LAYER2_AND_3_BITRATES: [u32; 15] =
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Version {
+    Mpeg1,
+    Mpeg2,
+    Mpeg25,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FrameHeader {
+    pub version: Version,
+    pub layer: u32,
+    pub crc_protected: bool,
+    pub bitrate_kbps: u32,
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub samples: u32,
+    // The whole frame in bytes, header included
+    pub length: usize,
+}
+
+pub fn parse_frame_header(bytes: &[u8]) -> Option<FrameHeader> {
+    if bytes.len() < 4 || bytes[0] != 0xFF || bytes[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+
+    let version = match (bytes[1] >> 3) & 0b11 {
+        0b00 => Version::Mpeg25,
+        0b10 => Version::Mpeg2,
+        0b11 => Version::Mpeg1,
+        _ => return None,
+    };
+    let layer = match (bytes[1] >> 1) & 0b11 {
+        0b01 => 3,
+        0b10 => 2,
+        0b11 => 1,
+        _ => return None,
+    };
+    let crc_protected = bytes[1] & 0b1 == 0;
+
+    // Free-format (index 0) and the reserved index 15 can't be walked frame by frame
+    let bitrate_index = (bytes[2] >> 4) as usize;
+    if bitrate_index == 0 || bitrate_index == 15 {
+        return None;
+    }
+    let bitrate_kbps = match (version, layer) {
+        (Version::Mpeg1, 1) => MPEG1_LAYER1_BITRATES[bitrate_index],
+        (Version::Mpeg1, 2) => MPEG1_LAYER2_BITRATES[bitrate_index],
+        (Version::Mpeg1, _) => MPEG1_LAYER3_BITRATES[bitrate_index],
+        (_, 1) => MPEG2_LAYER1_BITRATES[bitrate_index],
+        (_, _) => MPEG2_LAYER2_AND_3_BITRATES[bitrate_index],
+    };
+
+    let sample_rate_index = (bytes[2] >> 2) & 0b11;
+    let base_sample_rate = match sample_rate_index {
+        0 => 44_100,
+        1 => 48_000,
+        2 => 32_000,
+        _ => return None,
+    };
+    let sample_rate = match version {
+        Version::Mpeg1 => base_sample_rate,
+        Version::Mpeg2 => base_sample_rate / 2,
+        Version::Mpeg25 => base_sample_rate / 4,
+    };
+
+    let channels = if (bytes[3] >> 6) == 0b11 { 1 } else { 2 };
+
+    let samples = match (version, layer) {
+        (_, 1) => 384,
+        (Version::Mpeg1, _) => 1152,
+        (_, 2) => 1152,
+        (_, _) => 576,
+    };
+
+    let padding = ((bytes[2] >> 1) & 0b1) as usize;
+    let bitrate = bitrate_kbps as usize * 1000;
+    let length = match (version, layer) {
+        (_, 1) => (12 * bitrate / sample_rate as usize + padding) * 4,
+        (Version::Mpeg1, _) | (_, 2) => 144 * bitrate / sample_rate as usize + padding,
+        (_, _) => 72 * bitrate / sample_rate as usize + padding,
+    };
+
+    Some(FrameHeader {
+        version,
+        layer,
+        crc_protected,
+        bitrate_kbps,
+        sample_rate,
+        channels,
+        samples,
+        length,
+    })
+}
+
+pub fn id3v2_length(bytes: &[u8]) -> usize {
+    // An ID3v2 tag is "ID3", two version bytes, a flags byte,
+    // then a 4 byte syncsafe size which excludes the 10 byte header
+    // (and the 10 byte footer, when the footer flag is set)
+    if bytes.len() < 10 || &bytes[0..3] != b"ID3" {
+        return 0;
+    }
+    let size = bytes[6..10]
+        .iter()
+        .fold(0usize, |acc, b| (acc << 7) | (*b & 0x7F) as usize);
+    let footer = if bytes[5] & 0x10 != 0 { 10 } else { 0 };
+    10 + size + footer
+}
+
+pub fn estimate_duration(path: &path::Path) -> Result<Option<time::Duration>, io::Error> {
+    let mut file = fs::File::open(path)?;
+    let file_length = file.metadata()?.len();
+
+    let mut id3_header = [0u8; 10];
+    let id3_length = match file.read_exact(&mut id3_header) {
+        Ok(()) => id3v2_length(&id3_header) as u64,
+        Err(_) => return Ok(None),
+    };
+
+    // The first frame should sit right after the ID3v2 tag,
+    // but allow for a little junk before it
+    file.seek(io::SeekFrom::Start(id3_length))?;
+    let mut head = Vec::new();
+    file.take(16 * 1024).read_to_end(&mut head)?;
+
+    let (first_frame_offset, header) = match (0..head.len())
+        .find_map(|i| parse_frame_header(&head[i..]).map(|header| (i, header)))
+    {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let frame = &head[first_frame_offset..];
+
+    // VBR encoders leave the total frame count in a Xing/Info or VBRI header
+    // inside the first frame, which is exact; otherwise assume a constant bitrate
+    match vbr_frame_count(frame, &header) {
+        Some(frame_count) => Ok(Some(time::Duration::from_secs_f64(
+            frame_count as f64 * header.samples as f64 / header.sample_rate as f64,
+        ))),
+        None => {
+            let audio_bytes = file_length.saturating_sub(id3_length + first_frame_offset as u64);
+            Ok(Some(time::Duration::from_secs_f64(
+                audio_bytes as f64 * 8.0 / (header.bitrate_kbps as f64 * 1000.0),
+            )))
+        }
+    }
+}
+
+pub fn layer3_side_info_length(header: &FrameHeader) -> usize {
+    match (header.version, header.channels) {
+        (Version::Mpeg1, 1) => 17,
+        (Version::Mpeg1, _) => 32,
+        (_, 1) => 9,
+        (_, _) => 17,
+    }
+}
+
+pub fn layer3_crc_matches(frame: &[u8], header: &FrameHeader) -> Option<bool> {
+    // The CRC-16 after the header covers the last two header bytes and the side info;
+    // it is only defined this simply for Layer III, so other layers aren't checked
+    if !header.crc_protected || header.layer != 3 {
+        return None;
+    }
+    let side_info = frame.get(6..6 + layer3_side_info_length(header))?;
+    let stored = u16::from_be_bytes([frame[4], frame[5]]);
+
+    let mut crc: u16 = 0xFFFF;
+    for byte in frame[2..4].iter().chain(side_info.iter()) {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+    }
+    Some(crc == stored)
+}
+
+fn vbr_frame_count(frame: &[u8], header: &FrameHeader) -> Option<u32> {
+    let side_info_length = layer3_side_info_length(header);
+    let crc_length = if header.crc_protected { 2 } else { 0 };
+    let xing_offset = 4 + crc_length + side_info_length;
+
+    let read_u32 = |offset: usize| -> Option<u32> {
+        frame
+            .get(offset..offset + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    };
+
+    match frame.get(xing_offset..xing_offset + 4) {
+        Some(b"Xing") | Some(b"Info") => {
+            let flags = read_u32(xing_offset + 4)?;
+            if flags & 0x1 != 0 {
+                return read_u32(xing_offset + 8);
+            }
+        }
+        _ => (),
+    }
+
+    match frame.get(36..40) {
+        Some(b"VBRI") => read_u32(36 + 14),
+        _ => None,
+    }
+}
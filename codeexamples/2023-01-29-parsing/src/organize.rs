@@ -1,57 +1,517 @@
-use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path;
+use std::time;
 
-use crate::model;
+use crate::{ids, model, scan, util};
+
+// Words introducing guest artists, in an artist tag or a track title
+const FEATURING_WORDS: [&str; 4] = ["featuring ", "feat. ", "feat ", "ft. "];
 
 pub fn organize_tracks(
     tracks: Vec<model::AudioFileTrackMetadata>,
 ) -> Result<model::Library, model::Error> {
-    let mut library = model::Library {
+    let mut library = new_library();
+    for track in tracks.into_iter() {
+        insert_track(&mut library, track)?;
+    }
+    index_library(&mut library);
+
+    Ok(library)
+}
+
+pub fn new_library() -> model::Library {
+    model::Library {
         artists: BTreeMap::new(),
-    };
+        genres: BTreeMap::new(),
+        composers: BTreeMap::new(),
+        artist_ids: BTreeMap::new(),
+        album_ids: BTreeMap::new(),
+        track_ids: BTreeMap::new(),
+    }
+}
 
-    for track in tracks.into_iter() {
-        let artist_entry = library
+pub fn insert_track(
+    library: &mut model::Library,
+    track: model::AudioFileTrackMetadata,
+) -> Result<(), model::Error> {
+    let artist_entry = library
+        .artists
+        .entry(track.resolve_album_artist())
+        .or_insert(model::Artist {
+            // Artist and album ids depend on all of their tracks,
+            // so they are filled in by ids::index_ids at the end
+            id: model::ArtistId(String::new()),
+            name: track.resolve_album_artist(),
+            albums: BTreeMap::new(),
+            appears_on: BTreeMap::new(),
+        });
+
+    let album_entry = artist_entry
+        .albums
+        .entry(track.resolve_album())
+        .or_insert(model::Album {
+            id: model::AlbumId(String::new()),
+            name: track.resolve_album(),
+            discs: BTreeMap::new(),
+            dominant_genre: None,
+            date: None,
+            genres: Vec::new(),
+            track_count: 0,
+            duration: time::Duration::ZERO,
+            formats: BTreeSet::new(),
+            cover: None,
+            warnings: Vec::new(),
+            works: Vec::new(),
+        });
+
+    let disc_entry = album_entry
+        .discs
+        .entry(track.resolve_disc_number())
+        .or_insert(model::Disc {
+            number: track.resolve_disc_number(),
+            tracks: BTreeMap::new(),
+        });
+
+    // The track already there is kept, so a builder can carry on past the conflict
+    match disc_entry.tracks.entry(track.resolve_track_number()) {
+        Entry::Occupied(existing) => {
+            let c = existing.get();
+            Err(model::Error::ConflictingTrack(
+                c.resolve_album_artist(),
+                c.resolve_album(),
+                c.resolve_disc_number(),
+                c.resolve_track_number(),
+                c.full_path.clone(),
+                track.full_path,
+            ))
+        }
+        Entry::Vacant(vacant) => {
+            vacant.insert(track);
+            Ok(())
+        }
+    }
+}
+
+pub fn index_library(library: &mut model::Library) {
+    // Everything worked out from the tracks is started over,
+    // so this can run again after more tracks were inserted
+    library.genres.clear();
+    library.composers.clear();
+    library.artist_ids.clear();
+    library.album_ids.clear();
+    library.track_ids.clear();
+    // Artists that are only credited on tracks come back through index_track_artists
+    library
+        .artists
+        .retain(|_, artist| !artist.albums.is_empty());
+    for artist in library.artists.values_mut() {
+        artist.appears_on.clear();
+        for album in artist.albums.values_mut() {
+            album.dominant_genre = None;
+        }
+    }
+
+    index_genres(library);
+    index_composers(library);
+    summarize_albums(library);
+    index_track_artists(library);
+    ids::index_ids(library);
+}
+
+fn index_genres(library: &mut model::Library) {
+    for artist in library.artists.values_mut() {
+        for album in artist.albums.values_mut() {
+            // Count how many tracks carry each genre, keeping the first spelling seen
+            let mut genre_track_counts: BTreeMap<String, (String, usize)> = BTreeMap::new();
+
+            for disc in album.discs.values() {
+                for track in disc.tracks.values() {
+                    for genre_name in track.resolve_genres().into_iter() {
+                        let genre_key = genre_name.to_lowercase();
+
+                        genre_track_counts
+                            .entry(genre_key.clone())
+                            .or_insert((genre_name.clone(), 0))
+                            .1 += 1;
+
+                        library
+                            .genres
+                            .entry(genre_key)
+                            .or_insert(model::Genre {
+                                name: genre_name,
+                                albums: BTreeMap::new(),
+                            })
+                            .albums
+                            .entry((artist.name.clone(), album.name.clone()))
+                            .or_insert(model::GenreAlbum {
+                                artist_name: artist.name.clone(),
+                                album_name: album.name.clone(),
+                                tracks: BTreeSet::new(),
+                                dominant: false,
+                            })
+                            .tracks
+                            .insert((disc.number, track.resolve_track_number()));
+                    }
+                }
+            }
+
+            // Ties go to the alphabetically first genre so the result is stable
+            let dominant = genre_track_counts.into_iter().max_by(
+                |(a_key, (_, a_count)), (b_key, (_, b_count))| {
+                    a_count.cmp(b_count).then(b_key.cmp(a_key))
+                },
+            );
+
+            if let Some((dominant_key, (dominant_name, _))) = dominant {
+                album.dominant_genre = Some(dominant_name);
+                if let Some(genre_album) = library.genres.get_mut(&dominant_key).and_then(|genre| {
+                    genre
+                        .albums
+                        .get_mut(&(artist.name.clone(), album.name.clone()))
+                }) {
+                    genre_album.dominant = true;
+                }
+            }
+        }
+    }
+}
+
+fn index_composers(library: &mut model::Library) {
+    for artist in library.artists.values() {
+        for album in artist.albums.values() {
+            for disc in album.discs.values() {
+                for track in disc.tracks.values() {
+                    for composer_name in track.resolve_composers().into_iter() {
+                        let composer_album = library
+                            .composers
+                            .entry(composer_name.to_lowercase())
+                            .or_insert(model::Composer {
+                                name: composer_name,
+                                albums: BTreeMap::new(),
+                            })
+                            .albums
+                            .entry((artist.name.clone(), album.name.clone()))
+                            .or_insert(model::ComposerAlbum {
+                                artist_name: artist.name.clone(),
+                                album_name: album.name.clone(),
+                                tracks: BTreeSet::new(),
+                                works: Vec::new(),
+                            });
+                        composer_album
+                            .tracks
+                            .insert((disc.number, track.resolve_track_number()));
+                        if let Some(work_name) = work_name(track) {
+                            let seen = composer_album
+                                .works
+                                .iter()
+                                .any(|name| name.to_lowercase() == work_name.to_lowercase());
+                            if !seen {
+                                composer_album.works.push(work_name.to_string());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn group_works(album: &model::Album) -> Vec<model::Work> {
+    let mut works: Vec<model::Work> = Vec::new();
+    for disc in album.discs.values() {
+        for track in disc.tracks.values() {
+            let name = match work_name(track) {
+                Some(name) => name,
+                None => continue,
+            };
+            let composer = track
+                .classical
+                .composer
+                .as_deref()
+                .map(|composer| composer.trim())
+                .filter(|composer| !composer.is_empty());
+
+            // The same name by different composers is a different work,
+            // but a track that lacks the composer joins the work it names
+            let same_work = works.iter_mut().find(|work| {
+                work.name.to_lowercase() == name.to_lowercase()
+                    && match (&work.composer, composer) {
+                        (Some(a), Some(b)) => a.to_lowercase() == b.to_lowercase(),
+                        _ => true,
+                    }
+            });
+            let track_number = (disc.number, track.resolve_track_number());
+            match same_work {
+                Some(work) => {
+                    if work.composer.is_none() {
+                        work.composer = composer.map(|composer| composer.to_string());
+                    }
+                    work.tracks.push(track_number);
+                }
+                None => works.push(model::Work {
+                    name: name.to_string(),
+                    composer: composer.map(|composer| composer.to_string()),
+                    tracks: vec![track_number],
+                }),
+            }
+        }
+    }
+    works
+}
+
+fn work_name(track: &model::AudioFileTrackMetadata) -> Option<&str> {
+    track
+        .classical
+        .work
+        .as_deref()
+        .map(|work| work.trim())
+        .filter(|work| !work.is_empty())
+}
+
+fn summarize_albums(library: &mut model::Library) {
+    // Which album artists each (album, directory) ended up under, to spot split albums
+    let mut album_artists_by_directory: BTreeMap<(String, path::PathBuf), BTreeSet<String>> =
+        BTreeMap::new();
+
+    for artist in library.artists.values_mut() {
+        for album in artist.albums.values_mut() {
+            let tracks: Vec<&model::AudioFileTrackMetadata> = album
+                .discs
+                .values()
+                .flat_map(|disc| disc.tracks.values())
+                .collect();
+
+            album.date = most_common(tracks.iter().filter_map(|track| track.date.clone()));
+            album.genres = first_spellings(tracks.iter().flat_map(|track| track.resolve_genres()));
+            album.track_count = tracks.len();
+            album.works = group_works(album);
+            album.duration = tracks.iter().filter_map(|track| track.duration).sum();
+            album.formats = tracks
+                .iter()
+                .filter_map(|track| util::get_maybe_extension_string(&track.full_path))
+                .collect();
+
+            let directories: BTreeSet<&path::Path> = tracks
+                .iter()
+                .filter_map(|track| track.full_path.parent())
+                .collect();
+            album.cover = directories
+                .iter()
+                .find_map(|directory| find_cover(directory));
+            for directory in directories.into_iter() {
+                album_artists_by_directory
+                    .entry((album.name.clone(), directory.to_path_buf()))
+                    .or_default()
+                    .insert(artist.name.clone());
+            }
+
+            album.warnings.clear();
+            let dates = first_spellings(tracks.iter().filter_map(|track| track.date.clone()));
+            if dates.len() > 1 {
+                album
+                    .warnings
+                    .push(model::AlbumWarning::DifferentDates(dates));
+            }
+            // Compared as whole tags, so "Rock" next to "Rock; Blues" is a difference too
+            let genres = first_spellings(tracks.iter().filter_map(|track| track.genre.clone()));
+            if genres.len() > 1 {
+                album
+                    .warnings
+                    .push(model::AlbumWarning::DifferentGenres(genres));
+            }
+            let disc_totals: BTreeSet<u32> =
+                tracks.iter().filter_map(|track| track.disc_total).collect();
+            if disc_totals.len() > 1 {
+                album
+                    .warnings
+                    .push(model::AlbumWarning::DifferentDiscTotals(
+                        disc_totals.into_iter().collect(),
+                    ));
+            }
+        }
+    }
+
+    for ((album_name, _), artist_names) in album_artists_by_directory.into_iter() {
+        if artist_names.len() < 2 {
+            continue;
+        }
+        for artist_name in artist_names.iter() {
+            let others: Vec<String> = artist_names
+                .iter()
+                .filter(|other| *other != artist_name)
+                .cloned()
+                .collect();
+            let album = library
+                .artists
+                .get_mut(artist_name)
+                .and_then(|artist| artist.albums.get_mut(&album_name));
+            if let Some(album) = album {
+                let warning = model::AlbumWarning::SplitAcrossAlbumArtists(others);
+                // An album spread over several directories would otherwise repeat it
+                if !album.warnings.contains(&warning) {
+                    album.warnings.push(warning);
+                }
+            }
+        }
+    }
+}
+
+fn most_common(values: impl Iterator<Item = String>) -> Option<String> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for value in values {
+        *counts.entry(value).or_insert(0) += 1;
+    }
+    // Ties go to the alphabetically first value, for dates the earliest
+    counts
+        .into_iter()
+        .max_by(|(a, a_count), (b, b_count)| a_count.cmp(b_count).then(b.cmp(a)))
+        .map(|(value, _)| value)
+}
+
+fn first_spellings(values: impl Iterator<Item = String>) -> Vec<String> {
+    // Distinct values ignoring case and surrounding space, in the order first seen
+    let mut seen = BTreeSet::new();
+    values
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty() && seen.insert(value.to_lowercase()))
+        .collect()
+}
+
+fn find_cover(directory: &path::Path) -> Option<path::PathBuf> {
+    let mut images: Vec<path::PathBuf> = fs::read_dir(directory)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|entry_path| {
+            util::get_maybe_extension_string(entry_path)
+                .is_some_and(|extension| scan::COVER_EXTENSIONS.contains(&extension.as_str()))
+        })
+        .collect();
+    images.sort();
+
+    let preferred = ["cover", "folder", "front"].iter().find_map(|name| {
+        images.iter().find(|image| {
+            image
+                .file_stem()
+                .is_some_and(|stem| stem.to_string_lossy().eq_ignore_ascii_case(name))
+        })
+    });
+    preferred.or(images.first()).cloned()
+}
+
+fn index_track_artists(library: &mut model::Library) {
+    // Credits are matched to album artists regardless of case,
+    // and an artist only ever credited keeps the first spelling seen
+    let mut names: BTreeMap<String, String> = library
+        .artists
+        .keys()
+        .map(|name| (name.to_lowercase(), name.clone()))
+        .collect();
+
+    let mut appearances = Vec::new();
+    for artist in library.artists.values() {
+        for album in artist.albums.values() {
+            for disc in album.discs.values() {
+                for track in disc.tracks.values() {
+                    for credited in credited_artists(track).into_iter() {
+                        let credited_key = credited.to_lowercase();
+                        if credited_key == artist.name.to_lowercase() {
+                            continue;
+                        }
+                        let credited_name = names.entry(credited_key).or_insert(credited).clone();
+                        appearances.push((
+                            credited_name,
+                            artist.name.clone(),
+                            album.name.clone(),
+                            (disc.number, track.resolve_track_number()),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    for (credited_name, artist_name, album_name, track_number) in appearances.into_iter() {
+        library
             .artists
-            .entry(track.resolve_album_artist())
+            .entry(credited_name.clone())
             .or_insert(model::Artist {
-                name: track.resolve_album_artist(),
+                id: model::ArtistId(String::new()),
+                name: credited_name,
                 albums: BTreeMap::new(),
-            });
+                appears_on: BTreeMap::new(),
+            })
+            .appears_on
+            .entry((artist_name.clone(), album_name.clone()))
+            .or_insert(model::AppearsOnAlbum {
+                artist_name,
+                album_name,
+                tracks: BTreeSet::new(),
+            })
+            .tracks
+            .insert(track_number);
+    }
+}
 
-        let album_entry =
-            artist_entry
-                .albums
-                .entry(track.resolve_album())
-                .or_insert(model::Album {
-                    name: track.resolve_album(),
-                    discs: BTreeMap::new(),
-                });
-
-        let disc_entry = album_entry
-            .discs
-            .entry(track.resolve_disc_number())
-            .or_insert(model::Disc {
-                number: track.resolve_disc_number(),
-                tracks: BTreeMap::new(),
-            });
+fn credited_artists(track: &model::AudioFileTrackMetadata) -> Vec<String> {
+    let mut credited = Vec::new();
 
-        let conflict = disc_entry
-            .tracks
-            .insert(track.resolve_track_number(), track);
+    // The main artists are kept whole, since "&" and "," are as often part of a name,
+    // but a list of guests is split up
+    for value in track.artist.split([';', '\0']) {
+        let (main, featured) = split_featuring(value);
+        credited.push(main.to_string());
+        if let Some(featured) = featured {
+            credited.extend(split_guests(featured));
+        }
+    }
+    if let (_, Some(featured)) = split_featuring(&track.track_title) {
+        credited.extend(split_guests(featured));
+    }
 
-        match conflict {
-            Some(c) => {
-                return Err(model::Error::ConflictingTrack(
-                    c.resolve_album_artist(),
-                    c.resolve_album(),
-                    c.resolve_disc_number(),
-                    c.resolve_track_number(),
-                    c.track_title,
-                ))
-            }
-            None => (),
-        };
+    let mut seen = BTreeSet::new();
+    credited
+        .into_iter()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty() && seen.insert(name.to_lowercase()))
+        .collect()
+}
+
+fn split_featuring(value: &str) -> (&str, Option<&str>) {
+    // ASCII lowercasing keeps the byte offsets of the original
+    let lowercase = value.to_ascii_lowercase();
+    let found = FEATURING_WORDS
+        .iter()
+        .flat_map(|word| {
+            lowercase
+                .match_indices(word)
+                .map(move |(index, _)| (index, word.len()))
+        })
+        .filter(|(index, _)| {
+            *index > 0 && matches!(value.as_bytes()[index - 1], b' ' | b'(' | b'[')
+        })
+        .min();
+
+    match found {
+        Some((index, length)) => {
+            let main = value[..index].trim_end_matches([' ', '(', '[']);
+            // "Song (feat. Someone) [Remix]" only credits up to the closing bracket
+            let rest = &value[index + length..];
+            let featured = match rest.find([')', ']']) {
+                Some(end) => &rest[..end],
+                None => rest,
+            };
+            (main, Some(featured))
+        }
+        None => (value, None),
     }
+}
 
-    Ok(library)
+fn split_guests(featured: &str) -> Vec<String> {
+    featured
+        .split(", ")
+        .flat_map(|guest| guest.split(" & "))
+        .map(|guest| guest.trim().to_string())
+        .collect()
 }
@@ -1,61 +1,278 @@
+use std::collections::{BTreeSet, VecDeque};
 use std::path;
 
-use crate::{model, util};
+use crate::{cue, ids, infer, lyrics, model, scan, util};
 
-pub fn parse_all_audio_files(
-    paths: Vec<path::PathBuf>,
-) -> Result<Vec<model::AudioFileTrackMetadata>, model::Error> {
-    paths
-        .into_iter()
-        .map(|audio_file_path| parse_single_audio_file(audio_file_path))
-        .collect()
+pub fn stream_tracks<'a>(
+    scan_path: &path::Path,
+    options: &'a model::ScanOptions,
+    events: &'a mut dyn FnMut(model::ScanEvent),
+) -> model::TrackStream<'a> {
+    model::TrackStream {
+        walker: scan::Walker::new(scan_path, options, &scan::audio_extensions(options)),
+        options,
+        events,
+        pending_tracks: VecDeque::new(),
+        pending_paths: VecDeque::new(),
+        covered_by_cue_sheets: BTreeSet::new(),
+        found: 0,
+        done: 0,
+        finished: false,
+    }
+}
+
+impl Iterator for model::TrackStream<'_> {
+    type Item = Result<model::AudioFileTrackMetadata, model::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(track) = self.pending_tracks.pop_front() {
+                return Some(Ok(track));
+            }
+
+            if let Some(audio_file_path) = self.pending_paths.pop_front() {
+                let parsed = self.parse_audio_file(audio_file_path);
+                self.done += 1;
+                (self.events)(model::ScanEvent::Progress(self.done, self.found));
+                match parsed {
+                    Some(track) => return Some(Ok(track)),
+                    None => continue,
+                }
+            }
+
+            if self.finished {
+                return None;
+            }
+            match self.walker.next_directory(&mut *self.events) {
+                Ok(Some(paths)) => self.read_directory(paths),
+                Ok(None) => self.finished = true,
+                // The scan can't carry on past a directory it failed to read
+                Err(e) => {
+                    self.finished = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+impl model::TrackStream<'_> {
+    fn read_directory(&mut self, paths: Vec<path::PathBuf>) {
+        self.found += paths.len();
+
+        // CUE sheets go first, so the single-file rips they split up
+        // are not also added as one long track; a sheet is expected
+        // in the same directory as the file it splits
+        let (cue_paths, audio_file_paths): (Vec<path::PathBuf>, Vec<path::PathBuf>) = paths
+            .into_iter()
+            .partition(|p| util::get_maybe_extension_string(p).as_deref() == Some("cue"));
+
+        for cue_path in cue_paths.into_iter() {
+            match cue::parse_cue_file(&cue_path, self.options) {
+                Ok(cue_tracks) => {
+                    for track in cue_tracks.iter() {
+                        self.covered_by_cue_sheets.insert(track.full_path.clone());
+                    }
+                    (self.events)(model::ScanEvent::Parsed(cue_path));
+                    self.pending_tracks.extend(cue_tracks);
+                }
+                Err(e) => (self.events)(model::ScanEvent::Failed(cue_path, e)),
+            }
+            self.done += 1;
+            (self.events)(model::ScanEvent::Progress(self.done, self.found));
+        }
+
+        self.pending_paths.extend(audio_file_paths);
+    }
+
+    fn parse_audio_file(
+        &mut self,
+        audio_file_path: path::PathBuf,
+    ) -> Option<model::AudioFileTrackMetadata> {
+        if self.covered_by_cue_sheets.contains(&audio_file_path) {
+            (self.events)(model::ScanEvent::Skipped(
+                audio_file_path,
+                model::SkipReason::CoveredByCueSheet,
+            ));
+            return None;
+        }
+        match parse_single_audio_file(audio_file_path.clone(), self.options) {
+            Ok(track) => {
+                (self.events)(model::ScanEvent::Parsed(audio_file_path));
+                Some(track)
+            }
+            Err(e) => {
+                (self.events)(model::ScanEvent::Failed(audio_file_path, e));
+                None
+            }
+        }
+    }
 }
 
 pub fn parse_single_audio_file(
     audio_file_path: path::PathBuf,
+    options: &model::ScanOptions,
 ) -> Result<model::AudioFileTrackMetadata, model::Error> {
-    let maybe_extension = util::get_maybe_extension_string(&audio_file_path);
+    let mut track = read_audio_file(audio_file_path, options)?;
+    check_required_fields(&track)?;
+    ids::fill_track_id(&mut track, options.hash_audio)?;
+
+    let sidecar_lyrics = lyrics::read_sidecar_lrc(&track.full_path)?;
+    track.lyrics = lyrics::prefer_synced(track.lyrics.take(), sidecar_lyrics);
+
+    Ok(track)
+}
+
+// What the file's tags and its path say, which may still leave out the artist or title
+pub fn read_audio_file(
+    audio_file_path: path::PathBuf,
+    options: &model::ScanOptions,
+) -> Result<model::AudioFileTrackMetadata, model::Error> {
+    let mut track = match options.tag_readers.reader_for(&audio_file_path) {
+        Some(reader) => reader.read(&audio_file_path)?,
+        None => return Err(model::Error::UnsupportedFormat(audio_file_path)),
+    };
+
+    if let Some(ref path_inference) = options.path_inference {
+        infer::fill_from_path(&mut track, path_inference);
+    }
+    Ok(track)
+}
+
+pub fn check_required_fields(track: &model::AudioFileTrackMetadata) -> Result<(), model::Error> {
+    // Artist and title are the only fields a track can't do without
+    if track.artist.trim().is_empty() {
+        return Err(model::Error::MissingMetadataKey(
+            track.full_path.clone(),
+            "artist",
+        ));
+    }
+    if track.track_title.trim().is_empty() {
+        return Err(model::Error::MissingMetadataKey(
+            track.full_path.clone(),
+            "title",
+        ));
+    }
+    Ok(())
+}
+
+impl model::TagReader for model::FlacTagReader {
+    fn extensions(&self) -> &[&str] {
+        &["flac"]
+    }
+
+    fn read(&self, full_path: &path::Path) -> Result<model::AudioFileTrackMetadata, model::Error> {
+        flac::parse_flac_file(full_path.to_path_buf())
+    }
+}
+
+impl model::TagReader for model::Mp3TagReader {
+    fn extensions(&self) -> &[&str] {
+        &["mp3"]
+    }
 
-    match maybe_extension {
-        Some(extension) => match extension.as_str() {
-            "flac" => flac::parse_flac_file(audio_file_path),
-            "mp3" => id3::parse_mp3_file(audio_file_path),
-            _ => panic!("unknown audio file extension"),
-        },
-        None => panic!("file without extension"),
+    fn read(&self, full_path: &path::Path) -> Result<model::AudioFileTrackMetadata, model::Error> {
+        id3::parse_mp3_file(full_path.to_path_buf())
     }
 }
 
+fn first_musicbrainz_id(value: &str) -> String {
+    // Releases by several artists list all of their ids in one tag
+    value
+        .split([';', '/', '\0'])
+        .next()
+        .unwrap_or(value)
+        .trim()
+        .to_string()
+}
+
+fn parse_replay_gain_value(value: &str) -> Option<f64> {
+    // Gains are written as "-6.54 dB", peaks as a bare "0.988547"
+    let trimmed = value.trim();
+    let number = match trimmed.len().checked_sub(2) {
+        Some(i) if trimmed.is_char_boundary(i) && trimmed[i..].eq_ignore_ascii_case("db") => {
+            &trimmed[..i]
+        }
+        _ => trimmed,
+    };
+    number.trim().parse::<f64>().ok()
+}
+
 mod flac {
     use std::collections::BTreeMap;
     use std::path;
+    use std::time;
 
-    use crate::model;
+    use crate::{ids, lyrics, model, ratings};
 
     pub fn parse_flac_file(
         path: path::PathBuf,
     ) -> Result<model::AudioFileTrackMetadata, model::Error> {
         let reader = claxon::FlacReader::open(&path)?;
 
+        let streaminfo = reader.streaminfo();
+        let duration = streaminfo.samples.map(|samples| {
+            time::Duration::from_secs_f64(samples as f64 / streaminfo.sample_rate as f64)
+        });
+
         let tag_map = reader
             .tags()
             .map(|(k, v)| (k.to_string().to_lowercase(), v.to_string()))
             .collect::<BTreeMap<String, String>>();
 
-        // Note: "artist" and "title" are the only keys we require, the rest may or may not be set
+        let musicbrainz = model::MusicBrainzIds {
+            release_track_id: get_string_option(&tag_map, "musicbrainz_releasetrackid"),
+            album_id: get_string_option(&tag_map, "musicbrainz_albumid"),
+            album_artist_id: get_string_option(&tag_map, "musicbrainz_albumartistid")
+                .map(|v| super::first_musicbrainz_id(&v)),
+        };
+        let id = ids::track_id(&musicbrainz, ids::flac_stored_hash(streaminfo.md5sum));
+
+        // Note: "artist" and "title" are the only keys we require, the rest may or may not be set,
+        // but they may still come from the path so a missing one is left empty here
         // If one of the disc or track values are not numbers, then we will error out
         Ok(model::AudioFileTrackMetadata {
-            artist: get_string_result(&tag_map, "artist", &path)?,
+            id,
+            musicbrainz,
+            artist: get_string_option(&tag_map, "artist").unwrap_or_default(),
             album_artist: get_string_option(&tag_map, "albumartist"),
             album: get_string_option(&tag_map, "album"),
             disc_no: get_u32_optional_result(&tag_map, "discnumber", &path)?,
             disc_total: get_u32_optional_result(&tag_map, "disctotal", &path)?,
             track: get_u32_optional_result(&tag_map, "tracknumber", &path)?,
             track_total: get_u32_optional_result(&tag_map, "tracktotal", &path)?,
-            track_title: get_string_result(&tag_map, "title", &path)?,
+            track_title: get_string_option(&tag_map, "title").unwrap_or_default(),
             genre: get_string_option(&tag_map, "genre"),
             date: get_string_option(&tag_map, "date"),
+            duration,
+            replay_gain: model::ReplayGain {
+                track_gain: get_f64_optional_result(&tag_map, "replaygain_track_gain", &path)?,
+                track_peak: get_f64_optional_result(&tag_map, "replaygain_track_peak", &path)?,
+                album_gain: get_f64_optional_result(&tag_map, "replaygain_album_gain", &path)?,
+                album_peak: get_f64_optional_result(&tag_map, "replaygain_album_peak", &path)?,
+            },
+            classical: model::ClassicalTags {
+                composer: get_string_option(&tag_map, "composer"),
+                conductor: get_string_option(&tag_map, "conductor"),
+                // Taggers disagree on what to call it
+                performer: get_string_option(&tag_map, "performer")
+                    .or_else(|| get_string_option(&tag_map, "ensemble"))
+                    .or_else(|| get_string_option(&tag_map, "orchestra")),
+                work: get_string_option(&tag_map, "work"),
+                movement_name: get_string_option(&tag_map, "movementname"),
+                movement_number: get_u32_optional_result(&tag_map, "movement", &path)?,
+            },
+            rating: get_string_option(&tag_map, "fmps_rating")
+                .and_then(|v| ratings::from_fmps(&v))
+                .or_else(|| {
+                    get_string_option(&tag_map, "rating").and_then(|v| ratings::from_rating_tag(&v))
+                }),
+            lyrics: get_string_option(&tag_map, "lyrics")
+                .or_else(|| get_string_option(&tag_map, "unsyncedlyrics"))
+                .and_then(|text| lyrics::parse_lyrics(&text)),
             full_path: path,
+            range: None,
+            inferred_fields: Vec::new(),
         })
     }
 
@@ -63,29 +280,31 @@ mod flac {
         tag_map.get(key).map(|x| x.clone())
     }
 
-    fn get_string_result(
+    fn get_u32_optional_result(
         tag_map: &BTreeMap<String, String>,
         key: &'static str,
         path: &path::PathBuf,
-    ) -> Result<String, model::Error> {
-        Ok(tag_map
-            .get(key)
-            .ok_or(model::Error::MissingMetadataKey(
-                path.to_string_lossy().to_string(),
-                key,
-            ))?
-            .clone())
+    ) -> Result<Option<u32>, model::Error> {
+        Ok(match tag_map.get(key) {
+            Some(v) => Some(
+                v.parse::<u32>()
+                    .map_err(|_| model::Error::ExpectedU32MetadataValue(path.to_path_buf(), key))?,
+            ),
+            None => None,
+        })
     }
 
-    fn get_u32_optional_result(
+    fn get_f64_optional_result(
         tag_map: &BTreeMap<String, String>,
         key: &'static str,
-        path: &path::PathBuf,
-    ) -> Result<Option<u32>, model::Error> {
+        path: &path::Path,
+    ) -> Result<Option<f64>, model::Error> {
         Ok(match tag_map.get(key) {
-            Some(v) => Some(v.parse::<u32>().map_err(|_| {
-                model::Error::ExpectedU32MetadataValue(path.to_string_lossy().to_string(), key)
-            })?),
+            Some(v) => {
+                Some(super::parse_replay_gain_value(v).ok_or_else(|| {
+                    model::Error::ExpectedF64MetadataValue(path.to_path_buf(), key)
+                })?)
+            }
             None => None,
         })
     }
@@ -93,41 +312,211 @@ mod flac {
 
 mod id3 {
     use std::path;
+    use std::time;
 
     use id3::{self, TagLike};
 
-    use crate::model;
+    use crate::{ids, lyrics, model, mpeg, ratings};
 
     pub fn parse_mp3_file(
         path: path::PathBuf,
     ) -> Result<model::AudioFileTrackMetadata, model::Error> {
-        let tag = id3::Tag::read_from_path(&path)?;
+        // A file without an ID3 tag reads as one with nothing in it,
+        // leaving its artist and title to be inferred or reported
+        let tag = match id3::Tag::read_from_path(&path) {
+            Ok(tag) => tag,
+            Err(id3::Error {
+                kind: id3::ErrorKind::NoTag,
+                ..
+            }) => id3::Tag::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        // Prefer the length the tagger recorded (TLEN, in milliseconds),
+        // and only look at the audio frames when it is missing
+        let duration = match tag.duration() {
+            Some(millis) => Some(time::Duration::from_millis(millis as u64)),
+            None => mpeg::estimate_duration(&path)?,
+        };
+
+        let musicbrainz = model::MusicBrainzIds {
+            release_track_id: get_txxx_string_option(&tag, "musicbrainz release track id"),
+            album_id: get_txxx_string_option(&tag, "musicbrainz album id"),
+            album_artist_id: get_txxx_string_option(&tag, "musicbrainz album artist id")
+                .map(|v| super::first_musicbrainz_id(&v)),
+        };
+        let id = ids::track_id(&musicbrainz, None);
 
         Ok(model::AudioFileTrackMetadata {
-            artist: get_string_result(tag.artist(), "artist", &path)?,
+            id,
+            musicbrainz,
+            artist: tag.artist().unwrap_or_default().to_string(),
             album_artist: tag.album_artist().map(|x| x.to_string()),
             album: tag.album().map(|x| x.to_string()),
             disc_no: tag.disc(),
             disc_total: tag.total_discs(),
             track: tag.track(),
             track_total: tag.total_tracks(),
-            track_title: get_string_result(tag.title(), "title", &path)?,
+            track_title: tag.title().unwrap_or_default().to_string(),
             genre: tag.genre().map(|x| x.to_string()),
             date: tag.year().map(|x| x.to_string()),
+            duration,
+            replay_gain: model::ReplayGain {
+                track_gain: get_txxx_f64_optional_result(&tag, "replaygain_track_gain", &path)?,
+                track_peak: get_txxx_f64_optional_result(&tag, "replaygain_track_peak", &path)?,
+                album_gain: get_txxx_f64_optional_result(&tag, "replaygain_album_gain", &path)?,
+                album_peak: get_txxx_f64_optional_result(&tag, "replaygain_album_peak", &path)?,
+            },
+            classical: model::ClassicalTags {
+                composer: get_text_option(&tag, "TCOM"),
+                conductor: get_text_option(&tag, "TPE3"),
+                performer: get_performers(&tag),
+                // ID3 has no frame for the work, so taggers write a TXXX frame
+                work: get_txxx_string_option(&tag, "work"),
+                movement_name: get_undecoded_text_option(&tag, "MVNM"),
+                movement_number: get_movement_number(&tag, &path)?,
+            },
+            // Amarok and Quod Libet write the FMPS rating to a TXXX frame, most others use POPM
+            rating: get_txxx_string_option(&tag, "fmps_rating")
+                .and_then(|v| ratings::from_fmps(&v))
+                .or_else(|| {
+                    tag.frames()
+                        .filter_map(|frame| frame.content().popularimeter())
+                        .find_map(|popularimeter| ratings::from_popm(popularimeter.rating))
+                }),
+            lyrics: get_lyrics(&tag),
             full_path: path,
+            range: None,
+            inferred_fields: Vec::new(),
+        })
+    }
+
+    fn get_lyrics(tag: &id3::Tag) -> Option<model::Lyrics> {
+        // SYLT can count in MPEG frames instead of milliseconds,
+        // which we can't turn into a time without decoding, so only use the latter
+        let synced = tag
+            .synchronised_lyrics()
+            .find(|sylt| sylt.timestamp_format == id3::frame::TimestampFormat::Ms)
+            .and_then(|sylt| {
+                lyrics::from_timestamped_lines(
+                    sylt.content
+                        .iter()
+                        .map(|(millis, text)| {
+                            (time::Duration::from_millis(*millis as u64), text.clone())
+                        })
+                        .collect(),
+                )
+            });
+
+        match synced {
+            Some(v) => Some(v),
+            None => tag
+                .lyrics()
+                .next()
+                .and_then(|uslt| lyrics::parse_lyrics(&uslt.text)),
+        }
+    }
+
+    fn get_text_option(tag: &id3::Tag, id: &'static str) -> Option<String> {
+        tag.get(id)
+            .and_then(|frame| frame.content().text())
+            .map(|text| text.to_string())
+    }
+
+    fn get_performers(tag: &id3::Tag) -> Option<String> {
+        // ID3v2.4 lists musicians with their instruments in TMCL, e.g. "violin" and a name;
+        // older tags only have what taggers put in a TXXX frame
+        let musicians: Vec<&str> = tag
+            .get("TMCL")
+            .and_then(|frame| frame.content().involved_people_list())
+            .map(|list| {
+                list.items
+                    .iter()
+                    .map(|item| item.involvee.as_str())
+                    .collect()
+            })
+            .unwrap_or_default();
+        if musicians.is_empty() {
+            get_txxx_string_option(tag, "performer")
+        } else {
+            Some(musicians.join("; "))
+        }
+    }
+
+    fn get_movement_number(tag: &id3::Tag, path: &path::Path) -> Result<Option<u32>, model::Error> {
+        // Written like a track number, e.g. "2/4"
+        Ok(match get_undecoded_text_option(tag, "MVIN") {
+            Some(v) => Some(
+                v.split('/')
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .parse::<u32>()
+                    .map_err(|_| {
+                        model::Error::ExpectedU32MetadataValue(path.to_path_buf(), "MVIN")
+                    })?,
+            ),
+            None => None,
         })
     }
 
-    fn get_string_result(
-        val: Option<&str>,
+    fn get_undecoded_text_option(tag: &id3::Tag, id: &'static str) -> Option<String> {
+        // The id3 crate leaves iTunes' movement frames undecoded,
+        // though they are laid out like any text frame: an encoding byte, then the text
+        let unknown = tag.get(id)?.content().to_unknown().ok()?;
+        let (encoding, bytes) = unknown.data.split_first()?;
+        let text = match encoding {
+            0 => bytes.iter().map(|&b| b as char).collect(),
+            1 | 2 => {
+                // UTF-16 starts with a byte order mark, UTF-16BE doesn't
+                let (big_endian, bytes) = match bytes {
+                    [0xFE, 0xFF, rest @ ..] if *encoding == 1 => (true, rest),
+                    [0xFF, 0xFE, rest @ ..] if *encoding == 1 => (false, rest),
+                    _ => (*encoding == 2, bytes),
+                };
+                let units: Vec<u16> = bytes
+                    .chunks_exact(2)
+                    .map(|pair| match big_endian {
+                        true => u16::from_be_bytes([pair[0], pair[1]]),
+                        false => u16::from_le_bytes([pair[0], pair[1]]),
+                    })
+                    .collect();
+                String::from_utf16_lossy(&units)
+            }
+            3 => String::from_utf8_lossy(bytes).to_string(),
+            _ => return None,
+        };
+        let text = text.trim_end_matches('\0');
+        if text.is_empty() {
+            None
+        } else {
+            Some(text.to_string())
+        }
+    }
+
+    fn get_txxx_string_option(tag: &id3::Tag, key: &'static str) -> Option<String> {
+        tag.extended_texts()
+            .find(|text| text.description.eq_ignore_ascii_case(key))
+            .map(|text| text.value.clone())
+    }
+
+    fn get_txxx_f64_optional_result(
+        tag: &id3::Tag,
         key: &'static str,
-        path: &path::PathBuf,
-    ) -> Result<String, model::Error> {
-        Ok(val
-            .ok_or(model::Error::MissingMetadataKey(
-                path.to_string_lossy().to_string(),
-                key,
-            ))?
-            .to_string())
+        path: &path::Path,
+    ) -> Result<Option<f64>, model::Error> {
+        // ReplayGain in ID3 lives in user defined TXXX frames,
+        // whose descriptions taggers write in either case
+        let value = tag
+            .extended_texts()
+            .find(|text| text.description.eq_ignore_ascii_case(key));
+        Ok(match value {
+            Some(text) => {
+                Some(super::parse_replay_gain_value(&text.value).ok_or_else(|| {
+                    model::Error::ExpectedF64MetadataValue(path.to_path_buf(), key)
+                })?)
+            }
+            None => None,
+        })
     }
 }
@@ -0,0 +1,328 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::time;
+
+use crate::model;
+
+// For when nobody wrote rules of their own
+const DEFAULT_RULES: &str = "\
+Weekday Mornings: days mon-fri; hours 6-10
+Weekend Evenings: days sat-sun; hours 18-24
+Late Nights: hours 23-4
+Summer: seasons summer
+Winter: seasons winter
+";
+
+const DEFAULT_LIMIT: usize = 50;
+
+const WEEKDAYS: [(model::Weekday, &str); 7] = [
+    (model::Weekday::Monday, "monday"),
+    (model::Weekday::Tuesday, "tuesday"),
+    (model::Weekday::Wednesday, "wednesday"),
+    (model::Weekday::Thursday, "thursday"),
+    (model::Weekday::Friday, "friday"),
+    (model::Weekday::Saturday, "saturday"),
+    (model::Weekday::Sunday, "sunday"),
+];
+
+const MONTHS: [&str; 12] = [
+    "january",
+    "february",
+    "march",
+    "april",
+    "may",
+    "june",
+    "july",
+    "august",
+    "september",
+    "october",
+    "november",
+    "december",
+];
+
+const SEASONS: [(&str, [u32; 3]); 5] = [
+    ("spring", [3, 4, 5]),
+    ("summer", [6, 7, 8]),
+    ("autumn", [9, 10, 11]),
+    ("fall", [9, 10, 11]),
+    ("winter", [12, 1, 2]),
+];
+
+// When a play started, as the rules see it
+struct LocalTime {
+    year: i32,
+    month: u32,
+    weekday: model::Weekday,
+    hour: u32,
+}
+
+pub fn default_rules() -> model::PlaylistRules {
+    parse_rules(DEFAULT_RULES).expect("the default playlist rules are valid")
+}
+
+pub fn parse_rules(text: &str) -> Result<model::PlaylistRules, model::Error> {
+    let mut rules = model::PlaylistRules {
+        utc_offset_minutes: 0,
+        playlists: Vec::new(),
+    };
+
+    for (index, line) in text.lines().enumerate() {
+        let invalid = |reason| model::Error::InvalidPlaylistRules(index + 1, reason);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // Checked first, as the offset has a colon in it too
+        if let Some(offset) = line.strip_prefix("utc-offset ") {
+            rules.utc_offset_minutes = parse_utc_offset(offset.trim())
+                .ok_or_else(|| invalid("expected an offset like +01:00"))?;
+            continue;
+        }
+
+        let (name, conditions) = line
+            .split_once(':')
+            .ok_or_else(|| invalid("expected a name, a colon and the conditions"))?;
+        let mut rule = model::PlaylistRule {
+            name: name.trim().to_string(),
+            days: BTreeSet::new(),
+            hours: BTreeSet::new(),
+            months: BTreeSet::new(),
+            years: BTreeSet::new(),
+            limit: DEFAULT_LIMIT,
+        };
+        if rule.name.is_empty() {
+            return Err(invalid("the playlist has no name"));
+        }
+        for condition in conditions.split(';').map(|c| c.trim()) {
+            if condition.is_empty() {
+                continue;
+            }
+            let (key, values) = condition.split_once(' ').unwrap_or((condition, ""));
+            parse_condition(&mut rule, key, &values.trim().to_lowercase()).map_err(invalid)?;
+        }
+        rules.playlists.push(rule);
+    }
+
+    Ok(rules)
+}
+
+pub fn generate(
+    rules: &model::PlaylistRules,
+    history: &model::ListeningHistory,
+    library: &model::Library,
+) -> Vec<model::ContextPlaylist> {
+    // Skipped plays say what wasn't wanted, and tracks gone from the library can't be played
+    let plays: Vec<(&model::Play, LocalTime)> = history
+        .plays
+        .iter()
+        .filter(|play| !play.skipped && library.track_ids.contains_key(&play.track_id))
+        .map(|play| (play, local_time(play.started_at, rules.utc_offset_minutes)))
+        .collect();
+
+    rules
+        .playlists
+        .iter()
+        .map(|rule| {
+            let mut counts: BTreeMap<&model::TrackId, (usize, time::SystemTime)> = BTreeMap::new();
+            for (play, _) in plays.iter().filter(|(_, at)| matches(rule, at)) {
+                let (count, last_played) =
+                    counts.entry(&play.track_id).or_insert((0, play.started_at));
+                *count += 1;
+                *last_played = (*last_played).max(play.started_at);
+            }
+
+            // Most played first, and of those the most recently played
+            let mut tracks: Vec<(&model::TrackId, (usize, time::SystemTime))> =
+                counts.into_iter().collect();
+            tracks.sort_by(|(_, a), (_, b)| b.cmp(a));
+            tracks.truncate(rule.limit);
+
+            model::ContextPlaylist {
+                name: rule.name.clone(),
+                tracks: tracks
+                    .into_iter()
+                    .map(|(id, (count, _))| (id.clone(), count))
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+fn matches(rule: &model::PlaylistRule, at: &LocalTime) -> bool {
+    (rule.days.is_empty() || rule.days.contains(&at.weekday))
+        && (rule.hours.is_empty() || rule.hours.contains(&at.hour))
+        && (rule.months.is_empty() || rule.months.contains(&at.month))
+        && (rule.years.is_empty() || rule.years.contains(&at.year))
+}
+
+fn parse_condition(
+    rule: &mut model::PlaylistRule,
+    key: &str,
+    values: &str,
+) -> Result<(), &'static str> {
+    let items: Vec<(&str, &str)> = values
+        .split(',')
+        .map(|item| item.trim())
+        .map(|item| match item.split_once('-') {
+            Some((first, last)) => (first.trim(), last.trim()),
+            None => (item, item),
+        })
+        .collect();
+
+    match key {
+        "days" => {
+            let names = WEEKDAYS.map(|(_, name)| name);
+            for (first, last) in items {
+                let first =
+                    name_index(&names, first).ok_or("expected days like mon-fri or sat,sun")?;
+                let last =
+                    name_index(&names, last).ok_or("expected days like mon-fri or sat,sun")?;
+                for index in wrapping_range(first, last, 7) {
+                    rule.days.insert(WEEKDAYS[index].0);
+                }
+            }
+        }
+        "hours" => {
+            for item in values.split(',').map(|item| item.trim()) {
+                // The end of a range is when it is over, so 18-24 is the evening up to midnight
+                let (first, end) = match item.split_once('-') {
+                    Some((first, end)) => (parse_hour(first), parse_hour(end)),
+                    None => (parse_hour(item), parse_hour(item).map(|hour| hour + 1)),
+                };
+                let (first, end) = first.zip(end).ok_or("expected hours like 18-24")?;
+                if first == 24 || first == end {
+                    return Err("expected hours like 18-24");
+                }
+                for hour in wrapping_range(first, (end + 23) % 24, 24) {
+                    rule.hours.insert(hour as u32);
+                }
+            }
+        }
+        "months" => {
+            for (first, last) in items {
+                let first = parse_month(first).ok_or("expected months like dec-feb or 6,7")?;
+                let last = parse_month(last).ok_or("expected months like dec-feb or 6,7")?;
+                for index in wrapping_range(first, last, 12) {
+                    rule.months.insert(index as u32 + 1);
+                }
+            }
+        }
+        "seasons" => {
+            for (season, _) in items {
+                let (_, months) = SEASONS
+                    .iter()
+                    .find(|(name, _)| *name == season)
+                    .ok_or("expected seasons like winter or summer")?;
+                rule.months.extend(months.iter());
+            }
+        }
+        "years" => {
+            for (first, last) in items {
+                let (first, last) = match first.strip_suffix('s') {
+                    // A decade, like 2010s
+                    Some(decade) if first == last => {
+                        let decade = decade.parse::<i32>().ok().filter(|d| d % 10 == 0);
+                        decade.zip(decade.map(|decade| decade + 9))
+                    }
+                    _ => first.parse::<i32>().ok().zip(last.parse::<i32>().ok()),
+                }
+                .filter(|(first, last)| first <= last)
+                .ok_or("expected years like 2015, 2010-2014 or 2010s")?;
+                rule.years.extend(first..=last);
+            }
+        }
+        "limit" => {
+            rule.limit = values
+                .parse::<usize>()
+                .ok()
+                .filter(|limit| *limit > 0)
+                .ok_or("expected a number of tracks")?;
+        }
+        _ => return Err("expected days, hours, months, seasons, years or limit"),
+    }
+    Ok(())
+}
+
+fn wrapping_range(first: usize, last: usize, count: usize) -> Vec<usize> {
+    // From first to last, going round to the start again when last comes before first
+    let mut indexes = vec![first];
+    let mut index = first;
+    while index != last {
+        index = (index + 1) % count;
+        indexes.push(index);
+    }
+    indexes
+}
+
+fn name_index(names: &[&str], value: &str) -> Option<usize> {
+    // Names may be shortened down to their first three letters
+    if value.len() < 3 {
+        return None;
+    }
+    names.iter().position(|name| name.starts_with(value))
+}
+
+fn parse_month(value: &str) -> Option<usize> {
+    match value.parse::<usize>() {
+        Ok(month) if (1..=12).contains(&month) => Some(month - 1),
+        Ok(_) => None,
+        Err(_) => name_index(&MONTHS, value),
+    }
+}
+
+fn parse_hour(value: &str) -> Option<usize> {
+    value
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .filter(|hour| *hour <= 24)
+}
+
+fn parse_utc_offset(value: &str) -> Option<i32> {
+    let (sign, value) = match value.split_at(value.find(|c: char| c.is_ascii_digit())?) {
+        ("+", value) => (1, value),
+        ("-", value) => (-1, value),
+        _ => return None,
+    };
+    let (hours, minutes) = value.split_once(':').unwrap_or((value, "0"));
+    let hours = hours.parse::<i32>().ok().filter(|hours| *hours <= 14)?;
+    let minutes = minutes
+        .parse::<i32>()
+        .ok()
+        .filter(|minutes| *minutes < 60)?;
+    Some(sign * (hours * 60 + minutes))
+}
+
+fn local_time(at: time::SystemTime, utc_offset_minutes: i32) -> LocalTime {
+    let seconds = at
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+        + utc_offset_minutes as i64 * 60;
+    let days = seconds.div_euclid(86400);
+    let (year, month) = year_and_month(days);
+    LocalTime {
+        year,
+        month,
+        // The first of January 1970 was a Thursday
+        weekday: WEEKDAYS[(days + 3).rem_euclid(7) as usize].0,
+        hour: (seconds.rem_euclid(86400) / 3600) as u32,
+    }
+}
+
+fn year_and_month(days_since_1970: i64) -> (i32, u32) {
+    // Howard Hinnant's civil_from_days, counting in 400 year eras that start on the first of March
+    let days = days_since_1970 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let march_based_month = (5 * day_of_year + 2) / 153;
+    let month = if march_based_month < 10 {
+        march_based_month + 3
+    } else {
+        march_based_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year as i32, month as u32)
+}
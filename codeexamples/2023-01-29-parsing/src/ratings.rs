@@ -0,0 +1,145 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{model, tagwrite, util};
+
+// The POPM values Windows Media Player writes for no to five stars, which other players follow
+const POPM_STARS: [u8; 6] = [0, 1, 64, 128, 196, 255];
+
+// A missing array is taken as an empty one
+#[derive(Serialize, Deserialize)]
+struct StoreJson {
+    #[serde(default)]
+    ratings: Vec<RatingJson>,
+    #[serde(default)]
+    favourites: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RatingJson {
+    id: String,
+    rating: Option<f64>,
+}
+
+// A rating nobody can read is no reason to leave the track out,
+// so values that don't make sense are ignored rather than reported
+pub fn from_fmps(value: &str) -> Option<f64> {
+    // Already 0.0 to 1.0
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|rating| (0.0..=1.0).contains(rating))
+}
+
+pub fn from_rating_tag(value: &str) -> Option<f64> {
+    // RATING has no agreed scale; players write either stars or a percentage
+    match value.trim().parse::<f64>().ok()? {
+        stars if (0.0..=5.0).contains(&stars) => Some(stars / 5.0),
+        percent if (0.0..=100.0).contains(&percent) => Some(percent / 100.0),
+        _ => None,
+    }
+}
+
+pub fn from_popm(value: u8) -> Option<f64> {
+    // 0 is unrated, the rest is read as stars the way Windows Media Player does
+    let stars = match value {
+        0 => return None,
+        1..=31 => 1,
+        32..=95 => 2,
+        96..=159 => 3,
+        160..=223 => 4,
+        _ => 5,
+    };
+    Some(stars as f64 / 5.0)
+}
+
+pub fn to_popm(rating: f64) -> u8 {
+    POPM_STARS[(rating.clamp(0.0, 1.0) * 5.0).round() as usize]
+}
+
+pub fn to_fmps(rating: f64) -> String {
+    format!("{}", rating.clamp(0.0, 1.0))
+}
+
+pub fn open_store(store_path: &path::Path) -> Result<model::RatingsStore, model::Error> {
+    let mut store = model::RatingsStore {
+        path: store_path.to_path_buf(),
+        ratings: BTreeMap::new(),
+        favourites: BTreeSet::new(),
+        write_to_files: false,
+    };
+
+    let bytes = match fs::read(store_path) {
+        Ok(bytes) => bytes,
+        // Nothing was rated yet
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(store),
+        Err(e) => return Err(e.into()),
+    };
+    let invalid =
+        |message: String| model::Error::InvalidRatingsStore(store_path.to_path_buf(), message);
+
+    let saved: StoreJson = serde_json::from_slice(&bytes).map_err(|e| invalid(e.to_string()))?;
+    for entry in saved.ratings.into_iter() {
+        if let Some(rating) = entry.rating {
+            if !(0.0..=1.0).contains(&rating) {
+                return Err(invalid("expected a rating from 0 to 1".to_string()));
+            }
+        }
+        store.ratings.insert(model::TrackId(entry.id), entry.rating);
+    }
+    store
+        .favourites
+        .extend(saved.favourites.into_iter().map(model::TrackId));
+
+    Ok(store)
+}
+
+pub fn save_store(store: &model::RatingsStore) -> Result<(), model::Error> {
+    let saved = StoreJson {
+        ratings: store
+            .ratings
+            .iter()
+            .map(|(id, rating)| RatingJson {
+                id: id.0.clone(),
+                rating: *rating,
+            })
+            .collect(),
+        favourites: store.favourites.iter().map(|id| id.0.clone()).collect(),
+    };
+    let mut text = serde_json::to_string_pretty(&saved).map_err(io::Error::from)?;
+    text.push('\n');
+
+    // Swapped in whole, so a crash while saving never loses the ratings saved before
+    let mut temporary_path = store.path.as_os_str().to_owned();
+    temporary_path.push(".saving");
+    fs::write(&temporary_path, text)?;
+    fs::rename(&temporary_path, &store.path)?;
+
+    Ok(())
+}
+
+pub fn write_rating_to_file(
+    track: &model::AudioFileTrackMetadata,
+    rating: Option<f64>,
+) -> Result<(), model::Error> {
+    // The tracks of a CUE sheet share one file, which can't hold a rating for each
+    if track.range.is_some() {
+        return Ok(());
+    }
+
+    match util::get_maybe_extension_string(&track.full_path).as_deref() {
+        Some("flac") => match rating {
+            Some(rating) => {
+                tagwrite::write_flac_tags(&track.full_path, &[("FMPS_RATING", to_fmps(rating))])
+            }
+            None => tagwrite::remove_flac_tags(&track.full_path, &["FMPS_RATING"]),
+        },
+        Some("mp3") => tagwrite::write_mp3_rating(&track.full_path, rating),
+        _ => Err(model::Error::UnsupportedFormat(track.full_path.clone())),
+    }
+}
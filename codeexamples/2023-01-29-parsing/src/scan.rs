@@ -1,37 +1,158 @@
+use std::collections::BTreeSet;
 use std::fs;
 use std::path;
 
-use crate::{model, util};
+use crate::{exclude, model, util};
 
-pub fn find_audio_files(scan_path: &path::PathBuf) -> Result<Vec<path::PathBuf>, model::Error> {
-    let mut audio_files = Vec::new();
+pub const COVER_EXTENSIONS: [&str; 3] = ["jpg", "jpeg", "png"];
 
-    for child_entry in fs::read_dir(scan_path)? {
-        let child_entry = child_entry?;
-        let child_path = child_entry.path();
+pub fn audio_extensions(options: &model::ScanOptions) -> Vec<String> {
+    // CUE sheets are always read, as they split up files of any format that has a reader
+    let mut extensions = options.tag_readers.extensions();
+    if !extensions.iter().any(|e| e == "cue") {
+        extensions.push("cue".to_string());
+    }
+    extensions
+}
+
+pub fn find_files(
+    scan_path: &path::Path,
+    options: &model::ScanOptions,
+    extensions: &[&str],
+    events: &mut dyn FnMut(model::ScanEvent),
+) -> Result<Vec<path::PathBuf>, model::Error> {
+    let mut walker = Walker::new(scan_path, options, extensions);
+    let mut found_files = Vec::new();
+    while let Some(files) = walker.next_directory(events)? {
+        found_files.extend(files);
+    }
+    Ok(found_files)
+}
+
+// Reads one directory at a time, so files can be handled while the rest are still being found
+pub struct Walker<'a> {
+    options: &'a model::ScanOptions,
+    extensions: Vec<String>,
+    visited_directories: BTreeSet<path::PathBuf>,
+    pending_directories: Vec<(path::PathBuf, usize, exclude::ExcludeRules)>,
+}
 
-        if child_entry.file_type()?.is_dir() {
-            audio_files.append(&mut find_audio_files(&child_path)?);
+impl<'a> Walker<'a> {
+    pub fn new<S: AsRef<str>>(
+        scan_path: &path::Path,
+        options: &'a model::ScanOptions,
+        extensions: &[S],
+    ) -> Self {
+        let rules = exclude::ExcludeRules::new(scan_path, &options.exclude_patterns);
+        Walker {
+            options,
+            extensions: extensions.iter().map(|e| e.as_ref().to_string()).collect(),
+            visited_directories: BTreeSet::new(),
+            pending_directories: vec![(scan_path.to_path_buf(), 0, rules)],
         }
-        if child_entry.file_type()?.is_file() {
-            let maybe_extension = util::get_maybe_extension_string(&child_path);
-
-            match maybe_extension {
-                Some(extension) => match extension.as_str() {
-                    "flac" => audio_files.push(child_path),
-                    "mp3" => audio_files.push(child_path),
-                    _ => println!(
-                        "DEBUG: Skipping file with unknown extension: {}",
-                        child_path.to_string_lossy()
-                    ),
-                },
-                None => println!(
-                    "DEBUG: skipping file with no extension: {}",
-                    child_path.to_string_lossy()
-                ),
+    }
+
+    // The wanted files directly inside the next directory, or None once every directory was read
+    pub fn next_directory(
+        &mut self,
+        events: &mut dyn FnMut(model::ScanEvent),
+    ) -> Result<Option<Vec<path::PathBuf>>, model::Error> {
+        while let Some((scan_path, depth, parent_rules)) = self.pending_directories.pop() {
+            // Remember every directory by its real location, so a symlink
+            // pointing back up the tree (or at a directory we already saw) is only read once
+            if !self
+                .visited_directories
+                .insert(fs::canonicalize(&scan_path)?)
+            {
+                skip(events, &scan_path, model::SkipReason::AlreadyScanned);
+                continue;
+            }
+
+            return self
+                .read_directory(&scan_path, depth, &parent_rules, events)
+                .map(Some);
+        }
+        Ok(None)
+    }
+
+    fn read_directory(
+        &mut self,
+        scan_path: &path::Path,
+        depth: usize,
+        parent_rules: &exclude::ExcludeRules,
+        events: &mut dyn FnMut(model::ScanEvent),
+    ) -> Result<Vec<path::PathBuf>, model::Error> {
+        let rules = if self.options.use_musicignore_files {
+            parent_rules.with_musicignore(scan_path)?
+        } else {
+            parent_rules.clone()
+        };
+
+        let mut found_files = Vec::new();
+        let mut subdirectories = Vec::new();
+        for child_entry in fs::read_dir(scan_path)? {
+            let child_entry = child_entry?;
+            let child_path = child_entry.path();
+
+            if self.options.skip_hidden
+                && child_entry.file_name().to_string_lossy().starts_with('.')
+            {
+                skip(events, &child_path, model::SkipReason::Hidden);
+                continue;
+            }
+
+            let mut file_type = child_entry.file_type()?;
+            if file_type.is_symlink() {
+                if !self.options.follow_symlinks {
+                    skip(events, &child_path, model::SkipReason::Symlink);
+                    continue;
+                }
+                file_type = match fs::metadata(&child_path) {
+                    Ok(target_metadata) => target_metadata.file_type(),
+                    Err(_) => {
+                        skip(events, &child_path, model::SkipReason::BrokenSymlink);
+                        continue;
+                    }
+                };
+            }
+
+            if rules.is_excluded(&child_path, file_type.is_dir()) {
+                skip(events, &child_path, model::SkipReason::Excluded);
+                continue;
+            }
+
+            if file_type.is_dir() {
+                match self.options.max_depth {
+                    Some(max_depth) if depth >= max_depth => {
+                        skip(events, &child_path, model::SkipReason::TooDeep)
+                    }
+                    _ => subdirectories.push(child_path.clone()),
+                }
+            }
+            if file_type.is_file() {
+                let maybe_extension = util::get_maybe_extension_string(&child_path);
+
+                match maybe_extension {
+                    Some(extension) if self.extensions.contains(&extension) => {
+                        events(model::ScanEvent::Found(child_path.clone()));
+                        found_files.push(child_path);
+                    }
+                    Some(_) => skip(events, &child_path, model::SkipReason::UnknownExtension),
+                    None => skip(events, &child_path, model::SkipReason::NoExtension),
+                }
             }
         }
+
+        // Pushed in reverse so subdirectories are read in the order they were listed
+        for subdirectory in subdirectories.into_iter().rev() {
+            self.pending_directories
+                .push((subdirectory, depth + 1, rules.clone()));
+        }
+
+        Ok(found_files)
     }
+}
 
-    Ok(audio_files)
+fn skip(events: &mut dyn FnMut(model::ScanEvent), path: &path::Path, reason: model::SkipReason) {
+    events(model::ScanEvent::Skipped(path.to_path_buf(), reason));
 }
@@ -0,0 +1,273 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path;
+use std::time;
+
+use crate::model;
+
+const HEADER: &str = "#AUDIOSCROBBLER/1.1";
+
+pub fn from_history(
+    history: &model::ListeningHistory,
+    library: &model::Library,
+) -> model::ScrobblerLog {
+    let entries = history
+        .plays
+        .iter()
+        .map(|play| {
+            // The play remembers the names, the library knows the rest while the track is still there
+            let track = library.get_track(&play.track_id);
+            model::ScrobblerEntry {
+                artist: play.artist.clone(),
+                album: play.album.clone(),
+                track_title: play.track_title.clone(),
+                track: track.and_then(|track| track.track),
+                duration: track
+                    .and_then(|track| track.duration)
+                    .or(Some(play.played))
+                    .map(|duration| time::Duration::from_secs(duration.as_secs())),
+                listened: !play.skipped,
+                timestamp: play.started_at,
+                musicbrainz_track_id: None,
+            }
+        })
+        .collect();
+
+    model::ScrobblerLog { utc: true, entries }
+}
+
+pub fn write_log(log: &model::ScrobblerLog, output_path: &path::Path) -> Result<(), model::Error> {
+    let mut text = format!(
+        "{}\n#TZ/{}\n#CLIENT/simpleaudioparser {}\n",
+        HEADER,
+        if log.utc { "UTC" } else { "UNKNOWN" },
+        env!("CARGO_PKG_VERSION")
+    );
+    for entry in log.entries.iter() {
+        let fields = [
+            field(&entry.artist),
+            field(entry.album.as_deref().unwrap_or_default()),
+            field(&entry.track_title),
+            entry.track.map(|t| t.to_string()).unwrap_or_default(),
+            entry
+                .duration
+                .map(|d| d.as_secs().to_string())
+                .unwrap_or_default(),
+            if entry.listened { "L" } else { "S" }.to_string(),
+            unix_seconds(entry.timestamp).to_string(),
+            field(entry.musicbrainz_track_id.as_deref().unwrap_or_default()),
+        ];
+        text.push_str(&fields.join("\t"));
+        text.push('\n');
+    }
+    fs::write(output_path, text)?;
+    Ok(())
+}
+
+pub fn read_log(log_path: &path::Path) -> Result<model::ScrobblerLog, model::Error> {
+    // Players write UTF-8, but a stray byte is no reason to lose the rest of the log
+    let text = String::from_utf8_lossy(&fs::read(log_path)?).to_string();
+
+    let mut log = model::ScrobblerLog {
+        utc: false,
+        entries: Vec::new(),
+    };
+    for (index, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('#') {
+            if let Some(time_zone) = header.strip_prefix("TZ/") {
+                log.utc = time_zone.trim() == "UTC";
+            }
+            continue;
+        }
+        let entry = parse_entry(line)
+            .ok_or_else(|| model::Error::InvalidScrobblerLog(log_path.to_path_buf(), index + 1))?;
+        log.entries.push(entry);
+    }
+
+    Ok(log)
+}
+
+pub fn import_log(
+    history: &mut model::ListeningHistory,
+    log: &model::ScrobblerLog,
+    library: &model::Library,
+    utc_offset_minutes: i32,
+) -> Result<model::ScrobblerImport, model::Error> {
+    let tracks = index_tracks(library);
+    let mut recorded: BTreeSet<(model::TrackId, time::SystemTime)> = history
+        .plays
+        .iter()
+        .map(|play| (play.track_id.clone(), play.started_at))
+        .collect();
+
+    let mut import = model::ScrobblerImport {
+        imported: 0,
+        already_recorded: 0,
+        unmatched: Vec::new(),
+    };
+    for entry in log.entries.iter() {
+        let track = match find_track(&tracks, entry) {
+            Some(track) => track,
+            None => {
+                import.unmatched.push(entry.clone());
+                continue;
+            }
+        };
+
+        let started_at = if log.utc {
+            entry.timestamp
+        } else {
+            shift(entry.timestamp, -(utc_offset_minutes as i64) * 60)
+        };
+        if !recorded.insert((track.id.clone(), started_at)) {
+            import.already_recorded += 1;
+            continue;
+        }
+
+        history.record(model::Play {
+            track_id: track.id.clone(),
+            artist: track.artist.clone(),
+            album: track.album.clone(),
+            track_title: track.track_title.clone(),
+            started_at,
+            // The log doesn't say how much of a skipped track was heard
+            played: if entry.listened {
+                entry.duration.or(track.duration).unwrap_or_default()
+            } else {
+                time::Duration::ZERO
+            },
+            skipped: !entry.listened,
+        })?;
+        import.imported += 1;
+    }
+
+    Ok(import)
+}
+
+type TrackIndex<'a> =
+    BTreeMap<(String, Option<String>, String), Vec<&'a model::AudioFileTrackMetadata>>;
+
+// Lowercased (artist, album, title) to the tracks with them,
+// where the artist may be the track's artist or the album artist
+fn index_tracks(library: &model::Library) -> TrackIndex<'_> {
+    let mut tracks = TrackIndex::new();
+    for artist in library.artists.values() {
+        for album in artist.albums.values() {
+            for track in album.discs.values().flat_map(|disc| disc.tracks.values()) {
+                let artist_names: BTreeSet<String> =
+                    [Some(&track.artist), track.album_artist.as_ref()]
+                        .into_iter()
+                        .flatten()
+                        .map(|name| normalize(name))
+                        .collect();
+                // Also without the album, for logs that leave it out
+                let album_names: BTreeSet<Option<String>> =
+                    [track.album.as_deref().map(normalize), None]
+                        .into_iter()
+                        .collect();
+                for artist_name in artist_names.iter() {
+                    for album_name in album_names.iter() {
+                        tracks
+                            .entry((
+                                artist_name.clone(),
+                                album_name.clone(),
+                                normalize(&track.track_title),
+                            ))
+                            .or_default()
+                            .push(track);
+                    }
+                }
+            }
+        }
+    }
+    tracks
+}
+
+fn find_track<'a>(
+    tracks: &TrackIndex<'a>,
+    entry: &model::ScrobblerEntry,
+) -> Option<&'a model::AudioFileTrackMetadata> {
+    let key = (
+        normalize(&entry.artist),
+        entry.album.as_deref().map(normalize),
+        normalize(&entry.track_title),
+    );
+    match tracks.get(&key)?.as_slice() {
+        [track] => Some(track),
+        // The same song on a few albums, and the log doesn't say which;
+        // the track number may still tell, but a guess would count plays for the wrong album
+        candidates => {
+            let mut numbered = candidates
+                .iter()
+                .filter(|track| entry.track.is_some() && track.track == entry.track);
+            match (numbered.next(), numbered.next()) {
+                (Some(track), None) => Some(track),
+                _ => None,
+            }
+        }
+    }
+}
+
+fn parse_entry(line: &str) -> Option<model::ScrobblerEntry> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() < 7 || fields.len() > 8 {
+        return None;
+    }
+    let optional = |value: &str| {
+        let value = value.trim();
+        if value.is_empty() {
+            None
+        } else {
+            Some(value.to_string())
+        }
+    };
+
+    let entry = model::ScrobblerEntry {
+        artist: optional(fields[0])?,
+        album: optional(fields[1]),
+        track_title: optional(fields[2])?,
+        track: match optional(fields[3]) {
+            Some(track) => Some(track.parse::<u32>().ok()?),
+            None => None,
+        },
+        duration: match optional(fields[4]) {
+            Some(seconds) => Some(time::Duration::from_secs(seconds.parse::<u64>().ok()?)),
+            None => None,
+        },
+        listened: match fields[5].trim() {
+            "L" => true,
+            "S" => false,
+            _ => return None,
+        },
+        timestamp: time::UNIX_EPOCH + time::Duration::from_secs(fields[6].trim().parse().ok()?),
+        musicbrainz_track_id: fields.get(7).and_then(|id| optional(id)),
+    };
+    Some(entry)
+}
+
+fn normalize(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+fn field(value: &str) -> String {
+    // Tabs and line breaks would end the field or the entry
+    value.replace(['\t', '\n', '\r'], " ")
+}
+
+fn unix_seconds(at: time::SystemTime) -> u64 {
+    at.duration_since(time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn shift(at: time::SystemTime, seconds: i64) -> time::SystemTime {
+    if seconds < 0 {
+        at.checked_sub(time::Duration::from_secs(seconds.unsigned_abs()))
+            .unwrap_or(time::UNIX_EPOCH)
+    } else {
+        at + time::Duration::from_secs(seconds as u64)
+    }
+}
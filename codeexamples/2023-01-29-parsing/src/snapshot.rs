@@ -0,0 +1,97 @@
+use std::fs;
+use std::path;
+use std::time;
+
+use crate::export::{LibraryJson, TrackJson};
+use crate::model;
+
+// Reads back the tracks of a library written by export::library_to_json;
+// the artists and albums around them are rebuilt by organizing the tracks again
+pub fn read_snapshot(
+    snapshot_path: &path::Path,
+) -> Result<Vec<model::AudioFileTrackMetadata>, model::Error> {
+    let bytes = fs::read(snapshot_path)?;
+    let invalid =
+        |message: String| model::Error::InvalidSnapshot(snapshot_path.to_path_buf(), message);
+
+    let snapshot: LibraryJson =
+        serde_json::from_slice(&bytes).map_err(|e| invalid(e.to_string()))?;
+
+    let mut tracks = Vec::new();
+    for artist in snapshot.artists.into_iter() {
+        for album in artist.albums.into_iter() {
+            for disc in album.discs.into_iter() {
+                for track in disc.tracks.into_iter() {
+                    tracks.push(read_track(track).map_err(invalid)?);
+                }
+            }
+        }
+    }
+
+    Ok(tracks)
+}
+
+fn read_track(track: TrackJson) -> Result<model::AudioFileTrackMetadata, String> {
+    Ok(model::AudioFileTrackMetadata {
+        id: model::TrackId(track.id),
+        musicbrainz: model::MusicBrainzIds {
+            release_track_id: track.musicbrainz.release_track_id,
+            album_id: track.musicbrainz.album_id,
+            album_artist_id: track.musicbrainz.album_artist_id,
+        },
+        artist: track.artist,
+        album_artist: track.album_artist,
+        album: track.album,
+        disc_no: track.disc_no,
+        disc_total: track.disc_total,
+        track: track.track,
+        track_total: track.track_total,
+        track_title: track.track_title,
+        genre: track.genre,
+        date: track.date,
+        duration: seconds(track.duration_seconds, "duration_seconds")?,
+        replay_gain: model::ReplayGain {
+            track_gain: track.replay_gain.track_gain,
+            track_peak: track.replay_gain.track_peak,
+            album_gain: track.replay_gain.album_gain,
+            album_peak: track.replay_gain.album_peak,
+        },
+        classical: model::ClassicalTags {
+            composer: track.classical.composer,
+            conductor: track.classical.conductor,
+            performer: track.classical.performer,
+            work: track.classical.work,
+            movement_name: track.classical.movement_name,
+            movement_number: track.classical.movement_number,
+        },
+        rating: track.rating,
+        // Exports leave out lyrics
+        lyrics: None,
+        full_path: path::PathBuf::from(track.full_path),
+        range: match track.range {
+            Some(range) => Some(model::TrackRange {
+                start: seconds(range.start_seconds, "start_seconds")?.unwrap_or_default(),
+                end: seconds(range.end_seconds, "end_seconds")?,
+            }),
+            None => None,
+        },
+        inferred_fields: track
+            .inferred_fields
+            .unwrap_or_default()
+            .iter()
+            .map(|name| {
+                model::TrackField::from_name(name)
+                    .ok_or_else(|| "unknown field in \"inferred_fields\"".to_string())
+            })
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+fn seconds(value: Option<f64>, key: &str) -> Result<Option<time::Duration>, String> {
+    match value {
+        // Durations can't be negative, and from_secs_f64 would panic
+        Some(n) if !n.is_finite() || n < 0.0 => Err(format!("expected a number in \"{}\"", key)),
+        Some(n) => Ok(Some(time::Duration::from_secs_f64(n))),
+        None => Ok(None),
+    }
+}
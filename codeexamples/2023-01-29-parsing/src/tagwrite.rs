@@ -0,0 +1,227 @@
+use std::fs;
+use std::path;
+
+use id3::TagLike;
+
+use crate::{model, ratings};
+
+const STREAMINFO: u8 = 0;
+const VORBIS_COMMENT: u8 = 4;
+
+struct MetadataBlock {
+    block_type: u8,
+    data: Vec<u8>,
+}
+
+pub fn write_flac_tags(path: &path::Path, tags: &[(&str, String)]) -> Result<(), model::Error> {
+    let keys: Vec<&str> = tags.iter().map(|(key, _)| *key).collect();
+    rewrite_flac_tags(path, &keys, tags)
+}
+
+pub fn remove_flac_tags(path: &path::Path, keys: &[&str]) -> Result<(), model::Error> {
+    rewrite_flac_tags(path, keys, &[])
+}
+
+fn rewrite_flac_tags(
+    path: &path::Path,
+    removed_keys: &[&str],
+    tags: &[(&str, String)],
+) -> Result<(), model::Error> {
+    let bytes = fs::read(path)?;
+    let (mut blocks, audio_offset) = read_metadata_blocks(&bytes, path)?;
+
+    let existing = blocks.iter().position(|b| b.block_type == VORBIS_COMMENT);
+    let (vendor, mut comments) = match existing {
+        Some(i) => parse_vorbis_comment(&blocks[i].data, path)?,
+        None => ("simpleaudioparser".to_string(), Vec::new()),
+    };
+
+    // Replace rather than add to any existing values for the keys being written
+    comments.retain(|comment| {
+        let key = comment.split('=').next().unwrap_or("");
+        !removed_keys.iter().any(|k| k.eq_ignore_ascii_case(key))
+    });
+    for (key, value) in tags.iter() {
+        comments.push(format!("{}={}", key, value));
+    }
+
+    let new_block = MetadataBlock {
+        block_type: VORBIS_COMMENT,
+        data: serialize_vorbis_comment(&vendor, &comments),
+    };
+    match existing {
+        Some(i) => blocks[i] = new_block,
+        // STREAMINFO must stay first, so a new comment block goes right after it
+        None => blocks.insert(1, new_block),
+    }
+
+    let mut output = Vec::with_capacity(bytes.len() + 1024);
+    output.extend_from_slice(b"fLaC");
+    let last_index = blocks.len() - 1;
+    for (index, block) in blocks.iter().enumerate() {
+        let last_flag = if index == last_index { 0x80 } else { 0x00 };
+        let length = block.data.len() as u32;
+        if length >= 1 << 24 {
+            return Err(model::Error::MalformedFlacMetadata(
+                path.to_path_buf(),
+                "metadata block too large",
+            ));
+        }
+        output.push(last_flag | block.block_type);
+        output.extend_from_slice(&length.to_be_bytes()[1..]);
+        output.extend_from_slice(&block.data);
+    }
+    output.extend_from_slice(&bytes[audio_offset..]);
+
+    // Write next to the original and swap it in,
+    // so an interrupted write never leaves a truncated file behind
+    let mut temporary_path = path.as_os_str().to_owned();
+    temporary_path.push(".tagwrite");
+    fs::write(&temporary_path, output)?;
+    fs::rename(&temporary_path, path)?;
+
+    Ok(())
+}
+
+pub fn write_mp3_rating(path: &path::Path, rating: Option<f64>) -> Result<(), model::Error> {
+    // An untagged file, whose tags were all inferred, gets a tag of its own
+    let mut tag = match id3::Tag::read_from_path(path) {
+        Ok(tag) => tag,
+        Err(id3::Error {
+            kind: id3::ErrorKind::NoTag,
+            ..
+        }) => id3::Tag::new(),
+        Err(e) => return Err(e.into()),
+    };
+
+    // Every player keeps a POPM frame of its own, with a play count that is left alone;
+    // "no@email" is what taggers without an address of their own write
+    let mut popularimeters: Vec<id3::frame::Popularimeter> = tag
+        .frames()
+        .filter_map(|frame| frame.content().popularimeter())
+        .cloned()
+        .collect();
+    if popularimeters.is_empty() {
+        popularimeters.push(id3::frame::Popularimeter {
+            user: "no@email".to_string(),
+            rating: 0,
+            counter: 0,
+        });
+    }
+    tag.remove("POPM");
+    for mut popularimeter in popularimeters.into_iter() {
+        popularimeter.rating = rating.map(ratings::to_popm).unwrap_or(0);
+        tag.add_frame(popularimeter);
+    }
+
+    // An FMPS rating left behind would win over the new one when the file is read again
+    let fmps_descriptions: Vec<String> = tag
+        .extended_texts()
+        .filter(|text| text.description.eq_ignore_ascii_case("fmps_rating"))
+        .map(|text| text.description.clone())
+        .collect();
+    for description in fmps_descriptions.iter() {
+        tag.remove_extended_text(Some(description), None);
+    }
+    if let Some(rating) = rating {
+        tag.add_frame(id3::frame::ExtendedText {
+            description: "FMPS_Rating".to_string(),
+            value: ratings::to_fmps(rating),
+        });
+    }
+
+    tag.write_to_path(path, tag.version())?;
+    Ok(())
+}
+
+fn read_metadata_blocks(
+    bytes: &[u8],
+    path: &path::Path,
+) -> Result<(Vec<MetadataBlock>, usize), model::Error> {
+    let invalid =
+        || model::Error::MalformedFlacMetadata(path.to_path_buf(), "malformed metadata block");
+
+    if bytes.len() < 4 || &bytes[0..4] != b"fLaC" {
+        return Err(invalid());
+    }
+
+    let mut blocks = Vec::new();
+    let mut offset = 4;
+    loop {
+        let header = bytes.get(offset..offset + 4).ok_or_else(invalid)?;
+        let is_last = header[0] & 0x80 != 0;
+        let block_type = header[0] & 0x7F;
+        let length = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        let data = bytes
+            .get(offset + 4..offset + 4 + length)
+            .ok_or_else(invalid)?;
+
+        blocks.push(MetadataBlock {
+            block_type,
+            data: data.to_vec(),
+        });
+        offset += 4 + length;
+
+        if is_last {
+            break;
+        }
+    }
+
+    if blocks.first().map(|b| b.block_type) != Some(STREAMINFO) {
+        return Err(invalid());
+    }
+
+    Ok((blocks, offset))
+}
+
+fn parse_vorbis_comment(
+    data: &[u8],
+    path: &path::Path,
+) -> Result<(String, Vec<String>), model::Error> {
+    let invalid =
+        || model::Error::MalformedFlacMetadata(path.to_path_buf(), "malformed vorbis comment");
+
+    // Unlike the rest of FLAC, the vorbis comment lengths are little endian
+    let mut offset = 0;
+    let read_string = |offset: &mut usize| -> Option<String> {
+        let length_bytes = data.get(*offset..*offset + 4)?;
+        let length = u32::from_le_bytes([
+            length_bytes[0],
+            length_bytes[1],
+            length_bytes[2],
+            length_bytes[3],
+        ]) as usize;
+        let value = data.get(*offset + 4..*offset + 4 + length)?;
+        *offset += 4 + length;
+        Some(String::from_utf8_lossy(value).to_string())
+    };
+
+    let vendor = read_string(&mut offset).ok_or_else(invalid)?;
+    let count_bytes = data.get(offset..offset + 4).ok_or_else(invalid)?;
+    let count = u32::from_le_bytes([
+        count_bytes[0],
+        count_bytes[1],
+        count_bytes[2],
+        count_bytes[3],
+    ]);
+    offset += 4;
+
+    let mut comments = Vec::new();
+    for _ in 0..count {
+        comments.push(read_string(&mut offset).ok_or_else(invalid)?);
+    }
+
+    Ok((vendor, comments))
+}
+
+fn serialize_vorbis_comment(vendor: &str, comments: &[String]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    data.extend_from_slice(vendor.as_bytes());
+    data.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+    for comment in comments.iter() {
+        data.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+        data.extend_from_slice(comment.as_bytes());
+    }
+    data
+}
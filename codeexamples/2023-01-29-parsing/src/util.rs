@@ -1,8 +1,14 @@
 use std::path;
 
-pub fn get_maybe_extension_string(p: &path::PathBuf) -> Option<String> {
+pub fn get_maybe_extension_string(p: &path::Path) -> Option<String> {
+    // Extensions we understand are plain ASCII,
+    // so a lossy conversion of any other name never matches by accident
     match p.extension() {
-        Some(v) => Some(v.to_str().unwrap().to_lowercase()),
+        Some(v) => Some(v.to_string_lossy().to_lowercase()),
         None => None,
     }
 }
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
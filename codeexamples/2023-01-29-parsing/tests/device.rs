@@ -0,0 +1,80 @@
+mod fixtures;
+
+use std::path;
+
+use simpleaudioparser::model;
+
+use fixtures::{track, TestLibrary};
+
+fn sync(
+    library: &model::Library,
+    device: &TestLibrary,
+    artist_names: &[&str],
+) -> model::DeviceSyncReport {
+    let options = model::DeviceSyncOptions {
+        selection: artist_names
+            .iter()
+            .map(|name| model::DeviceSelection::Artist(name.to_string()))
+            .collect(),
+        ..model::DeviceSyncOptions::default()
+    };
+    library.sync_to_device(&device.root, &options).unwrap()
+}
+
+fn copied(report: &model::DeviceSyncReport) -> Vec<&path::Path> {
+    report
+        .copied
+        .iter()
+        .map(|file| file.target_path.as_path())
+        .collect()
+}
+
+#[test]
+fn copies_only_what_changed_and_removes_what_is_no_longer_selected() {
+    let fixture = TestLibrary::new();
+    fixture.add_flac("A/x/1.flac", &track("A", "x", 1, 1, "One"));
+    fixture.add_flac("B/y/1.flac", &track("B", "y", 1, 1, "Two"));
+    let library = model::Library::from_library_directory(&fixture.root).unwrap();
+    let device = TestLibrary::new();
+
+    let report = sync(&library, &device, &["A", "B"]);
+    assert_eq!(
+        copied(&report),
+        [
+            path::Path::new("A/x/Disc 1/1.flac"),
+            path::Path::new("B/y/Disc 1/1.flac")
+        ]
+    );
+    assert!(report.removed.is_empty());
+
+    let report = sync(&library, &device, &["A", "B"]);
+    assert!(report.copied.is_empty());
+    assert_eq!(report.unchanged, 2);
+
+    let report = sync(&library, &device, &["A"]);
+    assert!(report.copied.is_empty());
+    assert_eq!(report.removed, [path::PathBuf::from("B/y/Disc 1/1.flac")]);
+    assert!(!device.path("B").exists());
+    assert!(device.path("A/x/Disc 1/1.flac").is_file());
+}
+
+#[test]
+fn files_a_sync_did_not_write_are_kept() {
+    let fixture = TestLibrary::new();
+    fixture.add_flac("A/x/1.flac", &track("A", "x", 1, 1, "One"));
+    fixture.add_flac("B/y/1.flac", &track("B", "y", 1, 1, "Two"));
+    let library = model::Library::from_library_directory(&fixture.root).unwrap();
+    let device = TestLibrary::new();
+    device.add_file("photo.jpg", "not ours");
+    device.add_file("Old/z/Disc 1/1.flac", "not ours either");
+
+    sync(&library, &device, &["A", "B"]);
+    device.add_file("B/y/Disc 1/photo.jpg", "put next to a synced file");
+
+    let report = sync(&library, &device, &["A"]);
+    assert_eq!(report.removed, [path::PathBuf::from("B/y/Disc 1/1.flac")]);
+    assert!(device.path("photo.jpg").is_file());
+    assert!(device.path("Old/z/Disc 1/1.flac").is_file());
+    assert!(device.path("B/y/Disc 1/photo.jpg").is_file());
+    assert!(!device.path("B/y/Disc 1/1.flac").exists());
+}
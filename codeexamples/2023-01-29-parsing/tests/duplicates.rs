@@ -0,0 +1,113 @@
+mod fixtures;
+
+use std::path;
+
+use simpleaudioparser::model;
+
+use fixtures::{track, TestLibrary};
+
+fn find_duplicates(library: &TestLibrary) -> model::DuplicateReport {
+    find_duplicates_with(library, &model::DuplicateOptions::default(), &mut |_| {})
+}
+
+fn find_duplicates_with(
+    library: &TestLibrary,
+    options: &model::DuplicateOptions,
+    events: &mut dyn FnMut(model::ScanEvent),
+) -> model::DuplicateReport {
+    model::DuplicateReport::from_library_directory(&library.root, options, events).unwrap()
+}
+
+#[test]
+fn finds_copies_of_a_recording_across_formats_and_albums() {
+    let library = TestLibrary::new();
+    library.add_mp3(
+        "Someone/Best Of/1.mp3",
+        &track(
+            "Someone feat. Other",
+            "Best Of",
+            1,
+            1,
+            "Song (Remastered 2011)",
+        )
+        .duration_ms(3500),
+    );
+    library.add_flac(
+        "Someone/First/1.flac",
+        &track("Someone", "First", 1, 1, "Song").duration_ms(3000),
+    );
+    // The same title, but far too long to be the same recording
+    library.add_flac(
+        "Someone/Live/1.flac",
+        &track("Someone", "Live", 1, 1, "Song").duration_ms(9000),
+    );
+    library.add_flac(
+        "Someone/First/2.flac",
+        &track("Someone", "First", 1, 2, "Other Song").duration_ms(3000),
+    );
+
+    let report = find_duplicates(&library);
+    assert_eq!(report.groups.len(), 1);
+    let group = &report.groups[0];
+    assert_eq!(group.track_title, "Song");
+    // The lossless copy comes first, as the one to keep
+    let found: Vec<(&str, &str)> = group
+        .entries
+        .iter()
+        .map(|entry| (entry.format.as_str(), entry.album.as_str()))
+        .collect();
+    assert_eq!(found, [("flac", "First"), ("mp3", "Best Of")]);
+    assert!(group
+        .entries
+        .iter()
+        .all(|entry| entry.fingerprint_similarity.is_none()));
+}
+
+#[test]
+fn different_recordings_are_not_duplicates() {
+    let library = TestLibrary::new();
+    library.add_flac("A/x/1.flac", &track("A", "x", 1, 1, "One"));
+    library.add_flac("B/x/1.flac", &track("B", "x", 1, 1, "One"));
+    library.add_flac("A/x/2.flac", &track("A", "x", 1, 2, "Two"));
+
+    assert!(find_duplicates(&library).groups.is_empty());
+}
+
+#[test]
+fn a_copy_that_cannot_be_fingerprinted_is_reported_and_kept() {
+    let library = TestLibrary::new();
+    let song = track("Someone", "First", 1, 1, "Song").duration_ms(3000);
+    library.add_flac("Someone/First/1.flac", &song);
+    // Tags that read fine in front of audio that does not decode
+    let mut damaged = fixtures::flac_bytes(&song.tag("ALBUM", "Best Of"));
+    let length = damaged.len();
+    damaged[length - 2..].copy_from_slice(&[0, 0]);
+    let damaged_path = library.add_file("Someone/Best Of/1.flac", damaged);
+
+    let options = model::DuplicateOptions {
+        compare_fingerprints: true,
+        ..model::DuplicateOptions::default()
+    };
+    let mut failed = Vec::new();
+    let report = find_duplicates_with(&library, &options, &mut |event| {
+        if let model::ScanEvent::Failed(path, _) = event {
+            failed.push(path);
+        }
+    });
+
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0], damaged_path);
+    assert_eq!(report.groups.len(), 1);
+    let similarities: Vec<(&path::Path, Option<f64>)> = report.groups[0]
+        .entries
+        .iter()
+        .map(|entry| (entry.full_path.as_path(), entry.fingerprint_similarity))
+        .collect();
+    assert_eq!(
+        similarities,
+        [
+            (damaged_path.as_path(), None),
+            (library.path("Someone/First/1.flac").as_path(), Some(1.0))
+        ]
+    );
+}
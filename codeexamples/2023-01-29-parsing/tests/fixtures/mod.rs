@@ -0,0 +1,454 @@
+// Writes tiny but valid FLAC and MP3 files with whatever tags and layout a test needs,
+// so the integration tests run without ffmpeg or audio files checked into the repo
+
+// Every test crate only uses some of what is here
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+use std::path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use id3::TagLike;
+use md5::{Digest, Md5};
+
+const SAMPLE_RATE: u64 = 44_100;
+const FLAC_BLOCK_SIZE: u64 = 4096;
+const MP3_SAMPLES_PER_FRAME: u64 = 1152;
+// MPEG-1 Layer III, 128 kbps, 44.1 kHz, mono, no CRC and no padding
+const MP3_FRAME_HEADER: [u8; 4] = [0xFF, 0xFB, 0x90, 0xC4];
+const MP3_FRAME_LENGTH: usize = 417;
+
+static NEXT_LIBRARY: AtomicUsize = AtomicUsize::new(0);
+
+// A library directory of its own for each test, removed again when dropped
+pub struct TestLibrary {
+    pub root: path::PathBuf,
+}
+
+impl TestLibrary {
+    pub fn new() -> Self {
+        // Tests run in parallel within one process, so the pid alone is not enough
+        let root = env::temp_dir().join(format!(
+            "simpleaudioparser-test-{}-{}",
+            process::id(),
+            NEXT_LIBRARY.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        TestLibrary { root }
+    }
+
+    pub fn path(&self, relative_path: &str) -> path::PathBuf {
+        self.root.join(relative_path)
+    }
+
+    pub fn add_flac(&self, relative_path: &str, track: &Track) -> path::PathBuf {
+        self.add_file(relative_path, flac_bytes(track))
+    }
+
+    pub fn add_mp3(&self, relative_path: &str, track: &Track) -> path::PathBuf {
+        self.add_file(relative_path, mp3_bytes(track))
+    }
+
+    pub fn add_file<B: AsRef<[u8]>>(&self, relative_path: &str, contents: B) -> path::PathBuf {
+        let full_path = self.path(relative_path);
+        if let Some(parent) = full_path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(&full_path, contents).unwrap();
+        full_path
+    }
+
+    #[cfg(unix)]
+    pub fn add_symlink(&self, relative_path: &str, target: &path::Path) -> path::PathBuf {
+        let full_path = self.path(relative_path);
+        if let Some(parent) = full_path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        std::os::unix::fs::symlink(target, &full_path).unwrap();
+        full_path
+    }
+}
+
+impl Drop for TestLibrary {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+// Tags use the Vorbis comment names, e.g. "TRACKNUMBER", and are mapped to ID3 frames for MP3
+#[derive(Clone)]
+pub struct Track {
+    pub tags: Vec<(String, String)>,
+    pub duration_ms: u64,
+    // None writes an MP3 with no ID3v2 tag at all
+    pub id3_version: Option<id3::Version>,
+    pub id3_encoding: id3::Encoding,
+    // FLAC only; with tone_channels left empty every sample is the same value
+    pub channels: u64,
+    // FLAC channels playing a 1 kHz tone, the others are silent
+    pub tone_channels: Vec<usize>,
+}
+
+// The tags nearly every track has; leave one out again with without()
+pub fn track(artist: &str, album: &str, disc: u32, number: u32, title: &str) -> Track {
+    Track::new()
+        .tag("ARTIST", artist)
+        .tag("ALBUM", album)
+        .tag("DISCNUMBER", disc)
+        .tag("TRACKNUMBER", number)
+        .tag("TITLE", title)
+}
+
+impl Track {
+    pub fn new() -> Self {
+        Track {
+            tags: Vec::new(),
+            duration_ms: 100,
+            id3_version: Some(id3::Version::Id3v24),
+            id3_encoding: id3::Encoding::UTF8,
+            channels: 2,
+            tone_channels: Vec::new(),
+        }
+    }
+
+    // Adding a key twice keeps both values, as Vorbis comments allow
+    pub fn tag<V: ToString>(mut self, key: &str, value: V) -> Self {
+        self.tags.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn without(mut self, key: &str) -> Self {
+        self.tags.retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+        self
+    }
+
+    pub fn duration_ms(mut self, duration_ms: u64) -> Self {
+        self.duration_ms = duration_ms;
+        self
+    }
+
+    // A FLAC with real audio on some channels, for measuring loudness;
+    // a constant value is filtered out as silence
+    pub fn tone(mut self, channels: u64, tone_channels: &[usize]) -> Self {
+        self.channels = channels;
+        self.tone_channels = tone_channels.to_vec();
+        self
+    }
+
+    pub fn id3(mut self, version: Option<id3::Version>, encoding: id3::Encoding) -> Self {
+        self.id3_version = version;
+        self.id3_encoding = encoding;
+        self
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+
+    // Tracks with different tags get different audio, so they don't share a content id
+    fn audio_seed(&self) -> u32 {
+        let mut hash: u32 = 0x811c_9dc5;
+        for (key, value) in self.tags.iter() {
+            for byte in key.bytes().chain(value.bytes()) {
+                hash = (hash ^ byte as u32).wrapping_mul(0x0100_0193);
+            }
+        }
+        hash
+    }
+}
+
+impl Default for Track {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn flac_bytes(track: &Track) -> Vec<u8> {
+    let channels = track.channels;
+    let total_samples = track.duration_ms * SAMPLE_RATE / 1000;
+    // Without a tone every sample is the same, so each subframe is a single CONSTANT value
+    let value = (track.audio_seed() % 2000) as i16 - 1000;
+    // With one, the seed shifts its phase so the audio still differs between tracks
+    let phase = (track.audio_seed() % 44) as u64;
+    let sample = |index: u64, channel: usize| {
+        if track.tone_channels.is_empty() {
+            value
+        } else if track.tone_channels.contains(&channel) {
+            let time = (index + phase) as f64 / SAMPLE_RATE as f64;
+            ((2.0 * std::f64::consts::PI * 1000.0 * time).sin() * 16000.0) as i16
+        } else {
+            0
+        }
+    };
+
+    let mut hasher = Md5::new();
+    for index in 0..total_samples {
+        for channel in 0..channels as usize {
+            hasher.update(sample(index, channel).to_le_bytes());
+        }
+    }
+
+    let mut bytes = b"fLaC".to_vec();
+
+    let mut streaminfo = Vec::new();
+    streaminfo.extend_from_slice(&(FLAC_BLOCK_SIZE as u16).to_be_bytes());
+    streaminfo.extend_from_slice(&(FLAC_BLOCK_SIZE as u16).to_be_bytes());
+    // Minimum and maximum frame sizes, left unknown
+    streaminfo.extend_from_slice(&[0; 6]);
+    let packed = (SAMPLE_RATE << 44) | ((channels - 1) << 41) | (15 << 36) | total_samples;
+    streaminfo.extend_from_slice(&packed.to_be_bytes());
+    streaminfo.extend_from_slice(&hasher.finalize());
+    push_flac_metadata_block(&mut bytes, 0, false, &streaminfo);
+
+    let mut comments = Vec::new();
+    let vendor = b"simpleaudioparser fixtures";
+    comments.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    comments.extend_from_slice(vendor);
+    comments.extend_from_slice(&(track.tags.len() as u32).to_le_bytes());
+    for (key, value) in track.tags.iter() {
+        let comment = format!("{}={}", key, value);
+        comments.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+        comments.extend_from_slice(comment.as_bytes());
+    }
+    push_flac_metadata_block(&mut bytes, 4, true, &comments);
+
+    let mut frame_number = 0;
+    let mut first_sample = 0;
+    while first_sample < total_samples {
+        let block_size = (total_samples - first_sample).min(FLAC_BLOCK_SIZE);
+        let subframes: Vec<Vec<i16>> = (0..channels as usize)
+            .map(|channel| {
+                (first_sample..first_sample + block_size)
+                    .map(|index| sample(index, channel))
+                    .collect()
+            })
+            .collect();
+        bytes.extend(flac_frame(frame_number, &subframes));
+        first_sample += block_size;
+        frame_number += 1;
+    }
+
+    bytes
+}
+
+fn push_flac_metadata_block(bytes: &mut Vec<u8>, block_type: u8, is_last: bool, data: &[u8]) {
+    bytes.push(if is_last { 0x80 } else { 0 } | block_type);
+    bytes.extend_from_slice(&(data.len() as u32).to_be_bytes()[1..]);
+    bytes.extend_from_slice(data);
+}
+
+fn flac_frame(frame_number: u32, subframes: &[Vec<i16>]) -> Vec<u8> {
+    let channels = subframes.len() as u64;
+    let block_size = subframes[0].len() as u64;
+    // Fixed block size, then a 16 bit block size at the end of the header and 44.1 kHz;
+    // independent channels of 16 bit samples
+    let mut frame = vec![0xFF, 0xF8, 0x79, (((channels - 1) as u8) << 4) | 0x08];
+    frame.extend(utf8_coded(frame_number));
+    frame.extend_from_slice(&((block_size - 1) as u16).to_be_bytes());
+    frame.push(crc8(&frame));
+
+    for samples in subframes.iter() {
+        if samples.iter().all(|sample| *sample == samples[0]) {
+            // A CONSTANT subframe without wasted bits
+            frame.push(0x00);
+            frame.extend_from_slice(&samples[0].to_be_bytes());
+        } else {
+            // A VERBATIM one, 16 bits a sample so it stays byte aligned
+            frame.push(0x02);
+            for sample in samples.iter() {
+                frame.extend_from_slice(&sample.to_be_bytes());
+            }
+        }
+    }
+
+    let crc = crc16(&frame);
+    frame.extend_from_slice(&crc.to_be_bytes());
+    frame
+}
+
+fn utf8_coded(number: u32) -> Vec<u8> {
+    match number {
+        0..=0x7F => vec![number as u8],
+        0x80..=0x7FF => vec![0xC0 | (number >> 6) as u8, 0x80 | (number & 0x3F) as u8],
+        _ => vec![
+            0xE0 | (number >> 12) as u8,
+            0x80 | ((number >> 6) & 0x3F) as u8,
+            0x80 | (number & 0x3F) as u8,
+        ],
+    }
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    let mut crc: u8 = 0;
+    for byte in bytes {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+pub fn mp3_bytes(track: &Track) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    if let Some(version) = track.id3_version {
+        id3_tag(track).write_to(&mut bytes, version).unwrap();
+    }
+
+    // The side information is all zero, so the frames decode to silence and
+    // the bytes after it are ancillary data, where the seed makes the audio unique
+    let frame_count = (track.duration_ms * SAMPLE_RATE / 1000).div_ceil(MP3_SAMPLES_PER_FRAME);
+    for _ in 0..frame_count.max(1) {
+        let mut frame = vec![0; MP3_FRAME_LENGTH];
+        frame[..4].copy_from_slice(&MP3_FRAME_HEADER);
+        frame[MP3_FRAME_LENGTH - 4..].copy_from_slice(&track.audio_seed().to_be_bytes());
+        bytes.extend(frame);
+    }
+
+    bytes
+}
+
+fn id3_tag(track: &Track) -> id3::Tag {
+    let mut tag = id3::Tag::new();
+    let numbered = |number: &str, total: Option<&str>| match total {
+        Some(total) => format!("{}/{}", number, total),
+        None => number.to_string(),
+    };
+
+    for (key, value) in track.tags.iter() {
+        let frame = match key.to_uppercase().as_str() {
+            "ARTIST" => id3::Frame::text("TPE1", value),
+            "ALBUMARTIST" => id3::Frame::text("TPE2", value),
+            "ALBUM" => id3::Frame::text("TALB", value),
+            "TITLE" => id3::Frame::text("TIT2", value),
+            "GENRE" => id3::Frame::text("TCON", value),
+            "DATE" => id3::Frame::text("TYER", value),
+            "TRACKNUMBER" => id3::Frame::text("TRCK", numbered(value, track.get("TRACKTOTAL"))),
+            "DISCNUMBER" => id3::Frame::text("TPOS", numbered(value, track.get("DISCTOTAL"))),
+            "TRACKTOTAL" | "DISCTOTAL" => continue,
+            "COMPOSER" => id3::Frame::text("TCOM", value),
+            "CONDUCTOR" => id3::Frame::text("TPE3", value),
+            "MOVEMENTNAME" => undecoded_text_frame("MVNM", value, track.id3_encoding),
+            "MOVEMENT" => undecoded_text_frame("MVIN", value, track.id3_encoding),
+            // Not a Vorbis comment, but the raw rating of a POPM frame
+            "POPM" => id3::Frame::from(id3::frame::Popularimeter {
+                user: "no@email".to_string(),
+                rating: value.parse().unwrap(),
+                counter: 0,
+            }),
+            "LYRICS" => id3::Frame::with_content(
+                "USLT",
+                id3::Content::Lyrics(id3::frame::Lyrics {
+                    lang: "eng".to_string(),
+                    description: String::new(),
+                    text: value.clone(),
+                }),
+            ),
+            _ => id3::Frame::with_content(
+                "TXXX",
+                id3::Content::ExtendedText(id3::frame::ExtendedText {
+                    description: key.clone(),
+                    value: value.clone(),
+                }),
+            ),
+        };
+        tag.add_frame(frame.set_encoding(Some(track.id3_encoding)));
+    }
+
+    tag
+}
+
+fn undecoded_text_frame(id: &str, value: &str, encoding: id3::Encoding) -> id3::Frame {
+    // The id3 crate has no text content for these iTunes frames, so they are written as is
+    let mut data = Vec::new();
+    match encoding {
+        id3::Encoding::Latin1 => {
+            data.push(0);
+            data.extend(value.chars().map(|c| c as u8));
+        }
+        id3::Encoding::UTF16 => {
+            data.extend([1, 0xFF, 0xFE]);
+            data.extend(value.encode_utf16().flat_map(|unit| unit.to_le_bytes()));
+        }
+        id3::Encoding::UTF16BE => {
+            data.push(2);
+            data.extend(value.encode_utf16().flat_map(|unit| unit.to_be_bytes()));
+        }
+        id3::Encoding::UTF8 => {
+            data.push(3);
+            data.extend(value.as_bytes());
+        }
+    }
+    id3::Frame::with_content(
+        id,
+        id3::Content::Unknown(id3::frame::Unknown {
+            data,
+            version: id3::Version::Id3v24,
+        }),
+    )
+}
+
+// The same layout generate_flac_library.sh makes with ffmpeg
+pub fn demo_library() -> TestLibrary {
+    let library = TestLibrary::new();
+    let chillest = |disc: u32, number: u32, title: &str| {
+        track("Chillest", "Songs To Dream To", disc, number, title)
+            .tag("DATE", 2021)
+            .tag("DISCTOTAL", 2)
+    };
+    let rockers = |number: u32, title: &str| {
+        track("The Rockers", "Party Time", 1, number, title)
+            .without("DISCNUMBER")
+            .tag("DATE", 2008)
+    };
+
+    for (disc, number, title) in [
+        (1, 1, "Lying There"),
+        (1, 2, "Heavy Eyelids"),
+        (1, 3, "Dozing"),
+        (2, 1, "Enter the Dream"),
+        (2, 2, "The Adventure"),
+        (2, 3, "Sunlight"),
+    ] {
+        library.add_flac(
+            &format!(
+                "Chillest/Songs To Dream To/Disc {}/{}.{}.flac",
+                disc, number, title
+            ),
+            &chillest(disc, number, title),
+        );
+    }
+    for (number, title) in [(1, "Intro"), (2, "The Hit"), (3, "Outro")] {
+        library.add_flac(
+            &format!("The Rockers/Party Time/{}.{}.flac", number, title),
+            &rockers(number, title),
+        );
+    }
+
+    library
+}
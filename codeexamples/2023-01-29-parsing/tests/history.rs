@@ -0,0 +1,254 @@
+mod fixtures;
+
+use std::time;
+
+use simpleaudioparser::model;
+
+use fixtures::{track, TestLibrary};
+
+fn parse_all(library: &TestLibrary) -> Vec<model::AudioFileTrackMetadata> {
+    let options = model::ScanOptions::default();
+    let mut tracks: Vec<model::AudioFileTrackMetadata> =
+        model::TrackStream::from_library_directory(&library.root, &options, &mut |_| {})
+            .collect::<Result<_, _>>()
+            .unwrap();
+    tracks.sort_by(|a, b| a.full_path.cmp(&b.full_path));
+    tracks
+}
+
+fn at(seconds: u64) -> time::SystemTime {
+    time::UNIX_EPOCH + time::Duration::from_secs(seconds)
+}
+
+fn secs(seconds: u64) -> time::Duration {
+    time::Duration::from_secs(seconds)
+}
+
+#[test]
+fn tracker_turns_player_reports_into_plays() {
+    let library = TestLibrary::new();
+    library.add_flac("1.flac", &track("A", "x", 1, 1, "One"));
+    library.add_flac("2.flac", &track("A", "x", 1, 2, "Two"));
+    let mut tracks = parse_all(&library);
+    tracks[0].duration = Some(secs(300));
+    tracks[1].duration = Some(secs(600));
+
+    let mut tracker = model::PlayTracker::new();
+    assert_eq!(tracker.started(&tracks[0], at(1000)), None);
+    tracker.progress(secs(100));
+    // Less than half of it was heard
+    let skipped = tracker.started(&tracks[1], at(1100)).unwrap();
+    assert_eq!(skipped.track_id, tracks[0].id);
+    assert_eq!(skipped.started_at, at(1000));
+    assert_eq!(skipped.played, secs(100));
+    assert!(skipped.skipped);
+
+    // Four minutes is enough for a long track
+    tracker.progress(secs(250));
+    let listened = tracker.stopped().unwrap();
+    assert_eq!(listened.track_title, "Two");
+    assert!(!listened.skipped);
+    assert_eq!(tracker.stopped(), None);
+
+    tracker.started(&tracks[0], at(2000));
+    tracker.progress(secs(299));
+    let ended = tracker.ended().unwrap();
+    assert_eq!(ended.played, secs(300));
+    assert!(!ended.skipped);
+}
+
+#[test]
+fn history_counts_plays_and_is_kept_in_a_file() {
+    let library = TestLibrary::new();
+    let history_path = library.path("history.log");
+    let play = |id: &str, started_at: u64, skipped: bool| model::Play {
+        track_id: model::TrackId(id.to_string()),
+        artist: "A\tB".to_string(),
+        album: None,
+        track_title: "Line\\\nbreak".to_string(),
+        started_at: at(started_at),
+        played: time::Duration::from_millis(61500),
+        skipped,
+    };
+
+    let mut history = model::ListeningHistory::open(&history_path).unwrap();
+    history.record(play("audio:1", 100, false)).unwrap();
+    history.record(play("audio:2", 200, true)).unwrap();
+    history.record(play("audio:1", 300, true)).unwrap();
+    history.record(play("audio:1", 400, false)).unwrap();
+    // Counted as the plays come in, the same as when the file is read back
+    let recorded_stats = history.all_stats().clone();
+
+    let history = model::ListeningHistory::open(&history_path).unwrap();
+    assert_eq!(*history.all_stats(), recorded_stats);
+    assert_eq!(history.plays[0], play("audio:1", 100, false));
+    assert_eq!(
+        history.stats(&model::TrackId("audio:1".to_string())),
+        model::TrackPlayStats {
+            play_count: 2,
+            skip_count: 1,
+            last_played: Some(at(400)),
+        }
+    );
+    assert_eq!(history.all_stats().len(), 2);
+    assert_eq!(
+        history.stats(&model::TrackId("audio:3".to_string())),
+        model::TrackPlayStats::default()
+    );
+
+    let started: Vec<time::SystemTime> = history
+        .plays_between(at(200), at(400))
+        .iter()
+        .map(|play| play.started_at)
+        .collect();
+    assert_eq!(started, [at(200), at(300)]);
+}
+
+#[test]
+fn broken_history_is_an_error() {
+    let library = TestLibrary::new();
+    let history_path = library.add_file("history.log", "100\t5\tX\taudio:1\tA\t\tOne\n");
+
+    assert!(matches!(
+        model::ListeningHistory::open(history_path),
+        Err(model::Error::InvalidListeningHistory(_, 1))
+    ));
+}
+
+#[test]
+fn context_playlists_rank_tracks_played_at_those_times() {
+    let library = TestLibrary::new();
+    library.add_flac("1.flac", &track("A", "x", 1, 1, "One"));
+    library.add_flac("2.flac", &track("A", "x", 1, 2, "Two"));
+    library.add_flac("3.flac", &track("A", "x", 1, 3, "Three"));
+    let parsed = model::Library::from_library_directory(&library.root).unwrap();
+    let ids: Vec<model::TrackId> = parse_all(&library).into_iter().map(|t| t.id).collect();
+
+    let mut history = model::ListeningHistory::open(library.path("history.log")).unwrap();
+    let mut record = |id: &model::TrackId, started_at: u64, skipped: bool| {
+        let play = model::Play {
+            track_id: id.clone(),
+            artist: "A".to_string(),
+            album: Some("x".to_string()),
+            track_title: String::new(),
+            started_at: at(started_at),
+            played: secs(200),
+            skipped,
+        };
+        history.record(play).unwrap();
+    };
+    // Saturday 3 January 2015, 19:30 UTC
+    record(&ids[0], 1420313400, false);
+    record(&ids[0], 1420313400 + 60, false);
+    // Sunday 22:30 and 23:30 UTC, the second already Monday an hour ahead
+    record(&ids[1], 1420410600, false);
+    record(&ids[1], 1420414200, false);
+    // Saturday 10 July 2021, 18:00 UTC
+    record(&ids[2], 1625940000, false);
+    record(&ids[2], 1625940000 + 300, true);
+    record(&model::TrackId("audio:gone".to_string()), 1625940000, false);
+
+    let rules = model::PlaylistRules::parse(
+        "# times are in CET\n\
+         utc-offset +01:00\n\
+         Weekend Evenings: days sat-sun; hours 18-24\n\
+         2010s Winter: seasons winter; years 2010s; limit 1\n",
+    )
+    .unwrap();
+    let playlists = rules.generate(&history, &parsed);
+    let names: Vec<&str> = playlists.iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(names, ["Weekend Evenings", "2010s Winter"]);
+    // Equally often played tracks go by which was played last
+    assert_eq!(
+        playlists[0].tracks,
+        [
+            (ids[0].clone(), 2),
+            (ids[2].clone(), 1),
+            (ids[1].clone(), 1)
+        ]
+    );
+    assert_eq!(playlists[1].tracks, [(ids[1].clone(), 2)]);
+
+    assert_eq!(model::PlaylistRules::default().playlists.len(), 5);
+    for broken in [
+        "Nothing: hours 5-5",
+        "Weekend sat-sun",
+        "Colours: colours red",
+    ] {
+        assert!(matches!(
+            model::PlaylistRules::parse(broken),
+            Err(model::Error::InvalidPlaylistRules(1, _))
+        ));
+    }
+}
+
+#[test]
+fn scrobbler_logs_are_imported_and_exported() {
+    let library = TestLibrary::new();
+    library.add_flac("x/1.flac", &track("A", "x", 1, 1, "One"));
+    library.add_flac("x/2.flac", &track("A", "x", 1, 2, "Two"));
+    library.add_flac("y/1.flac", &track("A", "y", 1, 1, "Two"));
+    let parsed = model::Library::from_library_directory(&library.root).unwrap();
+    let ids: Vec<model::TrackId> = parse_all(&library).into_iter().map(|t| t.id).collect();
+
+    let log_path = library.add_file(
+        "scrobbler.log",
+        "#AUDIOSCROBBLER/1.1\n\
+         #TZ/UNKNOWN\n\
+         #CLIENT/Rockbox sansaclipplus $Revision$\n\
+         A\tx\tOne\t1\t200\tL\t1420317000\t\n\
+         a\t\tone\t\t200\tS\t1420317300\t\n\
+         A\t\tTwo\t\t100\tL\t1420317600\t\n\
+         A\t\tTwo\t2\t100\tL\t1420317700\t\n\
+         B\tz\tNope\t1\t100\tL\t1420317800\t\n",
+    );
+    let log = model::ScrobblerLog::read_from_file(&log_path).unwrap();
+    assert!(!log.utc);
+    assert_eq!(log.entries.len(), 5);
+
+    let mut history = model::ListeningHistory::open(library.path("history.log")).unwrap();
+    let import = history.import_scrobbler_log(&log, &parsed, 60).unwrap();
+    assert_eq!(import.imported, 3);
+    // Two is on both albums, so only the entry with its track number can be told apart
+    let unmatched: Vec<&str> = import
+        .unmatched
+        .iter()
+        .map(|e| e.track_title.as_str())
+        .collect();
+    assert_eq!(unmatched, ["Two", "Nope"]);
+
+    let plays: Vec<(&model::TrackId, time::SystemTime, time::Duration, bool)> = history
+        .plays
+        .iter()
+        .map(|play| (&play.track_id, play.started_at, play.played, play.skipped))
+        .collect();
+    // The player's clock was an hour ahead of UTC
+    assert_eq!(
+        plays,
+        [
+            (&ids[0], at(1420313400), secs(200), false),
+            (&ids[0], at(1420313700), secs(0), true),
+            (&ids[1], at(1420314100), secs(100), false),
+        ]
+    );
+
+    let again = history.import_scrobbler_log(&log, &parsed, 60).unwrap();
+    assert_eq!((again.imported, again.already_recorded), (0, 3));
+
+    let export_path = library.path("exported.log");
+    model::ScrobblerLog::from_history(&history, &parsed)
+        .write_to_file(&export_path)
+        .unwrap();
+    let exported = model::ScrobblerLog::read_from_file(&export_path).unwrap();
+    assert!(exported.utc);
+    assert_eq!(exported.entries[2].album.as_deref(), Some("x"));
+    assert_eq!(exported.entries[2].track, Some(2));
+    assert_eq!(exported.entries[2].timestamp, at(1420314100));
+    assert!(!exported.entries[1].listened);
+
+    let broken_path = library.add_file("broken.log", "#TZ/UTC\nA\tx\tOne\t1\t200\tQ\t1\t\n");
+    assert!(matches!(
+        model::ScrobblerLog::read_from_file(broken_path),
+        Err(model::Error::InvalidScrobblerLog(_, 2))
+    ));
+}
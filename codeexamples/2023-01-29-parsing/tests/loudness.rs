@@ -0,0 +1,86 @@
+mod fixtures;
+
+use std::path;
+
+use simpleaudioparser::model;
+
+use fixtures::{track, TestLibrary};
+
+fn measure(library: &TestLibrary) -> Vec<(path::PathBuf, Option<f64>)> {
+    let mut parsed = model::Library::from_library_directory(&library.root).unwrap();
+    let mut results: Vec<(path::PathBuf, Option<f64>)> = parsed
+        .fill_missing_replay_gain(&model::LoudnessScanOptions::default())
+        .unwrap()
+        .into_iter()
+        .map(|result| (result.full_path, result.integrated_loudness))
+        .collect();
+    results.sort_by(|a, b| a.0.cmp(&b.0));
+    results
+}
+
+#[test]
+fn lfe_channel_is_left_out_of_the_loudness() {
+    let library = TestLibrary::new();
+    let tone = |album: &str, tone_channels: &[usize]| {
+        track("A", album, 1, 1, album)
+            .duration_ms(1000)
+            .tone(6, tone_channels)
+    };
+    library.add_flac("A/front/1.flac", &tone("front", &[0]));
+    library.add_flac("A/front and lfe/1.flac", &tone("front and lfe", &[0, 3]));
+    library.add_flac("A/lfe/1.flac", &tone("lfe", &[3]));
+    library.add_flac("A/surround/1.flac", &tone("surround", &[4]));
+
+    let results = measure(&library);
+    let loudness: Vec<Option<f64>> = results.iter().map(|(_, loudness)| *loudness).collect();
+    let front = loudness[0].unwrap();
+    // The tone on the LFE adds nothing, and on its own it is as good as silence
+    assert!((loudness[1].unwrap() - front).abs() < 0.01);
+    assert_eq!(loudness[2], None);
+    // A surround channel is weighted 1.41, about 1.5 LU louder
+    assert!((loudness[3].unwrap() - front - 1.49).abs() < 0.05);
+}
+
+#[test]
+fn a_file_that_cannot_be_measured_is_reported_and_the_rest_are_measured() {
+    let library = TestLibrary::new();
+    let tone = |album: &str, n: u32| {
+        track("A", album, 1, n, album)
+            .duration_ms(1000)
+            .tone(2, &[0])
+    };
+    library.add_flac("A/good/1.flac", &tone("good", 1));
+    library.add_flac("A/mixed/1.flac", &tone("mixed", 1));
+    let mut damaged = fixtures::flac_bytes(&tone("mixed", 2));
+    let length = damaged.len();
+    damaged[length - 2..].copy_from_slice(&[0, 0]);
+    let damaged_path = library.add_file("A/mixed/2.flac", damaged);
+
+    let mut parsed = model::Library::from_library_directory(&library.root).unwrap();
+    let mut results = parsed
+        .fill_missing_replay_gain(&model::LoudnessScanOptions::default())
+        .unwrap();
+    results.sort_by(|a, b| a.full_path.cmp(&b.full_path));
+
+    let paths: Vec<&path::Path> = results.iter().map(|r| r.full_path.as_path()).collect();
+    assert_eq!(
+        paths,
+        [
+            library.path("A/good/1.flac").as_path(),
+            library.path("A/mixed/1.flac").as_path(),
+            damaged_path.as_path()
+        ]
+    );
+    let good = &results[0];
+    assert!(good.error.is_none());
+    assert!(good.replay_gain.track_gain.is_some());
+    assert!(good.replay_gain.album_gain.is_some());
+    // The readable track of the damaged album gets its own gain but no album gain
+    let mixed = &results[1];
+    assert!(mixed.error.is_none());
+    assert!(mixed.replay_gain.track_gain.is_some());
+    assert_eq!(mixed.replay_gain.album_gain, None);
+    let damaged = &results[2];
+    assert!(damaged.error.is_some());
+    assert_eq!(damaged.integrated_loudness, None);
+}
@@ -0,0 +1,351 @@
+mod fixtures;
+
+use std::path;
+use std::time;
+
+use simpleaudioparser::model;
+
+use fixtures::{track, TestLibrary};
+
+fn album<'a>(library: &'a model::Library, artist_name: &str, album_name: &str) -> &'a model::Album {
+    &library.artists[artist_name].albums[album_name]
+}
+
+#[test]
+fn organizes_the_demo_library() {
+    let fixture = fixtures::demo_library();
+    let library = model::Library::from_library_directory(&fixture.root).unwrap();
+
+    let artist_names: Vec<&String> = library.artists.keys().collect();
+    assert_eq!(artist_names, ["Chillest", "The Rockers"]);
+
+    let dream = album(&library, "Chillest", "Songs To Dream To");
+    assert_eq!(dream.discs.len(), 2);
+    assert_eq!(dream.track_count, 6);
+    assert_eq!(dream.duration, time::Duration::from_millis(600));
+    assert_eq!(dream.date.as_deref(), Some("2021"));
+    assert_eq!(dream.discs[&2].tracks[&1].track_title, "Enter the Dream");
+    assert!(dream.warnings.is_empty());
+
+    // Without a disc number every track is on disc 1
+    let party = album(&library, "The Rockers", "Party Time");
+    let titles: Vec<&str> = party.discs[&1]
+        .tracks
+        .values()
+        .map(|t| t.track_title.as_str())
+        .collect();
+    assert_eq!(titles, ["Intro", "The Hit", "Outro"]);
+
+    assert_eq!(library.track_ids.len(), 9);
+    assert_eq!(library.album_ids.len(), 2);
+}
+
+#[test]
+fn conflicting_tracks_fail_the_library() {
+    let fixture = TestLibrary::new();
+    fixture.add_flac("A/x/1.flac", &track("A", "x", 1, 1, "First"));
+    fixture.add_mp3("A/x/1 (copy).mp3", &track("A", "x", 1, 1, "Second"));
+
+    match model::Library::from_library_directory(&fixture.root) {
+        Err(model::Error::ConflictingTrack(artist, album, 1, 1, _, _)) => {
+            assert_eq!((artist.as_str(), album.as_str()), ("A", "x"));
+        }
+        Err(e) => panic!("unexpected error {:?}", e),
+        Ok(_) => panic!("the conflict went unnoticed"),
+    }
+}
+
+#[test]
+fn failures_are_reported_and_the_rest_is_kept() {
+    let fixture = TestLibrary::new();
+    fixture.add_flac("A/x/1.flac", &track("A", "x", 1, 1, "First"));
+    fixture.add_flac("A/x/2.flac", &track("A", "x", 1, 1, "Second"));
+    fixture.add_flac("A/x/3.flac", &track("A", "x", 1, 3, "Third"));
+    fixture.add_file("A/x/4.flac", "not really a flac file");
+
+    let mut failed = Vec::new();
+    let library = model::Library::from_library_directory_with_options(
+        &fixture.root,
+        &model::ScanOptions::default(),
+        &mut |event| {
+            if let model::ScanEvent::Failed(path, e) = event {
+                failed.push((path, e));
+            }
+        },
+    )
+    .unwrap();
+
+    assert_eq!(album(&library, "A", "x").track_count, 2);
+    // Files are read in directory order, so either copy of track 1 may be the one refused
+    let mut failed_paths: Vec<&path::PathBuf> = failed.iter().map(|(path, _)| path).collect();
+    failed_paths.sort();
+    assert_eq!(failed_paths.len(), 2);
+    assert_eq!(*failed_paths[1], fixture.path("A/x/4.flac"));
+    assert_eq!(
+        failed
+            .iter()
+            .filter(|(_, e)| matches!(e, model::Error::ConflictingTrack(..)))
+            .count(),
+        1
+    );
+
+    // Without an event sink the first failure is the result
+    assert!(model::Library::from_library_directory(&fixture.root).is_err());
+}
+
+#[test]
+fn builder_keeps_the_first_of_conflicting_tracks() {
+    let fixture = TestLibrary::new();
+    fixture.add_flac("A/x/1.flac", &track("A", "x", 1, 1, "First"));
+    fixture.add_flac("A/x/2.flac", &track("A", "x", 1, 2, "Other"));
+    fixture.add_flac("A/y/1.flac", &track("A", "x", 1, 1, "Second"));
+
+    let options = model::ScanOptions::default();
+    let mut builder = model::LibraryBuilder::new();
+    let mut conflicts = 0;
+    for track in model::TrackStream::from_library_directory(&fixture.root, &options, &mut |_| {}) {
+        if let Err(e) = builder.add_track(track.unwrap()) {
+            assert!(matches!(e, model::Error::ConflictingTrack(..)));
+            conflicts += 1;
+        }
+    }
+    let library = builder.finish();
+
+    assert_eq!(conflicts, 1);
+    let x = album(&library, "A", "x");
+    assert_eq!(x.track_count, 2);
+    assert_eq!(x.discs[&1].tracks[&1].track_title, "First");
+}
+
+#[test]
+fn builder_shows_artists_before_indexing() {
+    let fixture = fixtures::demo_library();
+    let options = model::ScanOptions::default();
+    let mut events = |_| {};
+    let mut tracks =
+        model::TrackStream::from_library_directory(&fixture.root, &options, &mut events);
+
+    let mut builder = model::LibraryBuilder::new();
+    builder.add_track(tracks.next().unwrap().unwrap()).unwrap();
+    assert_eq!(builder.library().artists.len(), 1);
+    assert!(builder.library().track_ids.is_empty());
+
+    builder.index();
+    assert_eq!(builder.library().track_ids.len(), 1);
+
+    for track in tracks {
+        builder.add_track(track.unwrap()).unwrap();
+        builder.index();
+    }
+    let streamed = builder.finish();
+    let scanned = model::Library::from_library_directory(&fixture.root).unwrap();
+    assert_eq!(streamed.to_json(), scanned.to_json());
+}
+
+#[test]
+fn json_exports_read_back_as_snapshots() {
+    let fixture = fixtures::demo_library();
+    let library = model::Library::from_library_directory(&fixture.root).unwrap();
+    let exported = library.to_json();
+    let snapshot_path = fixture.add_file("snapshot.json", &exported);
+
+    let snapshot = model::Library::from_json_snapshot(snapshot_path).unwrap();
+    assert_eq!(snapshot.to_json(), exported);
+
+    let broken_path = fixture.add_file("broken.json", "{\"artists\": [{\"id\": 1}]}");
+    assert!(matches!(
+        model::Library::from_json_snapshot(broken_path),
+        Err(model::Error::InvalidSnapshot(..))
+    ));
+}
+
+#[test]
+fn album_artist_groups_compilations() {
+    let fixture = TestLibrary::new();
+    for (number, artist) in [(1, "Chillest"), (2, "The Rockers")] {
+        fixture.add_flac(
+            &format!("Compilations/Mixed/{}.flac", number),
+            &track(artist, "Mixed", 1, number, "Song").tag("ALBUMARTIST", "Various Artists"),
+        );
+    }
+
+    let library = model::Library::from_library_directory(&fixture.root).unwrap();
+    assert_eq!(album(&library, "Various Artists", "Mixed").track_count, 2);
+    // The track artists are still found, as appearing on the compilation
+    for artist_name in ["Chillest", "The Rockers"] {
+        let artist = &library.artists[artist_name];
+        assert!(artist.albums.is_empty());
+        assert!(artist
+            .appears_on
+            .contains_key(&("Various Artists".to_string(), "Mixed".to_string())));
+    }
+}
+
+#[test]
+fn featured_artists_appear_on_albums() {
+    let fixture = TestLibrary::new();
+    fixture.add_flac(
+        "A/x/1.flac",
+        &track("Chillest feat. The Rockers", "x", 1, 1, "Together").tag("ALBUMARTIST", "Chillest"),
+    );
+    fixture.add_flac(
+        "A/x/2.flac",
+        &track("Chillest", "x", 1, 2, "Song (ft. Guest One & Guest Two)"),
+    );
+
+    let library = model::Library::from_library_directory(&fixture.root).unwrap();
+    let key = ("Chillest".to_string(), "x".to_string());
+    let appearances: Vec<(&String, Vec<&(u32, u32)>)> = library
+        .artists
+        .values()
+        .filter_map(|artist| {
+            artist
+                .appears_on
+                .get(&key)
+                .map(|a| (&artist.name, a.tracks.iter().collect()))
+        })
+        .collect();
+    assert_eq!(
+        appearances,
+        [
+            (&"Guest One".to_string(), vec![&(1, 2)]),
+            (&"Guest Two".to_string(), vec![&(1, 2)]),
+            (&"The Rockers".to_string(), vec![&(1, 1)]),
+        ]
+    );
+}
+
+#[test]
+fn missing_album_and_numbers_fall_back() {
+    let fixture = TestLibrary::new();
+    fixture.add_flac(
+        "Loose/a.flac",
+        &track("Loose", "", 1, 1, "Single")
+            .without("ALBUM")
+            .without("DISCNUMBER")
+            .without("TRACKNUMBER"),
+    );
+
+    let library = model::Library::from_library_directory(&fixture.root).unwrap();
+    let artist = &library.artists["Loose"];
+    assert_eq!(artist.albums.len(), 1);
+    let (_, only_album) = artist.albums.iter().next().unwrap();
+    assert_eq!(only_album.track_count, 1);
+}
+
+#[test]
+fn inconsistent_tags_are_warned_about() {
+    let fixture = TestLibrary::new();
+    fixture.add_flac(
+        "A/x/1.flac",
+        &track("A", "x", 1, 1, "One")
+            .tag("DATE", 2001)
+            .tag("GENRE", "Rock"),
+    );
+    fixture.add_flac(
+        "A/x/2.flac",
+        &track("A", "x", 1, 2, "Two")
+            .tag("DATE", 2001)
+            .tag("GENRE", "Rock"),
+    );
+    fixture.add_flac(
+        "A/x/3.flac",
+        &track("A", "x", 1, 3, "Three")
+            .tag("DATE", 2003)
+            .tag("GENRE", "rock"),
+    );
+
+    let library = model::Library::from_library_directory(&fixture.root).unwrap();
+    let x = album(&library, "A", "x");
+    // The most common date wins, and genres differing only in case are the same
+    assert_eq!(x.date.as_deref(), Some("2001"));
+    assert_eq!(x.genres, ["Rock"]);
+    assert_eq!(
+        x.warnings,
+        [model::AlbumWarning::DifferentDates(vec![
+            "2001".to_string(),
+            "2003".to_string()
+        ])]
+    );
+}
+
+#[test]
+fn genres_index_albums() {
+    let fixture = TestLibrary::new();
+    fixture.add_flac(
+        "A/x/1.flac",
+        &track("A", "x", 1, 1, "One").tag("GENRE", "Jazz"),
+    );
+    fixture.add_mp3(
+        "B/y/1.mp3",
+        &track("B", "y", 1, 1, "One").tag("GENRE", "jazz"),
+    );
+
+    let library = model::Library::from_library_directory(&fixture.root).unwrap();
+    let jazz = &library.genres["jazz"];
+    assert_eq!(jazz.albums.len(), 2);
+    assert_eq!(
+        album(&library, "B", "y").dominant_genre.as_deref(),
+        Some("jazz")
+    );
+}
+
+#[test]
+fn composers_index_albums_and_works_group_tracks() {
+    let fixture = TestLibrary::new();
+    let movement = |number: u32, work: &str, composer: &str| {
+        track(
+            "Orchestra",
+            "Evening",
+            1,
+            number,
+            &format!("Movement {}", number),
+        )
+        .tag("WORK", work)
+        .tag("COMPOSER", composer)
+    };
+    fixture.add_flac("O/e/1.flac", &movement(1, "Symphony No. 5", "Beethoven"));
+    fixture.add_flac("O/e/2.flac", &movement(2, "Symphony No. 5", "Beethoven"));
+    fixture.add_flac("O/e/3.flac", &movement(3, "Symphony No. 5", "Schubert"));
+    // Lacking the composer, it still joins the work it names
+    fixture.add_flac(
+        "O/e/4.flac",
+        &movement(4, "symphony no. 5", "").without("COMPOSER"),
+    );
+    fixture.add_flac(
+        "O/e/5.flac",
+        &track("Orchestra", "Evening", 1, 5, "Encore").tag("COMPOSER", "beethoven; Brahms"),
+    );
+
+    let library = model::Library::from_library_directory(&fixture.root).unwrap();
+    let works: Vec<_> = album(&library, "Orchestra", "Evening")
+        .works
+        .iter()
+        .map(|work| (work.name.as_str(), work.composer.as_deref(), &work.tracks))
+        .collect();
+    assert_eq!(
+        works,
+        [
+            (
+                "Symphony No. 5",
+                Some("Beethoven"),
+                &vec![(1, 1), (1, 2), (1, 4)]
+            ),
+            ("Symphony No. 5", Some("Schubert"), &vec![(1, 3)]),
+        ]
+    );
+
+    let composer_names: Vec<&str> = library
+        .composers
+        .values()
+        .map(|c| c.name.as_str())
+        .collect();
+    assert_eq!(composer_names, ["Beethoven", "Brahms", "Schubert"]);
+    let beethoven =
+        &library.composers["beethoven"].albums[&("Orchestra".to_string(), "Evening".to_string())];
+    assert_eq!(
+        beethoven.tracks.iter().collect::<Vec<_>>(),
+        [&(1, 1), &(1, 2), &(1, 5)]
+    );
+    assert_eq!(beethoven.works, ["Symphony No. 5"]);
+}
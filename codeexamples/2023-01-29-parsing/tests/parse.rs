@@ -0,0 +1,565 @@
+mod fixtures;
+
+use std::fs;
+use std::time;
+
+use simpleaudioparser::model;
+
+use fixtures::{track, TestLibrary, Track};
+
+fn parse_one(library: &TestLibrary) -> model::AudioFileTrackMetadata {
+    parse_one_with(library, &model::ScanOptions::default())
+}
+
+fn parse_one_with(
+    library: &TestLibrary,
+    options: &model::ScanOptions,
+) -> model::AudioFileTrackMetadata {
+    let mut tracks: Vec<model::AudioFileTrackMetadata> =
+        model::TrackStream::from_library_directory(&library.root, options, &mut |_| {})
+            .collect::<Result<_, _>>()
+            .unwrap();
+    assert_eq!(tracks.len(), 1);
+    tracks.pop().unwrap()
+}
+
+fn failures(library: &TestLibrary) -> Vec<model::Error> {
+    let options = model::ScanOptions::default();
+    let mut failed = Vec::new();
+    let mut events = |event| {
+        if let model::ScanEvent::Failed(_, e) = event {
+            failed.push(e);
+        }
+    };
+    let parsed = model::TrackStream::from_library_directory(&library.root, &options, &mut events)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert!(parsed.is_empty());
+    failed
+}
+
+fn full_track() -> Track {
+    track("Chillest", "Songs To Dream To", 2, 3, "Sunlight")
+        .tag("ALBUMARTIST", "Various Chillers")
+        .tag("DISCTOTAL", 2)
+        .tag("TRACKTOTAL", 9)
+        .tag("GENRE", "Ambient")
+        .tag("DATE", 2021)
+        .tag("REPLAYGAIN_TRACK_GAIN", "-6.54 dB")
+        .tag("REPLAYGAIN_TRACK_PEAK", "0.988547")
+        .tag("LYRICS", "Morning comes")
+        .duration_ms(1500)
+}
+
+fn assert_full_track(parsed: &model::AudioFileTrackMetadata) {
+    assert_eq!(parsed.artist, "Chillest");
+    assert_eq!(parsed.album_artist.as_deref(), Some("Various Chillers"));
+    assert_eq!(parsed.album.as_deref(), Some("Songs To Dream To"));
+    assert_eq!(parsed.disc_no, Some(2));
+    assert_eq!(parsed.disc_total, Some(2));
+    assert_eq!(parsed.track, Some(3));
+    assert_eq!(parsed.track_total, Some(9));
+    assert_eq!(parsed.track_title, "Sunlight");
+    assert_eq!(parsed.genre.as_deref(), Some("Ambient"));
+    assert_eq!(parsed.date.as_deref(), Some("2021"));
+    assert_eq!(parsed.replay_gain.track_gain, Some(-6.54));
+    assert_eq!(parsed.replay_gain.track_peak, Some(0.988547));
+    assert!(
+        matches!(parsed.lyrics, Some(model::Lyrics::Unsynced(ref text)) if text == "Morning comes")
+    );
+}
+
+#[test]
+fn reads_flac_tags() {
+    let library = TestLibrary::new();
+    library.add_flac("a.flac", &full_track());
+
+    let parsed = parse_one(&library);
+    assert_full_track(&parsed);
+    assert_eq!(parsed.duration, Some(time::Duration::from_millis(1500)));
+}
+
+#[test]
+fn reads_mp3_tags_in_every_id3_version_and_encoding() {
+    for (version, encoding) in [
+        (id3::Version::Id3v24, id3::Encoding::UTF8),
+        (id3::Version::Id3v24, id3::Encoding::UTF16BE),
+        (id3::Version::Id3v23, id3::Encoding::UTF16),
+        (id3::Version::Id3v23, id3::Encoding::Latin1),
+        (id3::Version::Id3v22, id3::Encoding::Latin1),
+    ] {
+        let library = TestLibrary::new();
+        library.add_mp3("a.mp3", &full_track().id3(Some(version), encoding));
+
+        let parsed = parse_one(&library);
+        assert_full_track(&parsed);
+        let duration = parsed.duration.unwrap().as_secs_f64();
+        assert!((duration - 1.5).abs() < 0.05, "{:?}: {}", version, duration);
+    }
+}
+
+#[test]
+fn keeps_non_ascii_text() {
+    let library = TestLibrary::new();
+    let title = "Jóga – 夢の中で";
+    library.add_flac("Björk/a.flac", &track("Björk", "Homogenic", 1, 1, title));
+    library.add_mp3(
+        "Björk/b.mp3",
+        &track("Björk", "Homogenic", 1, 2, title)
+            .id3(Some(id3::Version::Id3v23), id3::Encoding::UTF16),
+    );
+
+    let options = model::ScanOptions::default();
+    let tracks: Vec<model::AudioFileTrackMetadata> =
+        model::TrackStream::from_library_directory(&library.root, &options, &mut |_| {})
+            .collect::<Result<_, _>>()
+            .unwrap();
+    assert_eq!(tracks.len(), 2);
+    for parsed in tracks.iter() {
+        assert_eq!(parsed.artist, "Björk");
+        assert_eq!(parsed.track_title, title);
+    }
+}
+
+#[test]
+fn latin1_tags_keep_western_european_text() {
+    let library = TestLibrary::new();
+    library.add_mp3(
+        "a.mp3",
+        &track("Motörhead", "Ace of Spades", 1, 1, "Café")
+            .id3(Some(id3::Version::Id3v23), id3::Encoding::Latin1),
+    );
+
+    let parsed = parse_one(&library);
+    assert_eq!(parsed.artist, "Motörhead");
+    assert_eq!(parsed.track_title, "Café");
+}
+
+#[test]
+fn vorbis_comment_keys_ignore_case() {
+    let library = TestLibrary::new();
+    library.add_flac(
+        "a.flac",
+        &Track::new()
+            .tag("Artist", "Chillest")
+            .tag("title", "Dozing")
+            .tag("TrackNumber", 3),
+    );
+
+    let parsed = parse_one(&library);
+    assert_eq!(parsed.artist, "Chillest");
+    assert_eq!(parsed.track_title, "Dozing");
+    assert_eq!(parsed.track, Some(3));
+}
+
+#[test]
+fn values_may_contain_equals_signs() {
+    let library = TestLibrary::new();
+    library.add_flac("a.flac", &track("A=B", "x", 1, 1, "1+1=2"));
+
+    let parsed = parse_one(&library);
+    assert_eq!(parsed.artist, "A=B");
+    assert_eq!(parsed.track_title, "1+1=2");
+}
+
+#[test]
+fn missing_title_fails_the_file() {
+    let library = TestLibrary::new();
+    library.add_flac(
+        "a.flac",
+        &track("Chillest", "x", 1, 1, "x").without("TITLE"),
+    );
+    library.add_mp3("b.mp3", &track("Chillest", "x", 1, 2, "x").without("TITLE"));
+
+    let failed = failures(&library);
+    assert_eq!(failed.len(), 2);
+    for e in failed.iter() {
+        assert!(
+            matches!(e, model::Error::MissingMetadataKey(_, "title")),
+            "{:?}",
+            e
+        );
+    }
+}
+
+#[test]
+fn missing_artist_fails_the_file() {
+    let library = TestLibrary::new();
+    library.add_flac("a.flac", &track("x", "x", 1, 1, "x").without("ARTIST"));
+
+    let failed = failures(&library);
+    assert!(matches!(
+        failed[..],
+        [model::Error::MissingMetadataKey(_, "artist")]
+    ));
+}
+
+#[test]
+fn track_number_must_be_a_number() {
+    let library = TestLibrary::new();
+    library.add_flac(
+        "a.flac",
+        &track("x", "x", 1, 1, "x")
+            .without("TRACKNUMBER")
+            .tag("TRACKNUMBER", "one"),
+    );
+
+    let failed = failures(&library);
+    assert!(matches!(
+        failed[..],
+        [model::Error::ExpectedU32MetadataValue(_, "tracknumber")]
+    ));
+}
+
+#[test]
+fn mp3_without_id3v2_tag_is_missing_its_artist() {
+    let library = TestLibrary::new();
+    library.add_mp3(
+        "a.mp3",
+        &track("x", "x", 1, 1, "x").id3(None, id3::Encoding::UTF8),
+    );
+
+    let failed = failures(&library);
+    assert!(matches!(
+        failed[..],
+        [model::Error::MissingMetadataKey(_, "artist")]
+    ));
+}
+
+#[test]
+fn not_audio_at_all_fails() {
+    let library = TestLibrary::new();
+    library.add_file("a.flac", "not really a flac file");
+    library.add_file("b.mp3", "");
+
+    assert_eq!(failures(&library).len(), 2);
+}
+
+#[test]
+fn fixtures_pass_the_integrity_check() {
+    let library = TestLibrary::new();
+    library.add_flac("a.flac", &full_track().duration_ms(300));
+    library.add_mp3("b.mp3", &full_track());
+
+    let report = model::IntegrityReport::from_library_directory(
+        &library.root,
+        &model::IntegrityOptions::default(),
+        &mut |_| {},
+    )
+    .unwrap();
+    assert_eq!(report.files_checked, 2);
+    assert!(report.bad_files.is_empty());
+    assert!(report.files_without_md5.is_empty());
+}
+
+#[test]
+fn splits_single_file_rips_by_cue_sheet() {
+    let library = TestLibrary::new();
+    library.add_flac(
+        "Chillest/Live/live.flac",
+        &track("Chillest", "Live", 1, 1, "Whole Set").duration_ms(6000),
+    );
+    library.add_file(
+        "Chillest/Live/live.cue",
+        concat!(
+            "PERFORMER \"Chillest\"\n",
+            "TITLE \"Live\"\n",
+            "FILE \"live.flac\" WAVE\n",
+            "  TRACK 01 AUDIO\n",
+            "    TITLE \"Opening\"\n",
+            "    INDEX 01 00:00:00\n",
+            "  TRACK 02 AUDIO\n",
+            "    TITLE \"Closing\"\n",
+            "    INDEX 01 00:03:00\n",
+        ),
+    );
+
+    let options = model::ScanOptions::default();
+    let mut skipped = Vec::new();
+    let mut events = |event| {
+        if let model::ScanEvent::Skipped(path, model::SkipReason::CoveredByCueSheet) = event {
+            skipped.push(path);
+        }
+    };
+    let tracks: Vec<model::AudioFileTrackMetadata> =
+        model::TrackStream::from_library_directory(&library.root, &options, &mut events)
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+    let titles: Vec<&str> = tracks.iter().map(|t| t.track_title.as_str()).collect();
+    assert_eq!(titles, ["Opening", "Closing"]);
+    assert_eq!(tracks[1].range.unwrap().start, time::Duration::from_secs(3));
+    assert_eq!(skipped, [library.path("Chillest/Live/live.flac")]);
+}
+
+#[test]
+fn untagged_rips_take_their_names_from_the_cue_sheet() {
+    let library = TestLibrary::new();
+    library.add_flac("Chillest/Live/live.flac", &Track::new().duration_ms(6000));
+    library.add_file(
+        "Chillest/Live/live.cue",
+        concat!(
+            "PERFORMER \"Chillest\"\n",
+            "TITLE \"Live\"\n",
+            "FILE \"live.flac\" WAVE\n",
+            "  TRACK 01 AUDIO\n",
+            "    TITLE \"Opening\"\n",
+            "    INDEX 01 00:00:00\n",
+            "  TRACK 02 AUDIO\n",
+            "    TITLE \"Closing\"\n",
+            "    INDEX 01 00:03:00\n",
+        ),
+    );
+
+    let tracks: Vec<model::AudioFileTrackMetadata> = model::TrackStream::from_library_directory(
+        &library.root,
+        &model::ScanOptions::default(),
+        &mut |_| {},
+    )
+    .collect::<Result<_, _>>()
+    .unwrap();
+
+    let names: Vec<(&str, Option<&str>, &str)> = tracks
+        .iter()
+        .map(|t| {
+            (
+                t.artist.as_str(),
+                t.album.as_deref(),
+                t.track_title.as_str(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        names,
+        [
+            ("Chillest", Some("Live"), "Opening"),
+            ("Chillest", Some("Live"), "Closing")
+        ]
+    );
+    assert_ne!(tracks[0].id, tracks[1].id);
+
+    // Without a PERFORMER there is no artist to be had, from the sheet or the rip itself
+    library.add_file(
+        "Chillest/Live/live.cue",
+        "FILE \"live.flac\" WAVE\n  TRACK 01 AUDIO\n    INDEX 01 00:00:00\n",
+    );
+    assert!(matches!(
+        failures(&library)[..],
+        [
+            model::Error::MissingMetadataKey(_, "artist"),
+            model::Error::MissingMetadataKey(_, "artist")
+        ]
+    ));
+}
+
+#[test]
+fn track_ids_come_from_the_tags_unless_the_audio_is_hashed() {
+    use id3::TagLike;
+
+    let library = TestLibrary::new();
+    library.add_mp3("a.mp3", &track("A", "x", 1, 1, "One"));
+    let by_tags = parse_one(&library).id;
+    assert!(by_tags.0.starts_with("tags:"));
+
+    // Moving the file keeps the id
+    let moved_path = library.path("moved/b.mp3");
+    fs::create_dir(library.path("moved")).unwrap();
+    fs::rename(library.path("a.mp3"), &moved_path).unwrap();
+    assert_eq!(parse_one(&library).id, by_tags);
+
+    let hashing = model::ScanOptions {
+        hash_audio: true,
+        ..model::ScanOptions::default()
+    };
+    let by_audio = parse_one_with(&library, &hashing).id;
+    assert!(by_audio.0.starts_with("audio:"));
+
+    // Correcting the tags keeps only the audio id
+    let mut tag = id3::Tag::read_from_path(&moved_path).unwrap();
+    tag.set_title("Uno");
+    tag.write_to_path(&moved_path, id3::Version::Id3v24)
+        .unwrap();
+    assert_eq!(parse_one_with(&library, &hashing).id, by_audio);
+    assert_ne!(parse_one(&library).id, by_tags);
+}
+
+fn parse_inferring(
+    library: &TestLibrary,
+    inference: model::PathInference,
+) -> Vec<model::AudioFileTrackMetadata> {
+    let options = model::ScanOptions {
+        path_inference: Some(inference),
+        ..model::ScanOptions::default()
+    };
+    let mut tracks: Vec<model::AudioFileTrackMetadata> =
+        model::TrackStream::from_library_directory(&library.root, &options, &mut |_| {})
+            .collect::<Result<_, _>>()
+            .unwrap();
+    tracks.sort_by(|a, b| a.full_path.cmp(&b.full_path));
+    tracks
+}
+
+#[test]
+fn infers_missing_tags_from_the_library_layout() {
+    let library = TestLibrary::new();
+    library.add_flac(
+        "Chillest/Songs To Dream To/Disc 2/3.Sunlight.flac",
+        &Track::new().tag("GENRE", "Ambient"),
+    );
+    library.add_mp3(
+        "Chillest/Songs To Dream To/4.Tagged.mp3",
+        &track("Chillest", "Songs To Dream To", 1, 4, "Moonlight").without("ARTIST"),
+    );
+    library.add_mp3(
+        "Chillest/Songs To Dream To/5.No Tag At All.mp3",
+        &Track::new().id3(None, id3::Encoding::UTF8),
+    );
+
+    let tracks = parse_inferring(&library, model::PathInference::LibraryLayout);
+    assert_eq!(tracks.len(), 3);
+
+    let tagged = &tracks[0];
+    assert_eq!(tagged.artist, "Chillest");
+    // What the tags say wins over the path
+    assert_eq!(tagged.track_title, "Moonlight");
+    assert_eq!(tagged.inferred_fields, [model::TrackField::Artist]);
+
+    let without_id3 = &tracks[1];
+    assert_eq!(without_id3.artist, "Chillest");
+    assert_eq!(without_id3.track, Some(5));
+    assert_eq!(without_id3.track_title, "No Tag At All");
+    assert!(without_id3.duration.is_some());
+
+    let untagged = &tracks[2];
+    assert_eq!(untagged.artist, "Chillest");
+    assert_eq!(untagged.album.as_deref(), Some("Songs To Dream To"));
+    assert_eq!(untagged.disc_no, Some(2));
+    assert_eq!(untagged.track, Some(3));
+    assert_eq!(untagged.track_title, "Sunlight");
+    assert_eq!(untagged.genre.as_deref(), Some("Ambient"));
+    assert_eq!(
+        untagged.inferred_fields,
+        [
+            model::TrackField::Artist,
+            model::TrackField::Album,
+            model::TrackField::DiscNumber,
+            model::TrackField::TrackNumber,
+            model::TrackField::Title,
+        ]
+    );
+}
+
+#[test]
+fn infers_missing_tags_by_a_pattern() {
+    let library = TestLibrary::new();
+    library.add_flac(
+        "Incoming/The Rockers - Party Time - Live/02 The Hit.flac",
+        &Track::new(),
+    );
+
+    let pattern = model::PathPattern::parse("{artist} - {album}/{track} {title}").unwrap();
+    let tracks = parse_inferring(&library, model::PathInference::Pattern(pattern));
+    assert_eq!(tracks[0].artist, "The Rockers");
+    assert_eq!(tracks[0].album.as_deref(), Some("Party Time - Live"));
+    assert_eq!(tracks[0].track, Some(2));
+    assert_eq!(tracks[0].track_title, "The Hit");
+    assert_eq!(tracks[0].disc_no, None);
+}
+
+#[test]
+fn path_patterns_are_checked() {
+    for pattern in [
+        "",
+        "{artist}//{title}",
+        "{artist}/{title",
+        "{artist}/{name}",
+        "{title}/{title}",
+        "{artist}}/{title}",
+        "Music/Songs",
+    ] {
+        assert!(
+            matches!(
+                model::PathPattern::parse(pattern),
+                Err(model::Error::InvalidPathPattern(..))
+            ),
+            "{:?}",
+            pattern
+        );
+    }
+}
+
+#[test]
+fn reads_classical_tags() {
+    let movement = track(
+        "Berliner Philharmoniker",
+        "Symphonies",
+        1,
+        2,
+        "II. Andante con moto",
+    )
+    .tag("COMPOSER", "Ludwig van Beethoven")
+    .tag("CONDUCTOR", "Herbert von Karajan")
+    .tag("WORK", "Symphony No. 5 in C minor, Op. 67")
+    .tag("MOVEMENTNAME", "Andante con moto")
+    .tag("MOVEMENT", 2);
+    let library = TestLibrary::new();
+    library.add_flac(
+        "a.flac",
+        &movement.clone().tag("ORCHESTRA", "Berliner Philharmoniker"),
+    );
+    for (name, version, encoding) in [
+        ("b.mp3", id3::Version::Id3v24, id3::Encoding::UTF8),
+        ("c.mp3", id3::Version::Id3v23, id3::Encoding::UTF16),
+        ("d.mp3", id3::Version::Id3v23, id3::Encoding::Latin1),
+    ] {
+        library.add_mp3(
+            name,
+            &movement
+                .clone()
+                .tag("PERFORMER", "Berliner Philharmoniker")
+                .id3(Some(version), encoding),
+        );
+    }
+
+    let options = model::ScanOptions::default();
+    let tracks: Vec<model::AudioFileTrackMetadata> =
+        model::TrackStream::from_library_directory(&library.root, &options, &mut |_| {})
+            .collect::<Result<_, _>>()
+            .unwrap();
+    assert_eq!(tracks.len(), 4);
+    for parsed in tracks.iter() {
+        let classical = &parsed.classical;
+        let path = parsed.full_path.display();
+        assert_eq!(
+            classical.composer.as_deref(),
+            Some("Ludwig van Beethoven"),
+            "{}",
+            path
+        );
+        assert_eq!(
+            classical.conductor.as_deref(),
+            Some("Herbert von Karajan"),
+            "{}",
+            path
+        );
+        assert_eq!(
+            classical.performer.as_deref(),
+            Some("Berliner Philharmoniker"),
+            "{}",
+            path
+        );
+        assert_eq!(
+            classical.work.as_deref(),
+            Some("Symphony No. 5 in C minor, Op. 67"),
+            "{}",
+            path
+        );
+        assert_eq!(
+            classical.movement_name.as_deref(),
+            Some("Andante con moto"),
+            "{}",
+            path
+        );
+        assert_eq!(classical.movement_number, Some(2), "{}", path);
+    }
+}
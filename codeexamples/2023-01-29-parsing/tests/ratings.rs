@@ -0,0 +1,114 @@
+mod fixtures;
+
+use simpleaudioparser::model;
+
+use fixtures::{track, TestLibrary};
+
+fn parse_all(library: &TestLibrary) -> Vec<model::AudioFileTrackMetadata> {
+    let options = model::ScanOptions::default();
+    let mut tracks: Vec<model::AudioFileTrackMetadata> =
+        model::TrackStream::from_library_directory(&library.root, &options, &mut |_| {})
+            .collect::<Result<_, _>>()
+            .unwrap();
+    tracks.sort_by(|a, b| a.full_path.cmp(&b.full_path));
+    tracks
+}
+
+#[test]
+fn reads_ratings_from_tags() {
+    let library = TestLibrary::new();
+    let rated = |number: u32| track("A", "x", 1, number, &number.to_string());
+    library.add_flac("1.flac", &rated(1).tag("FMPS_RATING", 0.6));
+    library.add_flac("2.flac", &rated(2).tag("RATING", 4));
+    library.add_flac("3.flac", &rated(3).tag("RATING", 80));
+    library.add_flac("4.flac", &rated(4).tag("RATING", "great"));
+    library.add_mp3("5.mp3", &rated(5).tag("POPM", 196));
+    library.add_mp3("6.mp3", &rated(6).tag("FMPS_RATING", 0.2).tag("POPM", 255));
+    library.add_mp3("7.mp3", &rated(7).tag("POPM", 0));
+
+    let ratings: Vec<Option<f64>> = parse_all(&library).iter().map(|t| t.rating).collect();
+    assert_eq!(
+        ratings,
+        [
+            Some(0.6),
+            Some(0.8),
+            Some(0.8),
+            None,
+            Some(0.8),
+            Some(0.2),
+            None
+        ]
+    );
+}
+
+#[test]
+fn store_keeps_ratings_and_favourites() {
+    let library = TestLibrary::new();
+    library.add_flac(
+        "1.flac",
+        &track("A", "x", 1, 1, "One").tag("FMPS_RATING", 0.4),
+    );
+    library.add_flac("2.flac", &track("A", "x", 1, 2, "Two"));
+    let tracks = parse_all(&library);
+    let store_path = library.path("ratings.json");
+
+    let mut store = model::RatingsStore::open(&store_path).unwrap();
+    assert_eq!(store.rating(&tracks[0]), Some(0.4));
+    store.set_rating(&tracks[0], None).unwrap();
+    store.set_rating(&tracks[1], Some(1.0)).unwrap();
+    store.set_favourite(&tracks[1].id, true);
+    store.save().unwrap();
+
+    let store = model::RatingsStore::open(&store_path).unwrap();
+    // Taking a rating away in the store hides the one in the tags
+    assert_eq!(store.rating(&tracks[0]), None);
+    assert_eq!(store.rating(&tracks[1]), Some(1.0));
+    assert!(store.is_favourite(&tracks[1].id));
+    assert!(!store.is_favourite(&tracks[0].id));
+    // Without write_to_files the files are left alone
+    assert_eq!(parse_all(&library)[0].rating, Some(0.4));
+}
+
+#[test]
+fn writes_ratings_back_to_files() {
+    let library = TestLibrary::new();
+    library.add_flac(
+        "1.flac",
+        &track("A", "x", 1, 1, "One").tag("FMPS_RATING", 0.4),
+    );
+    library.add_mp3("2.mp3", &track("A", "x", 1, 2, "Two").tag("POPM", 1));
+    library.add_mp3(
+        "3.mp3",
+        &track("A", "x", 1, 3, "Three")
+            .tag("FMPS_RATING", 0.2)
+            .id3(Some(id3::Version::Id3v23), id3::Encoding::UTF16),
+    );
+    let tracks = parse_all(&library);
+
+    let mut store = model::RatingsStore::open(library.path("ratings.json")).unwrap();
+    store.write_to_files = true;
+    store.set_rating(&tracks[0], Some(0.8)).unwrap();
+    store.set_rating(&tracks[1], Some(0.6)).unwrap();
+    store.set_rating(&tracks[2], None).unwrap();
+
+    let reread = parse_all(&library);
+    assert_eq!(reread[0].rating, Some(0.8));
+    assert_eq!(reread[1].rating, Some(0.6));
+    assert_eq!(reread[2].rating, None);
+    // Only the tags changed, not the audio
+    for (before, after) in tracks.iter().zip(reread.iter()) {
+        assert_eq!(before.id, after.id);
+        assert_eq!(before.track_title, after.track_title);
+    }
+}
+
+#[test]
+fn broken_store_is_an_error() {
+    let library = TestLibrary::new();
+    let store_path = library.add_file("ratings.json", "{\"ratings\": [{\"rating\": 2}]}");
+
+    assert!(matches!(
+        model::RatingsStore::open(store_path),
+        Err(model::Error::InvalidRatingsStore(..))
+    ));
+}
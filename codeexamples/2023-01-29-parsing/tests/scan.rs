@@ -0,0 +1,210 @@
+mod fixtures;
+
+use std::path;
+
+use simpleaudioparser::model;
+
+use fixtures::{track, TestLibrary};
+
+struct Scanned {
+    titles: Vec<String>,
+    skipped: Vec<(path::PathBuf, model::SkipReason)>,
+}
+
+fn scan(library: &TestLibrary, options: &model::ScanOptions) -> Scanned {
+    let mut skipped = Vec::new();
+    let mut events = |event| {
+        if let model::ScanEvent::Skipped(path, reason) = event {
+            skipped.push((path, reason));
+        }
+    };
+    let mut titles: Vec<String> =
+        model::TrackStream::from_library_directory(&library.root, options, &mut events)
+            .map(|track| track.unwrap().track_title)
+            .collect();
+    titles.sort();
+    Scanned { titles, skipped }
+}
+
+fn skip_reason(
+    scanned: &Scanned,
+    library: &TestLibrary,
+    relative_path: &str,
+) -> Option<model::SkipReason> {
+    let full_path = library.path(relative_path);
+    scanned
+        .skipped
+        .iter()
+        .find(|(path, _)| *path == full_path)
+        .map(|(_, reason)| *reason)
+}
+
+#[test]
+fn finds_audio_files_at_any_depth() {
+    let library = TestLibrary::new();
+    library.add_flac("1.flac", &track("A", "x", 1, 1, "top"));
+    library.add_mp3("A/x/Disc 1/2.mp3", &track("A", "x", 1, 2, "deep"));
+    library.add_flac(
+        "A/x/Disc 1/Extras/3.FLAC",
+        &track("A", "x", 1, 3, "upper case"),
+    );
+
+    let scanned = scan(&library, &model::ScanOptions::default());
+    assert_eq!(scanned.titles, ["deep", "top", "upper case"]);
+}
+
+#[test]
+fn skips_other_files() {
+    let library = TestLibrary::new();
+    library.add_flac("A/1.flac", &track("A", "x", 1, 1, "kept"));
+    library.add_file("A/notes.txt", "liner notes");
+    library.add_file("A/README", "no extension");
+
+    let scanned = scan(&library, &model::ScanOptions::default());
+    assert_eq!(scanned.titles, ["kept"]);
+    assert_eq!(
+        skip_reason(&scanned, &library, "A/notes.txt"),
+        Some(model::SkipReason::UnknownExtension)
+    );
+    assert_eq!(
+        skip_reason(&scanned, &library, "A/README"),
+        Some(model::SkipReason::NoExtension)
+    );
+}
+
+#[test]
+fn skips_hidden_files_unless_asked_not_to() {
+    let library = TestLibrary::new();
+    library.add_flac("A/1.flac", &track("A", "x", 1, 1, "visible"));
+    library.add_flac("A/.2.flac", &track("A", "x", 1, 2, "hidden file"));
+    library.add_flac(".trash/3.flac", &track("A", "x", 1, 3, "hidden directory"));
+
+    let scanned = scan(&library, &model::ScanOptions::default());
+    assert_eq!(scanned.titles, ["visible"]);
+    assert_eq!(
+        skip_reason(&scanned, &library, ".trash"),
+        Some(model::SkipReason::Hidden)
+    );
+
+    let options = model::ScanOptions {
+        skip_hidden: false,
+        ..model::ScanOptions::default()
+    };
+    assert_eq!(
+        scan(&library, &options).titles,
+        ["hidden directory", "hidden file", "visible"]
+    );
+}
+
+#[test]
+fn exclude_patterns_and_musicignore_files() {
+    let library = TestLibrary::new();
+    library.add_flac("A/x/1.flac", &track("A", "x", 1, 1, "kept"));
+    library.add_flac("A/x/Bonus/2.flac", &track("A", "x", 1, 2, "bonus"));
+    library.add_flac("A/x/3.wip.flac", &track("A", "x", 1, 3, "work in progress"));
+    library.add_flac("B/y/1.flac", &track("B", "y", 1, 1, "ignored album"));
+    library.add_file("B/.musicignore", "# not ready yet\ny/\n");
+
+    let options = model::ScanOptions {
+        exclude_patterns: vec!["*.wip.flac".to_string(), "Bonus/".to_string()],
+        ..model::ScanOptions::default()
+    };
+    let scanned = scan(&library, &options);
+    assert_eq!(scanned.titles, ["kept"]);
+    assert_eq!(
+        skip_reason(&scanned, &library, "B/y"),
+        Some(model::SkipReason::Excluded)
+    );
+
+    let options = model::ScanOptions {
+        use_musicignore_files: false,
+        ..model::ScanOptions::default()
+    };
+    assert_eq!(
+        scan(&library, &options).titles,
+        ["bonus", "ignored album", "kept", "work in progress"]
+    );
+}
+
+#[test]
+fn max_depth_counts_directories_below_the_library() {
+    let library = TestLibrary::new();
+    library.add_flac("A/x/1.flac", &track("A", "x", 1, 1, "two deep"));
+    library.add_flac("A/x/Disc 2/2.flac", &track("A", "x", 2, 1, "three deep"));
+
+    let options = model::ScanOptions {
+        max_depth: Some(2),
+        ..model::ScanOptions::default()
+    };
+    let scanned = scan(&library, &options);
+    assert_eq!(scanned.titles, ["two deep"]);
+    assert_eq!(
+        skip_reason(&scanned, &library, "A/x/Disc 2"),
+        Some(model::SkipReason::TooDeep)
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_are_followed_only_when_asked_and_only_once() {
+    let library = TestLibrary::new();
+    library.add_flac("A/x/1.flac", &track("A", "x", 1, 1, "real"));
+    library.add_symlink("A/x/loop", &library.path("A"));
+    library.add_symlink("A/x/gone.flac", &library.path("A/x/missing.flac"));
+
+    let scanned = scan(&library, &model::ScanOptions::default());
+    assert_eq!(scanned.titles, ["real"]);
+    assert_eq!(
+        skip_reason(&scanned, &library, "A/x/loop"),
+        Some(model::SkipReason::Symlink)
+    );
+
+    let options = model::ScanOptions {
+        follow_symlinks: true,
+        ..model::ScanOptions::default()
+    };
+    let scanned = scan(&library, &options);
+    assert_eq!(scanned.titles, ["real"]);
+    assert_eq!(
+        skip_reason(&scanned, &library, "A/x/loop"),
+        Some(model::SkipReason::AlreadyScanned)
+    );
+    assert_eq!(
+        skip_reason(&scanned, &library, "A/x/gone.flac"),
+        Some(model::SkipReason::BrokenSymlink)
+    );
+}
+
+#[test]
+fn progress_counts_every_file_found() {
+    let library = fixtures::demo_library();
+    library.add_file("Chillest/notes.txt", "not counted");
+
+    let options = model::ScanOptions::default();
+    let mut last_progress = None;
+    let mut events = |event| {
+        if let model::ScanEvent::Progress(done, found) = event {
+            assert!(done <= found);
+            last_progress = Some((done, found));
+        }
+    };
+    let count =
+        model::TrackStream::from_library_directory(&library.root, &options, &mut events).count();
+
+    assert_eq!(count, 9);
+    assert_eq!(last_progress, Some((9, 9)));
+}
+
+#[test]
+fn missing_library_directory_is_an_error() {
+    let library = TestLibrary::new();
+    let options = model::ScanOptions::default();
+    let results: Vec<_> = model::TrackStream::from_library_directory(
+        library.path("does not exist"),
+        &options,
+        &mut |_| {},
+    )
+    .collect();
+
+    assert!(matches!(results[..], [Err(model::Error::IO(_))]));
+}
@@ -4,9 +4,11 @@ use std::sync::mpsc;
 use std::thread;
 use std::time;
 
+use rodio::Source;
+
 use crate::shared;
 
-const BLOCKING_TIMEOUT_MILLISECONDS: u64 = 1_000;
+const BLOCKING_TIMEOUT_MILLISECONDS: u64 = 250;
 
 pub fn create_backend_with_client_and_callback() -> (
     mpsc::Sender<shared::SinkMessage>,
@@ -41,6 +43,10 @@ pub struct SinkPlayback {
     // If None there is no loaded song;
     // If Some(x) the inner bool is whether the song is currently playing
     loaded_song_playing: Option<bool>,
+    // How much of the loaded song was played before the last pause,
+    // and when playback last (re)started if it is currently playing
+    played_before_resume: time::Duration,
+    resumed_at: Option<time::Instant>,
 }
 
 impl SinkPlayback {
@@ -51,6 +57,15 @@ impl SinkPlayback {
             sink: rodio::Sink::try_new(&stream_handle).unwrap(),
             stream_handle: stream_handle,
             loaded_song_playing: None,
+            played_before_resume: time::Duration::ZERO,
+            resumed_at: None,
+        }
+    }
+
+    fn position(&self) -> time::Duration {
+        match self.resumed_at {
+            Some(resumed_at) => self.played_before_resume + resumed_at.elapsed(),
+            None => self.played_before_resume,
         }
     }
 
@@ -81,6 +96,9 @@ impl SinkPlayback {
             shared::SinkMessage::PlayButton => match self.loaded_song_playing {
                 Some(ref mut playing) => {
                     *playing = true;
+                    if self.resumed_at.is_none() {
+                        self.resumed_at = Some(time::Instant::now());
+                    }
                     self.sink.play();
                     callback.send(shared::SinkCallbackMessage::Playing).unwrap();
                 }
@@ -89,18 +107,40 @@ impl SinkPlayback {
             shared::SinkMessage::PauseButton => match self.loaded_song_playing {
                 Some(ref mut playing) => {
                     *playing = false;
+                    self.played_before_resume = self.position();
+                    self.resumed_at = None;
                     self.sink.pause();
                     callback.send(shared::SinkCallbackMessage::Paused).unwrap();
                 }
                 None => (),
             },
-            shared::SinkMessage::LoadSong(path) => {
+            shared::SinkMessage::StopButton => {
+                self.loaded_song_playing = None;
+                self.played_before_resume = time::Duration::ZERO;
+                self.resumed_at = None;
+                self.sink.stop();
+            }
+            shared::SinkMessage::LoadSong(path, range) => {
                 self.loaded_song_playing = Some(true);
                 self.sink.stop();
                 self.sink = rodio::Sink::try_new(&self.stream_handle).unwrap();
 
                 let file = io::BufReader::new(fs::File::open(path).unwrap());
-                self.sink.append(rodio::Decoder::new(file).unwrap());
+                let source = rodio::Decoder::new(file).unwrap();
+                match range {
+                    Some(range) => {
+                        let source = source.skip_duration(range.start);
+                        match range.end {
+                            Some(end) => self
+                                .sink
+                                .append(source.take_duration(end.saturating_sub(range.start))),
+                            None => self.sink.append(source),
+                        }
+                    }
+                    None => self.sink.append(source),
+                }
+                self.played_before_resume = time::Duration::ZERO;
+                self.resumed_at = Some(time::Instant::now());
                 self.sink.play();
                 callback.send(shared::SinkCallbackMessage::Playing).unwrap();
             }
@@ -109,13 +149,19 @@ impl SinkPlayback {
 
     fn handle_timeout(&mut self, callback: &mpsc::Sender<shared::SinkCallbackMessage>) {
         match self.loaded_song_playing {
-            Some(_playing) => {
+            Some(playing) => {
                 if self.sink.len() == 0 {
                     println!("SINK:\ttimeout on recv poll and we noticed the song was over");
                     self.loaded_song_playing = None;
+                    self.played_before_resume = time::Duration::ZERO;
+                    self.resumed_at = None;
                     callback
                         .send(shared::SinkCallbackMessage::SongEnded)
                         .unwrap();
+                } else if playing {
+                    callback
+                        .send(shared::SinkCallbackMessage::Progress(self.position()))
+                        .unwrap();
                 }
             }
             None => (),
@@ -1,3 +1,4 @@
+use std::path;
 use std::sync::mpsc;
 use std::thread;
 use std::time;
@@ -7,21 +8,21 @@ use crate::shared;
 
 pub fn looping_main() {
     let (sender, rx) = backend::create_backend_with_client_and_callback();
-    play_tone_with_pause(&sender, &rx, "flacs/four.flac".to_string());
-    play_tone_with_pause(&sender, &rx, "flacs/three.flac".to_string());
-    play_tone_with_pause(&sender, &rx, "flacs/two_higher.flac".to_string());
-    play_tone_with_pause(&sender, &rx, "flacs/two_lower.flac".to_string());
-    play_tone_with_pause(&sender, &rx, "flacs/one.flac".to_string());
+    play_tone_with_pause(&sender, &rx, path::PathBuf::from("flacs/four.flac"));
+    play_tone_with_pause(&sender, &rx, path::PathBuf::from("flacs/three.flac"));
+    play_tone_with_pause(&sender, &rx, path::PathBuf::from("flacs/two_higher.flac"));
+    play_tone_with_pause(&sender, &rx, path::PathBuf::from("flacs/two_lower.flac"));
+    play_tone_with_pause(&sender, &rx, path::PathBuf::from("flacs/one.flac"));
 }
 
 fn play_tone_with_pause(
     sender: &mpsc::Sender<shared::SinkMessage>,
     rx: &mpsc::Receiver<shared::SinkCallbackMessage>,
-    filename: String,
+    filename: path::PathBuf,
 ) {
     let mut should_pause_once = true;
     sender
-        .send(shared::SinkMessage::LoadSong(filename))
+        .send(shared::SinkMessage::LoadSong(filename, None))
         .unwrap();
     loop {
         match rx.recv_timeout(time::Duration::from_millis(1_000)) {
@@ -32,17 +33,19 @@ fn play_tone_with_pause(
                     println!("we learned that the song ended");
                     break;
                 }
+                shared::SinkCallbackMessage::Progress(position) => {
+                    if should_pause_once && position >= time::Duration::from_millis(1_000) {
+                        println!("played for a second, pausing for two seconds");
+                        sender.send(shared::SinkMessage::PauseButton).unwrap();
+                        thread::sleep(time::Duration::from_millis(2_000));
+                        println!("resuming play after those seconds");
+                        sender.send(shared::SinkMessage::PlayButton).unwrap();
+                        should_pause_once = false;
+                    }
+                }
             },
             Err(mpsc::RecvTimeoutError::Timeout) => {
                 println!("waited for a second");
-                if should_pause_once {
-                    println!("pausing for two second");
-                    sender.send(shared::SinkMessage::PauseButton).unwrap();
-                    thread::sleep(time::Duration::from_millis(2_000));
-                    println!("resuming play after those seconds");
-                    sender.send(shared::SinkMessage::PlayButton).unwrap();
-                    should_pause_once = false;
-                }
             }
             Err(mpsc::RecvTimeoutError::Disconnected) => {
                 println!("recv sees that all clients have closed");
@@ -1,8 +1,13 @@
+use std::path;
+use std::time;
+
 #[derive(Clone, Debug)]
 pub enum SinkMessage {
     PlayButton,
     PauseButton,
-    LoadSong(String),
+    // Unloads the song; nothing plays until the next one is loaded
+    StopButton,
+    LoadSong(path::PathBuf, Option<SongRange>),
 }
 
 #[derive(Clone, Debug)]
@@ -10,4 +15,14 @@ pub enum SinkCallbackMessage {
     Playing,
     Paused,
     SongEnded,
+    // How far into the loaded song playback is, sent periodically while playing
+    Progress(time::Duration),
+}
+
+// Part of a file to play, e.g. one track of an album ripped to a single file
+#[derive(Clone, Copy, Debug)]
+pub struct SongRange {
+    pub start: time::Duration,
+    // None plays on to the end of the file
+    pub end: Option<time::Duration>,
 }
@@ -1,11 +1,20 @@
 use std::cell;
+use std::collections::VecDeque;
+use std::path;
+use std::time;
 
 use iced;
 
 use crate::datastore;
 use crate::sink;
 
-use super::{message, state, subscription, update, view};
+use super::{message, scan, state, subscription, update, view};
+
+// Next to the library, hidden so the scan passes it by
+const RATINGS_STORE_PATH: &str = ".simplemusicratings.json";
+const HISTORY_PATH: &str = ".simplemusichistory.log";
+// Without one the default playlists are used
+const PLAYLIST_RULES_PATH: &str = ".simplemusicplaylists";
 
 impl iced::Application for state::State {
     type Executor = iced::executor::Default;
@@ -17,9 +26,33 @@ impl iced::Application for state::State {
         let (sink_sender, sink_recv) = sink::create_backend_with_client_and_callback();
         let state = state::State {
             page: state::Page::Home,
-            datastore: datastore::model::Library::from_library_directory(".").unwrap(),
+            // Artists show up as the scan finds them, rather than after it is done
+            datastore: datastore::model::LibraryBuilder::new(),
+            scan: state::Scan {
+                scanning: true,
+                scan_recv: cell::RefCell::new(Some(scan::start_library_scan("."))),
+                progress: (0, 0),
+                skipped: Vec::new(),
+                failed: Vec::new(),
+            },
+            // Better not to start than to save over ratings that couldn't be read
+            ratings: datastore::model::RatingsStore::open(RATINGS_STORE_PATH)
+                .expect("the ratings store could not be read"),
+            history: datastore::model::ListeningHistory::open(HISTORY_PATH)
+                .expect("the listening history could not be read"),
+            playlist_rules: if path::Path::new(PLAYLIST_RULES_PATH).exists() {
+                datastore::model::PlaylistRules::read_from_file(PLAYLIST_RULES_PATH)
+                    .expect("the playlist rules could not be read")
+            } else {
+                datastore::model::PlaylistRules::default()
+            },
+            // Nothing to go on until the scan has found the tracks
+            playlists: Vec::new(),
             playback: state::PlaybackInfo {
                 currently_playing: None,
+                position: time::Duration::ZERO,
+                plays: datastore::model::PlayTracker::new(),
+                queue: VecDeque::new(),
             },
             sink: state::Sink {
                 sink_message_sender: sink_sender,
@@ -42,6 +75,10 @@ impl iced::Application for state::State {
     }
 
     fn subscription(&self) -> iced::Subscription<Self::Message> {
-        subscription::sink_callback(&self)
+        iced::Subscription::batch([
+            subscription::sink_callback(&self),
+            subscription::library_scan(&self),
+            subscription::close_requested(),
+        ])
     }
 }
@@ -1,3 +1,5 @@
+use std::path;
+
 use crate::datastore;
 use crate::shared;
 
@@ -6,7 +8,10 @@ pub enum Message {
     Nav(Navigate),
     Control(Control),
     SinkCallback(shared::SinkCallbackMessage),
+    Scan(Scan),
+    Rate(Rate),
     ErrorResponse(Result<(), String>),
+    CloseRequested,
 }
 
 #[derive(Debug, Clone)]
@@ -15,11 +20,40 @@ pub enum Navigate {
     ArtistList,
     ArtistAlbumList(String),
     AlbumTrackList(String, String),
+    GenreList,
+    GenreAlbumList(String),
+    PlaylistList,
+    Playlist(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum Scan {
+    Tracks(Vec<datastore::model::AudioFileTrackMetadata>),
+    // Files processed so far, out of how many were found
+    Progress(usize, usize),
+    Skipped(path::PathBuf, datastore::model::SkipReason),
+    // The file is left out, but the scan carries on
+    FileFailed(path::PathBuf, String),
+    // The scan could not go on
+    Failed(String),
+    Finished,
+}
+
+#[derive(Debug, Clone)]
+pub enum Rate {
+    // 0 stars takes the rating away
+    SetStars(datastore::model::AudioFileTrackMetadata, u8),
+    ToggleFavourite(datastore::model::TrackId),
+    WriteToFiles(bool),
 }
 
 #[derive(Debug, Clone)]
 pub enum Control {
     Play,
     Pause,
+    // Stops the track and drops the rest of the queue
+    Stop,
     PlayTrack(datastore::model::AudioFileTrackMetadata),
+    // Plays the first track, and the rest after it
+    PlayQueue(Vec<datastore::model::AudioFileTrackMetadata>),
 }
@@ -1,6 +1,7 @@
 pub mod state;
 
 mod message;
+mod scan;
 mod subscription;
 mod update;
 mod view;
@@ -0,0 +1,66 @@
+use std::cell;
+use std::sync::mpsc;
+use std::thread;
+use std::time;
+
+use crate::datastore;
+
+use super::message;
+
+// Hand tracks over in batches, so the view is not redrawn for every single track
+const BATCH_INTERVAL: time::Duration = time::Duration::from_millis(250);
+
+pub fn start_library_scan(library_directory: &str) -> mpsc::Receiver<message::Scan> {
+    let (scan_sender, scan_recv) = mpsc::channel();
+    let library_directory = library_directory.to_string();
+
+    thread::spawn(move || {
+        let scan_options = datastore::model::ScanOptions::default();
+        // Progress comes for every file, so it goes along with the batches instead
+        let progress = cell::Cell::new((0, 0));
+        let mut events = |event: datastore::model::ScanEvent| match event {
+            datastore::model::ScanEvent::Progress(done, found) => progress.set((done, found)),
+            datastore::model::ScanEvent::Skipped(path, reason) => {
+                let _ = scan_sender.send(message::Scan::Skipped(path, reason));
+            }
+            datastore::model::ScanEvent::Failed(path, e) => {
+                let _ = scan_sender.send(message::Scan::FileFailed(path, format!("{:?}", e)));
+            }
+            _ => (),
+        };
+        let mut batch = Vec::new();
+        let mut last_sent = time::Instant::now();
+
+        for track in datastore::model::TrackStream::from_library_directory(
+            &library_directory,
+            &scan_options,
+            &mut events,
+        ) {
+            match track {
+                Ok(track) => batch.push(track),
+                Err(e) => {
+                    let _ = scan_sender.send(message::Scan::Failed(format!("{:?}", e)));
+                    break;
+                }
+            }
+            if last_sent.elapsed() >= BATCH_INTERVAL {
+                if scan_sender
+                    .send(message::Scan::Tracks(std::mem::take(&mut batch)))
+                    .is_err()
+                {
+                    return;
+                }
+                let (done, found) = progress.get();
+                let _ = scan_sender.send(message::Scan::Progress(done, found));
+                last_sent = time::Instant::now();
+            }
+        }
+
+        // Dropping the sender afterwards tells the subscription the scan is over
+        let _ = scan_sender.send(message::Scan::Tracks(batch));
+        let (done, found) = progress.get();
+        let _ = scan_sender.send(message::Scan::Progress(done, found));
+    });
+
+    scan_recv
+}
@@ -1,12 +1,23 @@
 use std::cell;
+use std::collections::VecDeque;
+use std::path;
 use std::sync::mpsc;
+use std::time;
 
 use crate::datastore;
 use crate::shared;
 
+use super::message;
+
 pub struct State {
     pub page: Page,
-    pub datastore: datastore::model::Library,
+    pub datastore: datastore::model::LibraryBuilder,
+    pub scan: Scan,
+    pub ratings: datastore::model::RatingsStore,
+    pub history: datastore::model::ListeningHistory,
+    pub playlist_rules: datastore::model::PlaylistRules,
+    // Generated again whenever a play is recorded or a scan finishes
+    pub playlists: Vec<datastore::model::ContextPlaylist>,
     pub playback: PlaybackInfo,
     pub sink: Sink,
 }
@@ -16,6 +27,10 @@ pub enum Page {
     ArtistList,
     ArtistAlbumList(String),
     AlbumTrackList(String, String),
+    GenreList,
+    GenreAlbumList(String),
+    PlaylistList,
+    Playlist(String),
 }
 
 pub struct Sink {
@@ -23,6 +38,20 @@ pub struct Sink {
     pub sink_callback_recv: cell::RefCell<Option<mpsc::Receiver<shared::SinkCallbackMessage>>>,
 }
 
+pub struct Scan {
+    pub scanning: bool,
+    pub scan_recv: cell::RefCell<Option<mpsc::Receiver<message::Scan>>>,
+    // Files processed so far, out of how many were found
+    pub progress: (usize, usize),
+    pub skipped: Vec<(path::PathBuf, datastore::model::SkipReason)>,
+    pub failed: Vec<(path::PathBuf, String)>,
+}
+
 pub struct PlaybackInfo {
     pub currently_playing: Option<(datastore::model::AudioFileTrackMetadata, bool)>,
+    pub position: time::Duration,
+    // Turns what the sink reports into plays for the history
+    pub plays: datastore::model::PlayTracker,
+    // Played one after the other once the current track ends
+    pub queue: VecDeque<datastore::model::AudioFileTrackMetadata>,
 }
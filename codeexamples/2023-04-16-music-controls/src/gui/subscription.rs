@@ -11,3 +11,32 @@ pub fn sink_callback(app: &state::State) -> iced::Subscription<message::Message>
         },
     )
 }
+
+pub fn close_requested() -> iced::Subscription<message::Message> {
+    iced::subscription::events_with(|event, _status| match event {
+        iced::Event::Window(iced::window::Event::CloseRequested) => {
+            Some(message::Message::CloseRequested)
+        }
+        _ => None,
+    })
+}
+
+pub fn library_scan(app: &state::State) -> iced::Subscription<message::Message> {
+    if !app.scan.scanning {
+        return iced::Subscription::none();
+    }
+    iced::subscription::unfold(
+        "library scan",
+        app.scan.scan_recv.take(),
+        move |scan_recv| async move {
+            match scan_recv.as_ref().and_then(|recv| recv.recv().ok()) {
+                Some(msg) => (Some(message::Message::Scan(msg)), scan_recv),
+                // The scan thread is done; this subscription goes away once Finished is handled
+                None if scan_recv.is_some() => {
+                    (Some(message::Message::Scan(message::Scan::Finished)), None)
+                }
+                None => iced::futures::future::pending().await,
+            }
+        },
+    )
+}
@@ -1,5 +1,8 @@
+use std::collections::VecDeque;
 use std::sync::mpsc;
+use std::time;
 
+use crate::datastore;
 use crate::shared;
 
 use super::{message, state};
@@ -8,7 +11,14 @@ pub fn handle_message(
     state: &mut state::State,
     message: message::Message,
 ) -> iced::Command<message::Message> {
-    println!("handling _a_ message...");
+    // Progress comes in every 250 ms while playing, far too often to print
+    let progress = matches!(
+        message,
+        message::Message::SinkCallback(shared::SinkCallbackMessage::Progress(_))
+    );
+    if !progress {
+        println!("handling _a_ message...");
+    }
     match message {
         message::Message::Nav(nav_message) => {
             println!("handling nav message");
@@ -19,9 +29,15 @@ pub fn handle_message(
             println!("handling control message");
             handle_control(state, control_message)
         }
-        message::Message::SinkCallback(callb) => {
-            println!("handling callback message");
-            handle_sink_callback(state, callb);
+        message::Message::SinkCallback(callb) => handle_sink_callback(state, callb),
+        message::Message::Scan(scan_message) => {
+            println!("handling scan message");
+            handle_scan(state, scan_message);
+            iced::Command::none()
+        }
+        message::Message::Rate(rate_message) => {
+            println!("handling rate message");
+            handle_rate(state, rate_message);
             iced::Command::none()
         }
         message::Message::ErrorResponse(error_message) => {
@@ -29,6 +45,12 @@ pub fn handle_message(
             handle_error(state, error_message);
             iced::Command::none()
         }
+        message::Message::CloseRequested => {
+            println!("handling close request");
+            let play = state.playback.plays.stopped();
+            record_play(state, play);
+            iced::window::close()
+        }
     }
 }
 
@@ -42,10 +64,21 @@ fn handle_nav(state: &mut state::State, nav_message: message::Navigate) {
         message::Navigate::AlbumTrackList(artist_name, album_name) => {
             state.page = state::Page::AlbumTrackList(artist_name, album_name)
         }
+        message::Navigate::GenreList => state.page = state::Page::GenreList,
+        message::Navigate::GenreAlbumList(genre_key) => {
+            state.page = state::Page::GenreAlbumList(genre_key)
+        }
+        message::Navigate::PlaylistList => state.page = state::Page::PlaylistList,
+        message::Navigate::Playlist(playlist_name) => {
+            state.page = state::Page::Playlist(playlist_name)
+        }
     }
 }
 
-fn handle_sink_callback(state: &mut state::State, callback_message: shared::SinkCallbackMessage) {
+fn handle_sink_callback(
+    state: &mut state::State,
+    callback_message: shared::SinkCallbackMessage,
+) -> iced::Command<message::Message> {
     match callback_message {
         shared::SinkCallbackMessage::Playing => {
             println!("we're now officially playing");
@@ -63,11 +96,94 @@ fn handle_sink_callback(state: &mut state::State, callback_message: shared::Sink
         }
         shared::SinkCallbackMessage::SongEnded => {
             println!("the song has officially ended");
-            state.playback.currently_playing = None
+            let play = state.playback.plays.ended();
+            record_play(state, play);
+            state.playback.currently_playing = None;
+            state.playback.position = time::Duration::ZERO;
+            match state.playback.queue.pop_front() {
+                Some(next_track) => return play_track(state, next_track),
+                None => (),
+            }
+        }
+        shared::SinkCallbackMessage::Progress(position) => {
+            state.playback.position = position;
+            state.playback.plays.progress(position);
+        }
+    }
+    iced::Command::none()
+}
+
+fn record_play(state: &mut state::State, play: Option<datastore::model::Play>) {
+    match play {
+        Some(play) => match state.history.record(play) {
+            Ok(()) => (),
+            Err(e) => println!("could not record the play: {:?}", e),
+        },
+        None => return,
+    }
+    regenerate_playlists(state);
+}
+
+fn regenerate_playlists(state: &mut state::State) {
+    state.playlists = state
+        .playlist_rules
+        .generate(&state.history, state.datastore.library());
+}
+
+fn handle_scan(state: &mut state::State, scan_message: message::Scan) {
+    match scan_message {
+        message::Scan::Tracks(tracks) => {
+            for track in tracks.into_iter() {
+                match state.datastore.add_track(track) {
+                    Ok(()) => (),
+                    Err(e) => println!("leaving out a track: {:?}", e),
+                }
+            }
+        }
+        message::Scan::Progress(done, found) => state.scan.progress = (done, found),
+        message::Scan::Skipped(path, reason) => state.scan.skipped.push((path, reason)),
+        message::Scan::FileFailed(path, err_string) => {
+            println!("leaving out {:?}: {}", path, err_string);
+            state.scan.failed.push((path, err_string));
+        }
+        message::Scan::Failed(err_string) => println!("the library scan failed: {}", err_string),
+        message::Scan::Finished => {
+            println!("the library scan is done");
+            state.scan.scanning = false;
+            // Artists and albums show up as tracks come in, genres and ids once it is all in
+            state.datastore.index();
+            regenerate_playlists(state);
         }
     }
 }
 
+fn handle_rate(state: &mut state::State, rate_message: message::Rate) {
+    match rate_message {
+        message::Rate::SetStars(track, stars) => {
+            let rating = match stars {
+                0 => None,
+                stars => Some(stars as f64 / 5.0),
+            };
+            match state.ratings.set_rating(&track, rating) {
+                Ok(()) => (),
+                Err(e) => println!("could not rate the track: {:?}", e),
+            }
+        }
+        message::Rate::ToggleFavourite(track_id) => {
+            let favourite = !state.ratings.is_favourite(&track_id);
+            state.ratings.set_favourite(&track_id, favourite);
+        }
+        message::Rate::WriteToFiles(write_to_files) => {
+            state.ratings.write_to_files = write_to_files;
+            return;
+        }
+    }
+    match state.ratings.save() {
+        Ok(()) => (),
+        Err(e) => println!("could not save the ratings: {:?}", e),
+    }
+}
+
 fn handle_error(_state: &mut state::State, error_message: Result<(), String>) {
     match error_message {
         Ok(()) => println!("no error was seen"),
@@ -88,16 +204,58 @@ fn handle_control(
             state.sink.sink_message_sender.clone(),
             shared::SinkMessage::PauseButton,
         ),
-        message::Control::PlayTrack(track) => {
-            state.playback.currently_playing = Some((track.clone(), true));
+        message::Control::Stop => {
+            let play = state.playback.plays.stopped();
+            record_play(state, play);
+            state.playback.queue.clear();
+            state.playback.currently_playing = None;
+            state.playback.position = time::Duration::ZERO;
             sink_message(
                 state.sink.sink_message_sender.clone(),
-                shared::SinkMessage::LoadSong(track.full_path.to_string_lossy().to_string()),
+                shared::SinkMessage::StopButton,
             )
         }
+        message::Control::PlayTrack(track) => {
+            state.playback.queue.clear();
+            play_track(state, track)
+        }
+        message::Control::PlayQueue(tracks) => {
+            let mut queue = VecDeque::from(tracks);
+            match queue.pop_front() {
+                Some(first_track) => {
+                    state.playback.queue = queue;
+                    play_track(state, first_track)
+                }
+                None => iced::Command::none(),
+            }
+        }
     }
 }
 
+fn play_track(
+    state: &mut state::State,
+    track: datastore::model::AudioFileTrackMetadata,
+) -> iced::Command<message::Message> {
+    // Whatever was playing is cut short
+    let play = state
+        .playback
+        .plays
+        .started(&track, time::SystemTime::now());
+    record_play(state, play);
+    state.playback.currently_playing = Some((track.clone(), true));
+    state.playback.position = time::Duration::ZERO;
+    sink_message(
+        state.sink.sink_message_sender.clone(),
+        shared::SinkMessage::LoadSong(
+            track.full_path.clone(),
+            track.range.map(|range| shared::SongRange {
+                start: range.start,
+                end: range.end,
+            }),
+        ),
+    )
+}
+
 fn sink_message(
     tx: mpsc::Sender<shared::SinkMessage>,
     message: shared::SinkMessage,
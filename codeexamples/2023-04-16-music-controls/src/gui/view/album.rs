@@ -3,12 +3,15 @@ use iced::widget::{button, text, Column, Row, Scrollable, Space};
 
 use crate::datastore;
 
-use super::message;
+use super::{lyrics, message, state};
 
 pub fn view_album_track_list<'a>(
     artist_name: String,
     album_name: String,
     datastore: &'a datastore::model::Library,
+    ratings: &'a datastore::model::RatingsStore,
+    history: &'a datastore::model::ListeningHistory,
+    playback: &'a state::PlaybackInfo,
 ) -> (
     iced::Element<'a, message::Message>,
     Vec<iced::widget::Button<'a, message::Message>>,
@@ -23,27 +26,31 @@ pub fn view_album_track_list<'a>(
         )),
     ];
 
-    let mut discs_column = Column::new().padding(10);
-    for disc in datastore
+    let album = datastore
         .artists
         .get(&artist_name)
         .unwrap()
         .albums
         .get(&album_name)
-        .unwrap()
-        .discs
-        .values()
-    {
+        .unwrap();
+
+    let mut discs_column = Column::new().padding(10);
+    for disc in album.discs.values() {
         let mut tracks_column = Column::new().padding(10);
         for track in disc.tracks.values() {
-            let track_row =
+            let mut track_row =
                 Row::new()
                     .spacing(10)
                     .push(button(">").on_press(message::Message::Control(
                         message::Control::PlayTrack(track.clone()),
                     )))
                     .push(text(format!("{:>3}", track.track.unwrap_or(1))).size(26))
-                    .push(text(track.track_title.clone()).size(26));
+                    .push(text(track.track_title.clone()).size(26))
+                    .push(view_rating(track, ratings));
+            let play_count = history.stats(&track.id).play_count;
+            if play_count > 0 {
+                track_row = track_row.push(text(format!("{} plays", play_count)));
+            }
             tracks_column = tracks_column.push(track_row);
         }
         discs_column = discs_column
@@ -51,6 +58,40 @@ pub fn view_album_track_list<'a>(
             .push(tracks_column);
     }
 
+    // Show the lyrics of the playing track next to the track list
+    // when it is one of this album's tracks
+    let mut tracks_and_lyrics = Row::new().push(Scrollable::new(discs_column));
+    match playback.currently_playing {
+        Some((ref playing_track, _)) => {
+            let on_this_album = album
+                .discs
+                .values()
+                .flat_map(|disc| disc.tracks.values())
+                .any(|track| track.id == playing_track.id);
+            match playing_track.lyrics {
+                Some(ref track_lyrics) if on_this_album => {
+                    tracks_and_lyrics = tracks_and_lyrics
+                        .push(lyrics::view_lyrics_pane(track_lyrics, playback.position));
+                }
+                _ => (),
+            }
+        }
+        None => (),
+    }
+
+    let mut details = vec![format!(
+        "{} tracks, {}:{:02}",
+        album.track_count,
+        album.duration.as_secs() / 60,
+        album.duration.as_secs() % 60
+    )];
+    if let Some(ref date) = album.date {
+        details.insert(0, date.clone());
+    }
+    if !album.genres.is_empty() {
+        details.push(album.genres.join(", "));
+    }
+
     (
         Column::new()
             .padding(10)
@@ -65,9 +106,39 @@ pub fn view_album_track_list<'a>(
                     .push(text(artist_name).size(36))
                     .push(text("(Artist)").size(26)),
             )
+            .push(
+                Row::new()
+                    .push(Space::with_width(iced::Length::Units(50)))
+                    .push(text(details.join(" · ")).size(20)),
+            )
             .push(text("Tracks:").size(36))
-            .push(Scrollable::new(discs_column))
+            .push(tracks_and_lyrics)
             .into(),
         breadcrumbs,
     )
 }
+
+fn view_rating<'a>(
+    track: &datastore::model::AudioFileTrackMetadata,
+    ratings: &datastore::model::RatingsStore,
+) -> Row<'a, message::Message> {
+    let stars = ratings
+        .rating(track)
+        .map(|rating| (rating * 5.0).round() as u8)
+        .unwrap_or(0);
+
+    let mut row = Row::new().spacing(2);
+    for star in 1..=5 {
+        // Pressing the last lit star again takes the rating away
+        let new_stars = if star == stars { 0 } else { star };
+        row = row.push(button(if star <= stars { "*" } else { "." }).on_press(
+            message::Message::Rate(message::Rate::SetStars(track.clone(), new_stars)),
+        ));
+    }
+    let favourite = ratings.is_favourite(&track.id);
+    row.push(
+        button(if favourite { "<3" } else { "-" }).on_press(message::Message::Rate(
+            message::Rate::ToggleFavourite(track.id.clone()),
+        )),
+    )
+}
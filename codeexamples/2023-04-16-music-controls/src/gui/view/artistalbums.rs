@@ -1,5 +1,5 @@
 use iced;
-use iced::widget::{button, text, Column, Row, Scrollable};
+use iced::widget::{button, text, Column, Row, Scrollable, Space};
 
 use crate::datastore;
 
@@ -19,8 +19,10 @@ pub fn view_artist_album_list<'a>(
         )),
     ];
 
+    let artist = datastore.artists.get(&artist_name).unwrap();
+
     let mut albums_column = Column::new().padding(10);
-    for album_name in datastore.artists.get(&artist_name).unwrap().albums.keys() {
+    for album_name in artist.albums.keys() {
         albums_column = albums_column.push(button(text(album_name.clone()).size(26)).on_press(
             message::Message::Nav(message::Navigate::AlbumTrackList(
                 artist_name.clone(),
@@ -29,6 +31,58 @@ pub fn view_artist_album_list<'a>(
         ));
     }
 
+    // Other artists' albums this artist is credited on, with just the credited tracks
+    let mut appears_on_column = Column::new().padding(10);
+    for appears_on in artist.appears_on.values() {
+        appears_on_column = appears_on_column.push(
+            Row::new()
+                .spacing(10)
+                .push(
+                    button(text(appears_on.album_name.clone()).size(26)).on_press(
+                        message::Message::Nav(message::Navigate::AlbumTrackList(
+                            appears_on.artist_name.clone(),
+                            appears_on.album_name.clone(),
+                        )),
+                    ),
+                )
+                .push(button(text(appears_on.artist_name.clone())).on_press(
+                    message::Message::Nav(message::Navigate::ArtistAlbumList(
+                        appears_on.artist_name.clone(),
+                    )),
+                )),
+        );
+
+        let album = datastore
+            .artists
+            .get(&appears_on.artist_name)
+            .and_then(|album_artist| album_artist.albums.get(&appears_on.album_name));
+        for (disc_number, track_number) in appears_on.tracks.iter() {
+            let track = album
+                .and_then(|album| album.discs.get(disc_number))
+                .and_then(|disc| disc.tracks.get(track_number));
+            if let Some(track) = track {
+                appears_on_column = appears_on_column.push(
+                    Row::new()
+                        .spacing(10)
+                        .push(Space::with_width(iced::Length::Units(50)))
+                        .push(button(">").on_press(message::Message::Control(
+                            message::Control::PlayTrack(track.clone()),
+                        )))
+                        .push(text(track.track_title.clone())),
+                );
+            }
+        }
+    }
+
+    let mut albums_and_appearances = Column::new()
+        .push(text("Albums:").size(36))
+        .push(albums_column);
+    if !artist.appears_on.is_empty() {
+        albums_and_appearances = albums_and_appearances
+            .push(text("Appears on:").size(36))
+            .push(appears_on_column);
+    }
+
     (
         Column::new()
             .padding(10)
@@ -37,8 +91,7 @@ pub fn view_artist_album_list<'a>(
                     .push(text(artist_name).size(46))
                     .push(text("(Artist)").size(26)),
             )
-            .push(text("Albums:").size(36))
-            .push(Scrollable::new(albums_column))
+            .push(Scrollable::new(albums_and_appearances))
             .into(),
         breadcrumbs,
     )
@@ -0,0 +1,71 @@
+use iced;
+use iced::widget::{button, text, Column, Row, Scrollable};
+
+use crate::datastore;
+
+use super::super::message;
+
+pub fn view_genre_album_list<'a>(
+    genre_key: String,
+    datastore: &'a datastore::model::Library,
+) -> (
+    iced::Element<'a, message::Message>,
+    Vec<iced::widget::Button<'a, message::Message>>,
+) {
+    let genre = datastore.genres.get(&genre_key).unwrap();
+
+    let breadcrumbs = vec![
+        button("Genres").on_press(message::Message::Nav(message::Navigate::GenreList)),
+        button(text(genre.name.clone())).on_press(message::Message::Nav(
+            message::Navigate::GenreAlbumList(genre_key.clone()),
+        )),
+    ];
+
+    // Albums where this genre is the main one come first,
+    // then albums where only some of the tracks are tagged with it
+    let mut dominant_albums_column = Column::new().padding(10);
+    let mut other_albums_column = Column::new().padding(10);
+    for genre_album in genre.albums.values() {
+        let album_row = Row::new()
+            .spacing(10)
+            .push(
+                button(text(genre_album.album_name.clone()).size(26)).on_press(
+                    message::Message::Nav(message::Navigate::AlbumTrackList(
+                        genre_album.artist_name.clone(),
+                        genre_album.album_name.clone(),
+                    )),
+                ),
+            )
+            .push(
+                button(text(genre_album.artist_name.clone())).on_press(message::Message::Nav(
+                    message::Navigate::ArtistAlbumList(genre_album.artist_name.clone()),
+                )),
+            )
+            .push(text(format!("({} tracks)", genre_album.tracks.len())));
+
+        if genre_album.dominant {
+            dominant_albums_column = dominant_albums_column.push(album_row);
+        } else {
+            other_albums_column = other_albums_column.push(album_row);
+        }
+    }
+
+    (
+        Column::new()
+            .padding(10)
+            .push(
+                Row::new()
+                    .push(text(genre.name.clone()).size(46))
+                    .push(text("(Genre)").size(26)),
+            )
+            .push(Scrollable::new(
+                Column::new()
+                    .push(text("Albums:").size(36))
+                    .push(dominant_albums_column)
+                    .push(text("Also on:").size(36))
+                    .push(other_albums_column),
+            ))
+            .into(),
+        breadcrumbs,
+    )
+}
@@ -0,0 +1,34 @@
+use iced;
+use iced::widget::{button, text, Column, Scrollable};
+
+use crate::datastore;
+
+use super::super::message;
+
+pub fn view_genre_list<'a>(
+    datastore: &'a datastore::model::Library,
+) -> (
+    iced::Element<'a, message::Message>,
+    Vec<iced::widget::Button<'a, message::Message>>,
+) {
+    let breadcrumbs =
+        vec![button("Genres").on_press(message::Message::Nav(message::Navigate::GenreList))];
+
+    let mut genre_list_column = Column::new();
+    for (genre_key, genre) in datastore.genres.iter() {
+        genre_list_column = genre_list_column.push(
+            button(text(format!("{} ({})", genre.name, genre.albums.len()))).on_press(
+                message::Message::Nav(message::Navigate::GenreAlbumList(genre_key.clone())),
+            ),
+        )
+    }
+
+    (
+        Column::new()
+            .padding(10)
+            .push(text("Genres:").size(46))
+            .push(Scrollable::new(genre_list_column))
+            .into(),
+        breadcrumbs,
+    )
+}
@@ -1,17 +1,56 @@
 use iced;
-use iced::widget::{button, text, Column};
+use iced::widget::{button, checkbox, text, Column, Scrollable};
 
-use super::super::message;
+use crate::datastore;
 
-pub fn view_home<'a>() -> (
+use super::super::{message, state};
+
+pub fn view_home<'a>(
+    ratings: &'a datastore::model::RatingsStore,
+    scan: &'a state::Scan,
+) -> (
     iced::Element<'a, message::Message>,
     Vec<iced::widget::Button<'a, message::Message>>,
 ) {
+    let mut left_out = Column::new();
+    for (path, err_string) in scan.failed.iter() {
+        left_out = left_out.push(text(format!(
+            "{} could not be read: {}",
+            path.display(),
+            err_string
+        )));
+    }
+    for (path, reason) in scan.skipped.iter() {
+        left_out = left_out.push(text(format!(
+            "{} was skipped: {:?}",
+            path.display(),
+            reason
+        )));
+    }
+
     (
         Column::new()
             .padding(10)
             .push(text("Welcome").size(46))
             .push(button("Artists").on_press(message::Message::Nav(message::Navigate::ArtistList)))
+            .push(button("Genres").on_press(message::Message::Nav(message::Navigate::GenreList)))
+            .push(
+                button("Playlists")
+                    .on_press(message::Message::Nav(message::Navigate::PlaylistList)),
+            )
+            .push(checkbox(
+                "Also write ratings into the music files",
+                ratings.write_to_files,
+                |write_to_files| {
+                    message::Message::Rate(message::Rate::WriteToFiles(write_to_files))
+                },
+            ))
+            .push(text(format!(
+                "Left out of the library: {} unreadable, {} skipped",
+                scan.failed.len(),
+                scan.skipped.len()
+            )))
+            .push(Scrollable::new(left_out).height(iced::Length::Units(200)))
             .into(),
         Vec::new(),
     )
@@ -0,0 +1,65 @@
+use std::time;
+
+use iced;
+use iced::widget::{text, Column, Scrollable};
+
+use crate::datastore;
+
+use super::super::message;
+
+const CURRENT_LINE_COLOR: iced::Color = iced::Color {
+    r: 0.2,
+    g: 0.4,
+    b: 0.9,
+    a: 1.0,
+};
+
+pub fn view_lyrics_pane<'a>(
+    lyrics: &'a datastore::model::Lyrics,
+    position: time::Duration,
+) -> iced::Element<'a, message::Message> {
+    let mut lines_column = Column::new().padding(10).spacing(5);
+
+    match lyrics {
+        datastore::model::Lyrics::Unsynced(ref body) => {
+            for line in body.lines() {
+                lines_column = lines_column.push(text(line.to_string()).size(20));
+            }
+        }
+        datastore::model::Lyrics::Synced(ref lines) => {
+            let current_line_index = lyrics.current_line_index(position);
+            for (index, line) in lines.iter().enumerate() {
+                let line_text = if Some(index) == current_line_index {
+                    text(line.text.clone())
+                        .size(26)
+                        .style(iced::theme::Text::Color(CURRENT_LINE_COLOR))
+                } else {
+                    text(line.text.clone()).size(20)
+                };
+                lines_column = lines_column.push(line_text);
+            }
+        }
+    }
+
+    Column::new()
+        .padding(10)
+        .push(text("Lyrics:").size(36))
+        .push(Scrollable::new(lines_column))
+        .into()
+}
+
+pub fn view_current_line<'a>(
+    lyrics: &'a datastore::model::Lyrics,
+    position: time::Duration,
+) -> Option<iced::Element<'a, message::Message>> {
+    match lyrics {
+        datastore::model::Lyrics::Unsynced(_) => None,
+        datastore::model::Lyrics::Synced(ref lines) => {
+            lyrics.current_line_index(position).map(|index| {
+                text(lines[index].text.clone())
+                    .style(iced::theme::Text::Color(CURRENT_LINE_COLOR))
+                    .into()
+            })
+        }
+    }
+}
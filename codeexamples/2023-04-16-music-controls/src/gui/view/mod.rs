@@ -1,5 +1,5 @@
 use iced;
-use iced::widget::{button, text, Column, Row, Scrollable};
+use iced::widget::{button, progress_bar, text, Column, Row, Scrollable};
 
 use crate::datastore;
 
@@ -8,10 +8,23 @@ use super::{message, state};
 mod album;
 mod artistalbums;
 mod artists;
+mod genrealbums;
+mod genres;
 mod home;
+mod lyrics;
+mod playlist;
+mod playlists;
 
 pub fn view_state<'a>(state: &'a state::State) -> iced::Element<'a, message::Message> {
-    let (body, breadcrumbs) = view_page(&state.page, &state.datastore);
+    let (body, breadcrumbs) = view_page(
+        &state.page,
+        state.datastore.library(),
+        &state.ratings,
+        &state.history,
+        &state.scan,
+        &state.playlists,
+        &state.playback,
+    );
 
     let mut crumb_button_row = Row::new()
         .spacing(10)
@@ -20,6 +33,20 @@ pub fn view_state<'a>(state: &'a state::State) -> iced::Element<'a, message::Mes
     for crumb_button in breadcrumbs.into_iter() {
         crumb_button_row = crumb_button_row.push(Scrollable::new(crumb_button));
     }
+    if state.scan.scanning {
+        let (done, found) = state.scan.progress;
+        crumb_button_row = crumb_button_row
+            .push(text(format!(
+                "Scanning... {} of {} files, {} artists so far",
+                done,
+                found,
+                state.datastore.library().artists.len()
+            )))
+            .push(
+                progress_bar(0.0..=found.max(1) as f32, done as f32)
+                    .width(iced::Length::Units(200)),
+            );
+    }
 
     let playback_info = view_playback_info(&state.playback);
 
@@ -37,18 +64,38 @@ pub fn view_state<'a>(state: &'a state::State) -> iced::Element<'a, message::Mes
 fn view_page<'a>(
     page: &'a state::Page,
     datastore: &'a datastore::model::Library,
+    ratings: &'a datastore::model::RatingsStore,
+    history: &'a datastore::model::ListeningHistory,
+    scan: &'a state::Scan,
+    playlists: &'a [datastore::model::ContextPlaylist],
+    playback: &'a state::PlaybackInfo,
 ) -> (
     iced::Element<'a, message::Message>,
     Vec<iced::widget::Button<'a, message::Message>>,
 ) {
     match page {
-        state::Page::Home => home::view_home(),
+        state::Page::Home => home::view_home(ratings, scan),
         state::Page::ArtistList => artists::view_artist_list(&datastore),
         state::Page::ArtistAlbumList(ref artist_name) => {
             artistalbums::view_artist_album_list(artist_name.clone(), &datastore)
         }
         state::Page::AlbumTrackList(ref artist_name, ref album_name) => {
-            album::view_album_track_list(artist_name.clone(), album_name.clone(), &datastore)
+            album::view_album_track_list(
+                artist_name.clone(),
+                album_name.clone(),
+                &datastore,
+                ratings,
+                history,
+                &playback,
+            )
+        }
+        state::Page::GenreList => genres::view_genre_list(&datastore),
+        state::Page::GenreAlbumList(ref genre_key) => {
+            genrealbums::view_genre_album_list(genre_key.clone(), &datastore)
+        }
+        state::Page::PlaylistList => playlists::view_playlist_list(playlists),
+        state::Page::Playlist(ref playlist_name) => {
+            playlist::view_playlist(playlist_name.clone(), playlists, &datastore)
         }
     }
 }
@@ -67,7 +114,18 @@ fn view_playback_info<'a>(
                 row = row
                     .push(button(" >").on_press(message::Message::Control(message::Control::Play)));
             }
+            row =
+                row.push(button("[]").on_press(message::Message::Control(message::Control::Stop)));
             row = row.push(text(track.track_title.clone()));
+            match track.lyrics {
+                Some(ref track_lyrics) => {
+                    match lyrics::view_current_line(track_lyrics, playback.position) {
+                        Some(current_line) => row = row.push(current_line),
+                        None => (),
+                    }
+                }
+                None => (),
+            }
             Some(row.into())
         }
         None => None,
@@ -0,0 +1,71 @@
+use iced;
+use iced::widget::{button, text, Column, Row, Scrollable};
+
+use crate::datastore;
+
+use super::super::message;
+
+pub fn view_playlist<'a>(
+    playlist_name: String,
+    playlists: &'a [datastore::model::ContextPlaylist],
+    datastore: &'a datastore::model::Library,
+) -> (
+    iced::Element<'a, message::Message>,
+    Vec<iced::widget::Button<'a, message::Message>>,
+) {
+    let breadcrumbs = vec![
+        button("Playlists").on_press(message::Message::Nav(message::Navigate::PlaylistList)),
+        button(text(playlist_name.clone())).on_press(message::Message::Nav(
+            message::Navigate::Playlist(playlist_name.clone()),
+        )),
+    ];
+
+    // The playlists are generated again as plays come in, so this one may be gone
+    let tracks: Vec<(&datastore::model::AudioFileTrackMetadata, usize)> = playlists
+        .iter()
+        .find(|playlist| playlist.name == playlist_name)
+        .map(|playlist| {
+            playlist
+                .tracks
+                .iter()
+                .filter_map(|(id, play_count)| Some((datastore.get_track(id)?, *play_count)))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut tracks_column = Column::new().padding(10);
+    for (track, play_count) in tracks.iter() {
+        tracks_column =
+            tracks_column.push(
+                Row::new()
+                    .spacing(10)
+                    .push(button(">").on_press(message::Message::Control(
+                        message::Control::PlayTrack((*track).clone()),
+                    )))
+                    .push(text(track.track_title.clone()).size(26))
+                    .push(text(track.artist.clone()))
+                    .push(text(format!("({} plays)", play_count))),
+            );
+    }
+
+    let mut play_all = button("Play all");
+    if !tracks.is_empty() {
+        play_all = play_all.on_press(message::Message::Control(message::Control::PlayQueue(
+            tracks.iter().map(|(track, _)| (*track).clone()).collect(),
+        )));
+    }
+
+    (
+        Column::new()
+            .padding(10)
+            .push(
+                Row::new()
+                    .push(text(playlist_name).size(46))
+                    .push(text("(Playlist)").size(26)),
+            )
+            .push(play_all)
+            .push(Scrollable::new(tracks_column))
+            .into(),
+        breadcrumbs,
+    )
+}
@@ -0,0 +1,40 @@
+use iced;
+use iced::widget::{button, text, Column, Scrollable};
+
+use crate::datastore;
+
+use super::super::message;
+
+pub fn view_playlist_list<'a>(
+    playlists: &'a [datastore::model::ContextPlaylist],
+) -> (
+    iced::Element<'a, message::Message>,
+    Vec<iced::widget::Button<'a, message::Message>>,
+) {
+    let breadcrumbs =
+        vec![button("Playlists").on_press(message::Message::Nav(message::Navigate::PlaylistList))];
+
+    let mut playlist_list_column = Column::new();
+    for playlist in playlists.iter() {
+        playlist_list_column = playlist_list_column.push(
+            button(text(format!(
+                "{} ({})",
+                playlist.name,
+                playlist.tracks.len()
+            )))
+            .on_press(message::Message::Nav(message::Navigate::Playlist(
+                playlist.name.clone(),
+            ))),
+        )
+    }
+
+    (
+        Column::new()
+            .padding(10)
+            .push(text("Playlists:").size(46))
+            .push(text("The tracks played most at these times").size(20))
+            .push(Scrollable::new(playlist_list_column))
+            .into(),
+        breadcrumbs,
+    )
+}
@@ -7,5 +7,10 @@ mod gui;
 use iced::Application;
 
 fn main() {
-    gui::state::State::run(iced::Settings::default()).unwrap();
+    gui::state::State::run(iced::Settings {
+        // The play that is going on is recorded before the window closes
+        exit_on_close_request: false,
+        ..iced::Settings::default()
+    })
+    .unwrap();
 }